@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE pending_parent_links;
+DROP TABLE sync_watermarks;
+DROP TABLE sync_runs;
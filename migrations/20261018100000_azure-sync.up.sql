@@ -0,0 +1,35 @@
+-- Add up migration script here
+CREATE TABLE sync_runs (
+    id SERIAL PRIMARY KEY,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    finished_at TIMESTAMP WITH TIME ZONE,
+    status VARCHAR NOT NULL DEFAULT 'running',
+    full_sync BOOLEAN NOT NULL DEFAULT FALSE,
+    projects INTEGER NOT NULL DEFAULT 0,
+    teams INTEGER NOT NULL DEFAULT 0,
+    users INTEGER NOT NULL DEFAULT 0,
+    work_items INTEGER NOT NULL DEFAULT 0,
+    error VARCHAR
+);
+
+CREATE INDEX idx_sync_runs_started_at ON sync_runs(started_at);
+CREATE INDEX idx_sync_runs_status ON sync_runs(status);
+
+-- Highest System.ChangedDate pulled per project, used for incremental sync
+CREATE TABLE sync_watermarks (
+    project_id UUID PRIMARY KEY,
+    last_changed_date TIMESTAMP NOT NULL,
+    FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE CASCADE
+);
+
+-- Azure DevOps parents that were not synced yet when their child was. The
+-- child is linked once a work item with that azure_id arrives.
+CREATE TABLE pending_parent_links (
+    work_item_id UUID PRIMARY KEY REFERENCES work_items(id) ON DELETE CASCADE,
+    parent_azure_id VARCHAR NOT NULL
+);
+
+CREATE INDEX idx_pending_parent_links_parent_azure_id ON pending_parent_links (parent_azure_id);
+
+COMMENT ON TABLE sync_runs IS 'Log of Azure DevOps synchronization runs';
+COMMENT ON TABLE sync_watermarks IS 'Per-project work item change watermark for incremental Azure DevOps sync';
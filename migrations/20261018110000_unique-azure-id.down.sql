@@ -0,0 +1,10 @@
+-- Add down migration script here
+ALTER TABLE teams DROP CONSTRAINT teams_azure_id_key;
+ALTER TABLE users DROP CONSTRAINT users_azure_id_key;
+ALTER TABLE projects DROP CONSTRAINT projects_azure_id_key;
+ALTER TABLE work_items DROP CONSTRAINT work_items_azure_id_key;
+
+CREATE INDEX idx_teams_azure_id ON teams(azure_id);
+CREATE INDEX idx_users_azure_id ON users(azure_id);
+CREATE INDEX idx_projects_azure_id ON projects(azure_id);
+CREATE INDEX idx_work_items_azure_id ON work_items(azure_id);
@@ -0,0 +1,76 @@
+-- Add up migration script here
+-- Azure ids identify the mirrored entity, so they must be unique per table.
+-- NULLs remain allowed for rows created locally.
+
+-- Rows mirrored twice are merged into one first: every duplicate maps onto
+-- the row that survives, references are repointed to it and the duplicate is
+-- deleted. Work items keep the most recently changed copy, the rest the oldest id.
+CREATE TEMP TABLE merge_work_items ON COMMIT DROP AS
+SELECT id, first_value(id) OVER (
+    PARTITION BY azure_id ORDER BY changed_date DESC NULLS LAST, id
+) AS keep_id
+FROM work_items WHERE azure_id IS NOT NULL;
+DELETE FROM merge_work_items WHERE id = keep_id;
+
+CREATE TEMP TABLE merge_projects ON COMMIT DROP AS
+SELECT id, first_value(id) OVER (PARTITION BY azure_id ORDER BY id) AS keep_id
+FROM projects WHERE azure_id IS NOT NULL;
+DELETE FROM merge_projects WHERE id = keep_id;
+
+CREATE TEMP TABLE merge_users ON COMMIT DROP AS
+SELECT id, first_value(id) OVER (PARTITION BY azure_id ORDER BY id) AS keep_id
+FROM users WHERE azure_id IS NOT NULL;
+DELETE FROM merge_users WHERE id = keep_id;
+
+CREATE TEMP TABLE merge_teams ON COMMIT DROP AS
+SELECT id, first_value(id) OVER (PARTITION BY azure_id ORDER BY id) AS keep_id
+FROM teams WHERE azure_id IS NOT NULL;
+DELETE FROM merge_teams WHERE id = keep_id;
+
+-- work items
+UPDATE work_items w SET parent_id = m.keep_id
+FROM merge_work_items m WHERE w.parent_id = m.id;
+UPDATE work_items SET parent_id = NULL WHERE parent_id = id;
+DELETE FROM work_items WHERE id IN (SELECT id FROM merge_work_items);
+
+-- projects; the survivor's watermark is dropped so it resyncs in full
+UPDATE work_items w SET project = m.keep_id
+FROM merge_projects m WHERE w.project = m.id;
+DELETE FROM sync_watermarks WHERE project_id IN (
+    SELECT keep_id FROM merge_projects
+);
+DELETE FROM projects WHERE id IN (SELECT id FROM merge_projects);
+
+-- users
+UPDATE work_items w SET assigned_to_id = m.keep_id
+FROM merge_users m WHERE w.assigned_to_id = m.id;
+UPDATE work_items w SET created_by_id = m.keep_id
+FROM merge_users m WHERE w.created_by_id = m.id;
+UPDATE notification n SET sender_id = m.keep_id
+FROM merge_users m WHERE n.sender_id = m.id;
+UPDATE notification n SET reciever_id = m.keep_id
+FROM merge_users m WHERE n.reciever_id = m.id;
+INSERT INTO team_users (team_id, user_id)
+SELECT t.team_id, m.keep_id FROM team_users t JOIN merge_users m ON t.user_id = m.id
+ON CONFLICT DO NOTHING;
+DELETE FROM users WHERE id IN (SELECT id FROM merge_users);
+
+-- teams
+UPDATE projects p SET team_id = m.keep_id
+FROM merge_teams m WHERE p.team_id = m.id;
+UPDATE users u SET team_id = m.keep_id
+FROM merge_teams m WHERE u.team_id = m.id;
+INSERT INTO team_users (team_id, user_id)
+SELECT m.keep_id, t.user_id FROM team_users t JOIN merge_teams m ON t.team_id = m.id
+ON CONFLICT DO NOTHING;
+DELETE FROM teams WHERE id IN (SELECT id FROM merge_teams);
+
+DROP INDEX idx_teams_azure_id;
+DROP INDEX idx_users_azure_id;
+DROP INDEX idx_projects_azure_id;
+DROP INDEX idx_work_items_azure_id;
+
+ALTER TABLE teams ADD CONSTRAINT teams_azure_id_key UNIQUE (azure_id);
+ALTER TABLE users ADD CONSTRAINT users_azure_id_key UNIQUE (azure_id);
+ALTER TABLE projects ADD CONSTRAINT projects_azure_id_key UNIQUE (azure_id);
+ALTER TABLE work_items ADD CONSTRAINT work_items_azure_id_key UNIQUE (azure_id);
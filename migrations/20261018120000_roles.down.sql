@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE team_users DROP COLUMN role;
+ALTER TABLE users DROP COLUMN role;
@@ -0,0 +1,11 @@
+-- Add up migration script here
+-- Global role of a user: 'admin' may do anything, 'user' is limited by team roles.
+ALTER TABLE users ADD COLUMN role VARCHAR NOT NULL DEFAULT 'user'
+    CONSTRAINT users_role_check CHECK (role IN ('admin', 'user'));
+
+-- Role of a user within a team: 'admin' manages the team, 'member' works in its projects.
+ALTER TABLE team_users ADD COLUMN role VARCHAR NOT NULL DEFAULT 'member'
+    CONSTRAINT team_users_role_check CHECK (role IN ('admin', 'member'));
+
+COMMENT ON COLUMN users.role IS 'Global role: admin or user';
+COMMENT ON COLUMN team_users.role IS 'Team role: admin or member';
@@ -0,0 +1,10 @@
+-- Add down migration script here
+ALTER TABLE users ADD COLUMN team_id UUID REFERENCES teams(id);
+CREATE INDEX idx_users_team_id ON users(team_id);
+
+-- users.team_id holds a single team, keep the first membership of each user
+UPDATE users SET team_id = first_team.team_id
+FROM (
+    SELECT DISTINCT ON (user_id) user_id, team_id FROM team_users ORDER BY user_id, team_id
+) AS first_team
+WHERE users.id = first_team.user_id;
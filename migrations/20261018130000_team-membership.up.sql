@@ -0,0 +1,9 @@
+-- Add up migration script here
+-- team_users is the single source of truth for team membership.
+-- Carry over memberships only recorded in the legacy users.team_id column.
+INSERT INTO team_users (team_id, user_id)
+SELECT team_id, id FROM users WHERE team_id IS NOT NULL
+ON CONFLICT DO NOTHING;
+
+DROP INDEX idx_users_team_id;
+ALTER TABLE users DROP COLUMN team_id;
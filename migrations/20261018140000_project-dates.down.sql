@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE projects
+DROP COLUMN begin_date,
+DROP COLUMN end_date;
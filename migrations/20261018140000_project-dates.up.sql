@@ -0,0 +1,9 @@
+-- Add up migration script here
+-- Planned start and end of a project, restored after remove-dates.
+ALTER TABLE projects
+ADD COLUMN begin_date TIMESTAMP WITH TIME ZONE,
+ADD COLUMN end_date TIMESTAMP WITH TIME ZONE,
+ADD CONSTRAINT projects_date_range_check CHECK (end_date IS NULL OR begin_date IS NULL OR end_date >= begin_date);
+
+CREATE INDEX idx_projects_begin_date ON projects(begin_date);
+CREATE INDEX idx_projects_end_date ON projects(end_date);
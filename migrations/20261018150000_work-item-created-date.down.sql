@@ -0,0 +1,6 @@
+-- Add down migration script here
+DROP INDEX idx_work_items_priority;
+DROP INDEX idx_work_items_changed_date;
+
+ALTER TABLE work_items
+ALTER COLUMN created_date DROP DEFAULT;
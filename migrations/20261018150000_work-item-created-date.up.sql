@@ -0,0 +1,9 @@
+-- Add up migration script here
+-- Work items created through the API never got a created_date, which breaks date filters and sorting.
+UPDATE work_items SET created_date = COALESCE(changed_date, NOW()) WHERE created_date IS NULL;
+
+ALTER TABLE work_items
+ALTER COLUMN created_date SET DEFAULT NOW();
+
+CREATE INDEX idx_work_items_changed_date ON work_items(changed_date);
+CREATE INDEX idx_work_items_priority ON work_items(priority);
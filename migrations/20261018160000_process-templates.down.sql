@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP TABLE process_transitions;
+DROP TABLE process_states;
+DROP TABLE process_work_item_types;
+DROP TABLE process_templates;
@@ -0,0 +1,179 @@
+-- Add up migration script here
+-- Process templates define the work item types of a project, their states and the
+-- transitions allowed between those states. projects.template names the template.
+CREATE TABLE process_templates (
+    name VARCHAR PRIMARY KEY,
+    description TEXT
+);
+
+CREATE TABLE process_work_item_types (
+    template VARCHAR NOT NULL REFERENCES process_templates(name) ON DELETE CASCADE ON UPDATE CASCADE,
+    name VARCHAR NOT NULL,
+    position INT NOT NULL,
+    PRIMARY KEY (template, name)
+);
+
+-- category follows the Azure DevOps state categories
+CREATE TABLE process_states (
+    template VARCHAR NOT NULL,
+    w_type VARCHAR NOT NULL,
+    name VARCHAR NOT NULL,
+    category VARCHAR NOT NULL
+        CONSTRAINT process_states_category_check
+        CHECK (category IN ('Proposed', 'InProgress', 'Resolved', 'Completed', 'Removed')),
+    position INT NOT NULL,
+    PRIMARY KEY (template, w_type, name),
+    FOREIGN KEY (template, w_type) REFERENCES process_work_item_types(template, name)
+        ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE TABLE process_transitions (
+    template VARCHAR NOT NULL,
+    w_type VARCHAR NOT NULL,
+    from_state VARCHAR NOT NULL,
+    to_state VARCHAR NOT NULL,
+    PRIMARY KEY (template, w_type, from_state, to_state),
+    FOREIGN KEY (template, w_type, from_state) REFERENCES process_states(template, w_type, name)
+        ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY (template, w_type, to_state) REFERENCES process_states(template, w_type, name)
+        ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- The three default Azure DevOps processes
+INSERT INTO process_templates (name, description) VALUES
+    ('Basic', 'Epics, issues and tasks tracked as To Do, Doing and Done'),
+    ('Agile', 'Epics, features and user stories with tasks, bugs and issues'),
+    ('Scrum', 'Epics, features and product backlog items with tasks, bugs and impediments');
+
+CREATE TEMPORARY TABLE process_type_seed (template VARCHAR, w_type VARCHAR, state_set VARCHAR, position INT);
+INSERT INTO process_type_seed VALUES
+    ('Basic', 'Epic', 'default', 1),
+    ('Basic', 'Issue', 'default', 2),
+    ('Basic', 'Task', 'default', 3),
+    ('Agile', 'Epic', 'requirement', 1),
+    ('Agile', 'Feature', 'requirement', 2),
+    ('Agile', 'User Story', 'requirement', 3),
+    ('Agile', 'Task', 'task', 4),
+    ('Agile', 'Bug', 'bug', 5),
+    ('Agile', 'Issue', 'issue', 6),
+    ('Scrum', 'Epic', 'portfolio', 1),
+    ('Scrum', 'Feature', 'portfolio', 2),
+    ('Scrum', 'Product Backlog Item', 'backlog', 3),
+    ('Scrum', 'Bug', 'backlog', 4),
+    ('Scrum', 'Task', 'task', 5),
+    ('Scrum', 'Impediment', 'impediment', 6);
+
+CREATE TEMPORARY TABLE process_state_seed (template VARCHAR, state_set VARCHAR, name VARCHAR, category VARCHAR, position INT);
+INSERT INTO process_state_seed VALUES
+    ('Basic', 'default', 'To Do', 'Proposed', 1),
+    ('Basic', 'default', 'Doing', 'InProgress', 2),
+    ('Basic', 'default', 'Done', 'Completed', 3),
+    ('Agile', 'requirement', 'New', 'Proposed', 1),
+    ('Agile', 'requirement', 'Active', 'InProgress', 2),
+    ('Agile', 'requirement', 'Resolved', 'Resolved', 3),
+    ('Agile', 'requirement', 'Closed', 'Completed', 4),
+    ('Agile', 'requirement', 'Removed', 'Removed', 5),
+    ('Agile', 'task', 'New', 'Proposed', 1),
+    ('Agile', 'task', 'Active', 'InProgress', 2),
+    ('Agile', 'task', 'Closed', 'Completed', 3),
+    ('Agile', 'task', 'Removed', 'Removed', 4),
+    ('Agile', 'bug', 'New', 'Proposed', 1),
+    ('Agile', 'bug', 'Active', 'InProgress', 2),
+    ('Agile', 'bug', 'Resolved', 'Resolved', 3),
+    ('Agile', 'bug', 'Closed', 'Completed', 4),
+    ('Agile', 'issue', 'Active', 'InProgress', 1),
+    ('Agile', 'issue', 'Closed', 'Completed', 2),
+    ('Scrum', 'portfolio', 'New', 'Proposed', 1),
+    ('Scrum', 'portfolio', 'In Progress', 'InProgress', 2),
+    ('Scrum', 'portfolio', 'Done', 'Completed', 3),
+    ('Scrum', 'portfolio', 'Removed', 'Removed', 4),
+    ('Scrum', 'backlog', 'New', 'Proposed', 1),
+    ('Scrum', 'backlog', 'Approved', 'Proposed', 2),
+    ('Scrum', 'backlog', 'Committed', 'InProgress', 3),
+    ('Scrum', 'backlog', 'Done', 'Completed', 4),
+    ('Scrum', 'backlog', 'Removed', 'Removed', 5),
+    ('Scrum', 'task', 'To Do', 'Proposed', 1),
+    ('Scrum', 'task', 'In Progress', 'InProgress', 2),
+    ('Scrum', 'task', 'Done', 'Completed', 3),
+    ('Scrum', 'task', 'Removed', 'Removed', 4),
+    ('Scrum', 'impediment', 'Open', 'InProgress', 1),
+    ('Scrum', 'impediment', 'Closed', 'Completed', 2);
+
+INSERT INTO process_work_item_types (template, name, position)
+SELECT template, w_type, position FROM process_type_seed;
+
+INSERT INTO process_states (template, w_type, name, category, position)
+SELECT t.template, t.w_type, s.name, s.category, s.position
+FROM process_type_seed t
+JOIN process_state_seed s ON s.template = t.template AND s.state_set = t.state_set;
+
+-- The workflows of the Azure DevOps processes: forward one state at a time, back one
+-- state, done items reopened and removed items only restored to the first state
+CREATE TEMPORARY TABLE process_transition_seed (template VARCHAR, state_set VARCHAR, from_state VARCHAR, to_state VARCHAR);
+INSERT INTO process_transition_seed VALUES
+    ('Basic', 'default', 'To Do', 'Doing'),
+    ('Basic', 'default', 'To Do', 'Done'),
+    ('Basic', 'default', 'Doing', 'To Do'),
+    ('Basic', 'default', 'Doing', 'Done'),
+    ('Basic', 'default', 'Done', 'Doing'),
+    ('Agile', 'requirement', 'New', 'Active'),
+    ('Agile', 'requirement', 'New', 'Removed'),
+    ('Agile', 'requirement', 'Active', 'New'),
+    ('Agile', 'requirement', 'Active', 'Resolved'),
+    ('Agile', 'requirement', 'Active', 'Removed'),
+    ('Agile', 'requirement', 'Resolved', 'Active'),
+    ('Agile', 'requirement', 'Resolved', 'Closed'),
+    ('Agile', 'requirement', 'Closed', 'Active'),
+    ('Agile', 'requirement', 'Removed', 'New'),
+    ('Agile', 'task', 'New', 'Active'),
+    ('Agile', 'task', 'New', 'Closed'),
+    ('Agile', 'task', 'New', 'Removed'),
+    ('Agile', 'task', 'Active', 'New'),
+    ('Agile', 'task', 'Active', 'Closed'),
+    ('Agile', 'task', 'Active', 'Removed'),
+    ('Agile', 'task', 'Closed', 'Active'),
+    ('Agile', 'task', 'Removed', 'New'),
+    ('Agile', 'bug', 'New', 'Active'),
+    ('Agile', 'bug', 'New', 'Resolved'),
+    ('Agile', 'bug', 'Active', 'New'),
+    ('Agile', 'bug', 'Active', 'Resolved'),
+    ('Agile', 'bug', 'Resolved', 'Active'),
+    ('Agile', 'bug', 'Resolved', 'Closed'),
+    ('Agile', 'bug', 'Closed', 'Active'),
+    ('Agile', 'issue', 'Active', 'Closed'),
+    ('Agile', 'issue', 'Closed', 'Active'),
+    ('Scrum', 'portfolio', 'New', 'In Progress'),
+    ('Scrum', 'portfolio', 'New', 'Removed'),
+    ('Scrum', 'portfolio', 'In Progress', 'New'),
+    ('Scrum', 'portfolio', 'In Progress', 'Done'),
+    ('Scrum', 'portfolio', 'In Progress', 'Removed'),
+    ('Scrum', 'portfolio', 'Done', 'In Progress'),
+    ('Scrum', 'portfolio', 'Removed', 'New'),
+    ('Scrum', 'backlog', 'New', 'Approved'),
+    ('Scrum', 'backlog', 'New', 'Removed'),
+    ('Scrum', 'backlog', 'Approved', 'New'),
+    ('Scrum', 'backlog', 'Approved', 'Committed'),
+    ('Scrum', 'backlog', 'Approved', 'Removed'),
+    ('Scrum', 'backlog', 'Committed', 'Approved'),
+    ('Scrum', 'backlog', 'Committed', 'Done'),
+    ('Scrum', 'backlog', 'Committed', 'Removed'),
+    ('Scrum', 'backlog', 'Done', 'Committed'),
+    ('Scrum', 'backlog', 'Removed', 'New'),
+    ('Scrum', 'task', 'To Do', 'In Progress'),
+    ('Scrum', 'task', 'To Do', 'Removed'),
+    ('Scrum', 'task', 'In Progress', 'To Do'),
+    ('Scrum', 'task', 'In Progress', 'Done'),
+    ('Scrum', 'task', 'In Progress', 'Removed'),
+    ('Scrum', 'task', 'Done', 'In Progress'),
+    ('Scrum', 'task', 'Removed', 'To Do'),
+    ('Scrum', 'impediment', 'Open', 'Closed'),
+    ('Scrum', 'impediment', 'Closed', 'Open');
+
+INSERT INTO process_transitions (template, w_type, from_state, to_state)
+SELECT t.template, t.w_type, s.from_state, s.to_state
+FROM process_type_seed t
+JOIN process_transition_seed s ON s.template = t.template AND s.state_set = t.state_set;
+
+DROP TABLE process_type_seed;
+DROP TABLE process_state_seed;
+DROP TABLE process_transition_seed;
@@ -0,0 +1,29 @@
+-- Add up migration script here
+-- One row per change to a work item. changes holds {"field": {"old": ..., "new": ...}}
+-- and snapshot the whole item after the change (NULL once deleted). Rows outlive the
+-- work item so deletions stay auditable; changed_by is NULL for Azure DevOps syncs.
+CREATE TABLE work_item_revisions (
+    work_item_id UUID NOT NULL,
+    revision INT NOT NULL,
+    operation VARCHAR NOT NULL
+        CONSTRAINT work_item_revisions_operation_check
+        CHECK (operation IN ('create', 'update', 'delete')),
+    changed_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    changes JSONB NOT NULL,
+    snapshot JSONB,
+    PRIMARY KEY (work_item_id, revision)
+);
+
+CREATE INDEX idx_work_item_revisions_changed_by ON work_item_revisions(changed_by);
+
+-- Existing work items start their history from their current state
+INSERT INTO work_item_revisions (work_item_id, revision, operation, changed_at, changes, snapshot)
+SELECT w.id, 1, 'create', COALESCE(w.created_date AT TIME ZONE 'UTC', NOW()),
+    (
+        SELECT COALESCE(jsonb_object_agg(field.key, jsonb_build_object('old', NULL, 'new', field.value)), '{}')
+        FROM jsonb_each(to_jsonb(w)) field
+        WHERE field.value <> 'null'::jsonb AND field.key NOT IN ('id', 'changed_date')
+    ),
+    to_jsonb(w)
+FROM work_items w;
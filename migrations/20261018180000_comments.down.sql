@@ -0,0 +1,8 @@
+-- Add down migration script here
+DELETE FROM work_item_revisions WHERE operation = 'comment';
+ALTER TABLE work_item_revisions DROP CONSTRAINT work_item_revisions_operation_check;
+ALTER TABLE work_item_revisions ADD CONSTRAINT work_item_revisions_operation_check
+    CHECK (operation IN ('create', 'update', 'delete'));
+
+DROP TABLE comment_mentions;
+DROP TABLE comments;
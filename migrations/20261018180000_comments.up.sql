@@ -0,0 +1,32 @@
+-- Add up migration script here
+-- Discussion on work items. A reply points at the comment it answers; deleting a
+-- comment keeps it as a tombstone (text NULL) so its replies stay in the thread.
+CREATE TABLE comments (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    work_item_id UUID NOT NULL REFERENCES work_items(id) ON DELETE CASCADE,
+    parent_id UUID REFERENCES comments(id) ON DELETE CASCADE,
+    author_id UUID NOT NULL REFERENCES users(id),
+    text TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ,
+    deleted_at TIMESTAMPTZ,
+    CONSTRAINT comments_text_check CHECK (text IS NOT NULL OR deleted_at IS NOT NULL)
+);
+
+CREATE INDEX idx_comments_work_item_id ON comments(work_item_id, created_at);
+CREATE INDEX idx_comments_parent_id ON comments(parent_id);
+CREATE INDEX idx_comments_author_id ON comments(author_id);
+
+-- Users @mentioned in a comment
+CREATE TABLE comment_mentions (
+    comment_id UUID NOT NULL REFERENCES comments(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    PRIMARY KEY (comment_id, user_id)
+);
+
+CREATE INDEX idx_comment_mentions_user_id ON comment_mentions(user_id);
+
+-- Comment activity shows up in the work item history
+ALTER TABLE work_item_revisions DROP CONSTRAINT work_item_revisions_operation_check;
+ALTER TABLE work_item_revisions ADD CONSTRAINT work_item_revisions_operation_check
+    CHECK (operation IN ('create', 'update', 'delete', 'comment'));
@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE work_items DROP COLUMN iteration_id;
+DROP TABLE iterations;
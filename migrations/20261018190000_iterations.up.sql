@@ -0,0 +1,31 @@
+-- Add up migration script here
+-- Sprints of a project. path is the Azure DevOps iteration path (e.g. "Project\Sprint 1")
+-- that work items still carry as text; work items with a matching path link to the iteration.
+CREATE TABLE iterations (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    azure_id VARCHAR UNIQUE,
+    name VARCHAR NOT NULL,
+    path VARCHAR NOT NULL,
+    start_date TIMESTAMPTZ,
+    end_date TIMESTAMPTZ,
+    CONSTRAINT iterations_date_range_check CHECK (end_date IS NULL OR start_date IS NULL OR end_date >= start_date)
+);
+
+CREATE UNIQUE INDEX idx_iterations_project_path ON iterations(project_id, lower(path));
+CREATE INDEX idx_iterations_dates ON iterations(project_id, start_date, end_date);
+
+ALTER TABLE work_items ADD COLUMN iteration_id UUID REFERENCES iterations(id);
+CREATE INDEX idx_work_items_iteration_id ON work_items(iteration_id);
+
+-- Every iteration path already in use becomes an unscheduled iteration
+INSERT INTO iterations (project_id, name, path)
+SELECT DISTINCT ON (project, lower(iteration_path))
+    project, regexp_replace(iteration_path, '^.*\\', ''), iteration_path
+FROM work_items
+WHERE iteration_path IS NOT NULL AND iteration_path <> ''
+ORDER BY project, lower(iteration_path), iteration_path;
+
+UPDATE work_items w SET iteration_id = i.id
+FROM iterations i
+WHERE i.project_id = w.project AND lower(i.path) = lower(w.iteration_path);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX idx_work_items_area_path;
+DROP TABLE areas;
@@ -0,0 +1,41 @@
+-- Add up migration script here
+-- Area tree of a project. path is the Azure DevOps area path (e.g. "Project\Web\Checkout")
+-- that work items carry as text; a team owns the subtrees of the areas it is set on.
+CREATE TABLE areas (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    parent_id UUID REFERENCES areas(id) ON DELETE CASCADE,
+    azure_id VARCHAR UNIQUE,
+    name VARCHAR NOT NULL,
+    path VARCHAR NOT NULL,
+    team_id UUID REFERENCES teams(id) ON DELETE SET NULL
+);
+
+CREATE UNIQUE INDEX idx_areas_project_path ON areas(project_id, lower(path));
+CREATE INDEX idx_areas_parent_id ON areas(parent_id);
+CREATE INDEX idx_areas_team_id ON areas(team_id);
+CREATE INDEX idx_work_items_area_path ON work_items(project, lower(area_path));
+
+-- Every area path already in use becomes an area, along with each of its ancestors.
+-- The root area is named after the project and is not stored, its children are top-level.
+CREATE TEMPORARY TABLE area_seed AS
+SELECT DISTINCT ON (w.project, lower(prefix.path))
+    uuid_generate_v4() AS id, w.project, prefix.path, prefix.depth
+FROM work_items w
+    JOIN projects p ON p.id = w.project,
+    LATERAL (
+        SELECT array_to_string((string_to_array(w.area_path, '\'))[1:n], '\') AS path, n AS depth
+        FROM generate_series(1, array_length(string_to_array(w.area_path, '\'), 1)) n
+    ) prefix
+WHERE w.area_path IS NOT NULL AND w.area_path <> ''
+    AND NOT (prefix.depth = 1 AND lower(prefix.path) = lower(p.name))
+ORDER BY w.project, lower(prefix.path), prefix.path;
+
+INSERT INTO areas (id, project_id, parent_id, name, path)
+SELECT s.id, s.project, parent.id, regexp_replace(s.path, '^.*\\', ''), s.path
+FROM area_seed s
+LEFT JOIN area_seed parent
+    ON parent.project = s.project AND parent.depth = s.depth - 1
+    AND lower(parent.path) = lower(regexp_replace(s.path, '\\[^\\]*$', ''));
+
+DROP TABLE area_seed;
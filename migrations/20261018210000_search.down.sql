@@ -0,0 +1,8 @@
+-- Add down migration script here
+DROP INDEX idx_users_search;
+DROP INDEX idx_projects_search;
+DROP INDEX idx_work_items_search;
+DROP FUNCTION user_search_document;
+DROP FUNCTION project_search_document;
+DROP FUNCTION work_item_search_document;
+DROP FUNCTION html_escape(VARCHAR);
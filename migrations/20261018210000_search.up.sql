@@ -0,0 +1,38 @@
+-- Add up migration script here
+-- Search documents for GET /search. The functions are shared by the indexes and the
+-- queries, so both build exactly the same tsvector; titles and names weigh the most.
+CREATE FUNCTION work_item_search_document(title VARCHAR, description VARCHAR, tags VARCHAR[])
+RETURNS tsvector LANGUAGE sql IMMUTABLE PARALLEL SAFE AS $$
+    SELECT setweight(to_tsvector('english', coalesce(title, '')), 'A')
+        || setweight(to_tsvector('english', coalesce(array_to_string(tags, ' '), '')), 'B')
+        || setweight(to_tsvector('english', coalesce(description, '')), 'C')
+$$;
+
+CREATE FUNCTION project_search_document(name VARCHAR, description VARCHAR)
+RETURNS tsvector LANGUAGE sql IMMUTABLE PARALLEL SAFE AS $$
+    SELECT setweight(to_tsvector('english', coalesce(name, '')), 'A')
+        || setweight(to_tsvector('english', coalesce(description, '')), 'C')
+$$;
+
+-- the email is also split on its punctuation so "alice" finds alice.smith@example.com
+CREATE FUNCTION user_search_document(name VARCHAR, email VARCHAR)
+RETURNS tsvector LANGUAGE sql IMMUTABLE PARALLEL SAFE AS $$
+    SELECT setweight(to_tsvector('english', coalesce(name, '')), 'A')
+        || setweight(to_tsvector('english', coalesce(email, '')), 'B')
+        || setweight(to_tsvector('english', coalesce(regexp_replace(email, '[@._+-]', ' ', 'g'), '')), 'B')
+$$;
+
+CREATE INDEX idx_work_items_search ON work_items
+    USING GIN (work_item_search_document(title, description, tags));
+CREATE INDEX idx_projects_search ON projects
+    USING GIN (project_search_document(name, description));
+CREATE INDEX idx_users_search ON users
+    USING GIN (user_search_document(name, email));
+
+-- ts_headline copies the text it highlights verbatim, so search snippets are built
+-- from HTML-escaped text and <mark> is the only markup a client receives.
+CREATE FUNCTION html_escape(text VARCHAR)
+RETURNS VARCHAR LANGUAGE sql IMMUTABLE PARALLEL SAFE AS $$
+    SELECT replace(replace(replace(replace(replace(text,
+        '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '"', '&quot;'), '''', '&#39;')
+$$;
@@ -0,0 +1,14 @@
+-- Add up migration script here
+-- Tags are stored lowercased with single spaces and without duplicates, as written by the API
+UPDATE work_items SET tags = ARRAY(
+    SELECT tag FROM (
+        SELECT lower(btrim(regexp_replace(t, '\s+', ' ', 'g'))) AS tag, MIN(ord) AS ord
+        FROM unnest(tags) WITH ORDINALITY u(t, ord)
+        GROUP BY 1
+    ) normalized
+    WHERE tag <> ''
+    ORDER BY ord
+)
+WHERE tags IS NOT NULL;
+
+CREATE INDEX idx_work_items_tags ON work_items USING GIN (tags);
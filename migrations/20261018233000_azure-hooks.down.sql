@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE deleted_work_items;
+ALTER TABLE work_items DROP COLUMN azure_rev;
@@ -0,0 +1,12 @@
+-- Add up migration script here
+-- Azure DevOps revision number of the mirrored work item. Syncs and service hooks
+-- never overwrite a newer revision; local edits leave it untouched.
+ALTER TABLE work_items ADD COLUMN azure_rev INTEGER;
+
+-- Work items deleted in Azure DevOps, so a late update delivery does not bring one
+-- back. Only a newer revision, e.g. after a restore, is mirrored again.
+CREATE TABLE deleted_work_items (
+    azure_id VARCHAR PRIMARY KEY,
+    azure_rev INTEGER,
+    deleted_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
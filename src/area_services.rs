@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+
+use actix_web::{
+    delete, get, patch, post, put,
+    web::{Data, Json, Path, Query},
+    HttpResponse,
+};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    auth::CurrentUser,
+    error::AppError,
+    listing::{contains_pattern, Listing, Page, SortField},
+    model::{Area, AreaNode, ProjectModel, WorkItem},
+    policy::Permission,
+    projects_services::manage_permission,
+    revision_services,
+    schema::{AreaFilterOptions, CreateAreaRequest, UpdateAreaRequest, UpdateAreaTeamRequest},
+    AppState,
+};
+
+const AREA_SORT_FIELDS: &[SortField] = &[
+    SortField::new("path", "path", "varchar"),
+    SortField::new("name", "name", "varchar"),
+];
+
+async fn find_project(
+    tx: &mut Transaction<'_, Postgres>,
+    project_id: Uuid,
+) -> Result<ProjectModel, AppError> {
+    sqlx::query_as!(
+        ProjectModel,
+        "SELECT * FROM projects WHERE id = $1",
+        project_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Project {} not found", project_id)))
+}
+
+// Locks the area, since its path is the prefix a subtree move rewrites
+async fn find_area(tx: &mut Transaction<'_, Postgres>, area_id: Uuid) -> Result<Area, AppError> {
+    sqlx::query_as!(
+        Area,
+        "SELECT * FROM areas WHERE id = $1 FOR UPDATE",
+        area_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Area {} not found", area_id)))
+}
+
+async fn find_parent(
+    tx: &mut Transaction<'_, Postgres>,
+    project_id: Uuid,
+    parent_id: Uuid,
+) -> Result<Area, AppError> {
+    sqlx::query_as!(
+        Area,
+        "SELECT * FROM areas WHERE id = $1 AND project_id = $2 FOR UPDATE",
+        parent_id,
+        project_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| {
+        AppError::Unprocessable(format!(
+            "Area {} not found in project {}",
+            parent_id, project_id
+        ))
+    })
+}
+
+async fn check_team(tx: &mut Transaction<'_, Postgres>, team_id: Uuid) -> Result<(), AppError> {
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM teams WHERE id = $1) AS "exists!""#,
+        team_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if !exists {
+        return Err(AppError::Unprocessable(format!(
+            "Team {} not found",
+            team_id
+        )));
+    }
+    Ok(())
+}
+
+// Azure DevOps names the root area after the project, top-level areas sit right below it
+fn area_path(project: &ProjectModel, parent: Option<&Area>, name: &str) -> String {
+    match parent {
+        Some(parent) => format!("{}\\{}", parent.path, name),
+        None => format!("{}\\{}", project.name.as_deref().unwrap_or_default(), name),
+    }
+}
+
+// Rewrites the paths of the area's subtree and of the work items filed under it
+pub async fn move_paths(
+    tx: &mut Transaction<'_, Postgres>,
+    changed_by: Option<Uuid>,
+    project_id: Uuid,
+    old_path: &str,
+    new_path: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r"UPDATE areas SET path = $3 || substr(path, length($2) + 1)
+        WHERE project_id = $1
+            AND (lower(path) = lower($2) OR starts_with(lower(path), lower($2) || '\'))",
+        project_id,
+        old_path,
+        new_path
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let workitems = sqlx::query_as!(
+        WorkItem,
+        r"SELECT * FROM work_items
+        WHERE project = $1
+            AND (lower(area_path) = lower($2) OR starts_with(lower(area_path), lower($2) || '\'))
+        FOR UPDATE",
+        project_id,
+        old_path
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for before in &workitems {
+        let after = sqlx::query_as!(
+            WorkItem,
+            "UPDATE work_items SET area_path = $1 || substr(area_path, length($2) + 1),
+                changed_date = NOW()
+            WHERE id = $3 RETURNING *",
+            new_path,
+            old_path,
+            before.id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        revision_services::record_and_publish(tx, changed_by, Some(before), Some(&after)).await?;
+    }
+    Ok(())
+}
+
+fn build_tree(
+    children: &mut HashMap<Option<Uuid>, Vec<Area>>,
+    parent: Option<Uuid>,
+) -> Vec<AreaNode> {
+    children
+        .remove(&parent)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|area| AreaNode {
+            children: build_tree(children, Some(area.id)),
+            area,
+        })
+        .collect()
+}
+
+#[post("/projects/{id}/areas")]
+async fn create_area(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    body: Json<CreateAreaRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
+    let mut tx = data.db.begin().await?;
+
+    let project = find_project(&mut tx, path.into_inner()).await?;
+    current
+        .authorize(&data.db, manage_permission(project.team_id))
+        .await?;
+
+    let parent = match body.parent_id {
+        Some(parent_id) => Some(find_parent(&mut tx, project.id, parent_id).await?),
+        None => None,
+    };
+    if let Some(team_id) = body.team_id {
+        check_team(&mut tx, team_id).await?;
+    }
+
+    let area = sqlx::query_as!(
+        Area,
+        "INSERT INTO areas (project_id, parent_id, azure_id, name, path, team_id)
+        VALUES ($1,$2,$3,$4,$5,$6) RETURNING *",
+        project.id,
+        body.parent_id,
+        body.azure_id,
+        body.name,
+        area_path(&project, parent.as_ref(), &body.name),
+        body.team_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Created().json(json!({"status":"success", "data":area})))
+}
+
+#[get("/projects/{id}/areas")]
+async fn get_project_areas(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    opts: Query<AreaFilterOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let project_id = path.into_inner();
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM projects WHERE id = $1) AS "exists!""#,
+        project_id
+    )
+    .fetch_one(&data.db)
+    .await?;
+    if !exists {
+        return Err(AppError::NotFound(format!(
+            "Project {} not found",
+            project_id
+        )));
+    }
+
+    let mut listing = Listing::new("*", "areas", "id", "uuid", AREA_SORT_FIELDS, "path");
+    listing
+        .filter("project_id = $", Some(project_id))
+        .filter("name ILIKE $", opts.name.as_deref().map(contains_pattern))
+        .filter("parent_id = $", opts.parent_id)
+        .filter("team_id = $", opts.team_id);
+
+    let page: Page<Area> = listing
+        .fetch(
+            &data.db,
+            opts.sort.as_deref(),
+            opts.cursor.as_deref(),
+            opts.limit,
+        )
+        .await?;
+
+    Ok(page.into_response("areas"))
+}
+
+#[get("/projects/{id}/areas/tree")]
+async fn get_project_area_tree(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let mut tx = data.db.begin().await?;
+    let project = find_project(&mut tx, path.into_inner()).await?;
+
+    let areas = sqlx::query_as!(
+        Area,
+        "SELECT * FROM areas WHERE project_id = $1 ORDER BY lower(path), id",
+        project.id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    let mut children: HashMap<Option<Uuid>, Vec<Area>> = HashMap::new();
+    for area in areas {
+        children.entry(area.parent_id).or_default().push(area);
+    }
+    let tree = build_tree(&mut children, None);
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status":"success",
+        "result": tree.len(),
+        "areas": tree
+    })))
+}
+
+#[get("/areas/{id}")]
+async fn get_area_by_id(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let area_id = path.into_inner();
+
+    let area = sqlx::query_as!(Area, "SELECT * FROM areas WHERE id = $1", area_id)
+        .fetch_optional(&data.db)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Area {} not found", area_id)))?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":area})))
+}
+
+#[patch("/areas/{id}")]
+async fn update_area_by_id(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    body: Json<UpdateAreaRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
+    let area_id = path.into_inner();
+
+    let mut tx = data.db.begin().await?;
+
+    let before = find_area(&mut tx, area_id).await?;
+    let project = find_project(&mut tx, before.project_id).await?;
+    current
+        .authorize(&data.db, manage_permission(project.team_id))
+        .await?;
+
+    let parent = match body.parent_id {
+        Some(Some(parent_id)) => {
+            let parent = find_parent(&mut tx, project.id, parent_id).await?;
+            let below_itself = parent.id == before.id
+                || parent
+                    .path
+                    .to_lowercase()
+                    .starts_with(&format!("{}\\", before.path.to_lowercase()));
+            if below_itself {
+                return Err(AppError::Validation(
+                    "An area cannot be moved below itself".to_string(),
+                ));
+            }
+            Some(parent)
+        }
+        Some(None) => None,
+        None => match before.parent_id {
+            Some(parent_id) => Some(find_area(&mut tx, parent_id).await?),
+            None => None,
+        },
+    };
+    let name = body.name.as_deref().unwrap_or(&before.name);
+    let new_path = area_path(&project, parent.as_ref(), name);
+
+    sqlx::query!(
+        "UPDATE areas SET name = $1, parent_id = $2 WHERE id = $3",
+        name,
+        parent.as_ref().map(|parent| parent.id),
+        area_id
+    )
+    .execute(&mut tx)
+    .await?;
+    if new_path != before.path {
+        move_paths(
+            &mut tx,
+            Some(current.0.id),
+            project.id,
+            &before.path,
+            &new_path,
+        )
+        .await?;
+    }
+
+    let area = find_area(&mut tx, area_id).await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":area})))
+}
+
+#[put("/areas/{id}/team")]
+async fn update_area_team(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    body: Json<UpdateAreaTeamRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
+    let area_id = path.into_inner();
+
+    let mut tx = data.db.begin().await?;
+
+    let area = find_area(&mut tx, area_id).await?;
+    let project = find_project(&mut tx, area.project_id).await?;
+    current
+        .authorize(&data.db, manage_permission(project.team_id))
+        .await?;
+    if let Some(team_id) = body.team_id {
+        check_team(&mut tx, team_id).await?;
+    }
+
+    let area = sqlx::query_as!(
+        Area,
+        "UPDATE areas SET team_id = $1 WHERE id = $2 RETURNING *",
+        body.team_id,
+        area_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":area})))
+}
+
+#[delete("/areas/{id}")]
+async fn delete_area(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let area_id = path.into_inner();
+
+    let mut tx = data.db.begin().await?;
+
+    let area = find_area(&mut tx, area_id).await?;
+    let project = find_project(&mut tx, area.project_id).await?;
+    current
+        .authorize(&data.db, manage_permission(project.team_id))
+        .await?;
+
+    //the areas below go with it, so none of them may still hold work items
+    let workitems = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM work_items
+        WHERE project = $1
+            AND (lower(area_path) = lower($2) OR starts_with(lower(area_path), lower($2) || '\'))"#,
+        project.id,
+        area.path
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    if workitems > 0 {
+        return Err(AppError::Conflict(format!(
+            "Area {} still has {} work items",
+            area_id, workitems
+        )));
+    }
+
+    sqlx::query!("DELETE FROM areas WHERE id = $1", area_id)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
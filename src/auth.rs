@@ -0,0 +1,532 @@
+use std::{
+    collections::HashMap,
+    future::{ready, Ready},
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web::{Data, Query},
+    Error, FromRequest, HttpMessage, HttpRequest,
+};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{AlgorithmParameters, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use log::{info, warn};
+use serde::Deserialize;
+use sqlx::{Executor, Postgres};
+
+use crate::{error::AppError, model::User, AppState};
+
+// Routes reachable without a bearer token; the service hook receiver checks its own credentials
+const PUBLIC_PATHS: &[&str] = &["/api/healthcheck", "/api/integrations/azure/hooks"];
+// Browsers cannot set headers on EventSource and WebSocket requests, so the live
+// feed also takes the token as an access_token query parameter
+const QUERY_TOKEN_PATHS: &[&str] = &["/api/live", "/api/live/ws"];
+// A token signed with an unknown kid refreshes the JWKS at most this often
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub email: Option<String>,
+}
+
+/// The authenticated caller, resolved from the bearer token's subject.
+#[derive(Debug, Clone)]
+pub struct CurrentUser(pub User);
+
+impl FromRequest for CurrentUser {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<CurrentUser>()
+                .cloned()
+                .ok_or_else(|| AppError::Unauthorized("Authentication required".to_string())),
+        )
+    }
+}
+
+enum JwksSource {
+    File(String),
+    Url(String),
+}
+
+struct RsaKeys {
+    keys: HashMap<Option<String>, DecodingKey>,
+    fetched_at: Instant,
+}
+
+/// Verifies bearer tokens signed either with an HS256 shared secret or with
+/// RS256 keys published as a JWKS document (local file or URL).
+///
+/// Configured through `JWT_HS256_SECRET`, `JWT_JWKS_FILE` / `JWT_JWKS_URL`,
+/// and optionally `JWT_ISSUER` and `JWT_AUDIENCE`.
+pub struct Authenticator {
+    hs256: Option<DecodingKey>,
+    jwks: Option<JwksSource>,
+    rsa: RwLock<RsaKeys>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    http: reqwest::Client,
+}
+
+impl Authenticator {
+    pub async fn from_env() -> Result<Self, String> {
+        let hs256 = std::env::var("JWT_HS256_SECRET")
+            .ok()
+            .map(|secret| DecodingKey::from_secret(secret.as_bytes()));
+        let jwks = match (
+            std::env::var("JWT_JWKS_FILE"),
+            std::env::var("JWT_JWKS_URL"),
+        ) {
+            (Ok(path), _) => Some(JwksSource::File(path)),
+            (_, Ok(url)) => Some(JwksSource::Url(url)),
+            _ => None,
+        };
+        if hs256.is_none() && jwks.is_none() {
+            return Err(
+                "one of JWT_HS256_SECRET, JWT_JWKS_FILE or JWT_JWKS_URL must be set".to_string(),
+            );
+        }
+
+        Self::new(
+            hs256,
+            jwks,
+            std::env::var("JWT_ISSUER").ok(),
+            std::env::var("JWT_AUDIENCE").ok(),
+        )
+        .await
+    }
+
+    async fn new(
+        hs256: Option<DecodingKey>,
+        jwks: Option<JwksSource>,
+        issuer: Option<String>,
+        audience: Option<String>,
+    ) -> Result<Self, String> {
+        let authenticator = Authenticator {
+            hs256,
+            jwks,
+            rsa: RwLock::new(RsaKeys {
+                keys: HashMap::new(),
+                fetched_at: Instant::now(),
+            }),
+            issuer,
+            audience,
+            http: reqwest::Client::new(),
+        };
+        if authenticator.jwks.is_some() {
+            authenticator.refresh_jwks().await?;
+        }
+        Ok(authenticator)
+    }
+
+    async fn load_jwks(&self) -> Result<JwkSet, String> {
+        match &self.jwks {
+            Some(JwksSource::File(path)) => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|error| format!("reading {}: {}", path, error))?;
+                serde_json::from_str(&contents)
+                    .map_err(|error| format!("parsing {}: {}", path, error))
+            }
+            Some(JwksSource::Url(url)) => self
+                .http
+                .get(url)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|error| format!("fetching {}: {}", url, error))?
+                .json()
+                .await
+                .map_err(|error| format!("parsing {}: {}", url, error)),
+            None => Ok(JwkSet { keys: Vec::new() }),
+        }
+    }
+
+    async fn refresh_jwks(&self) -> Result<(), String> {
+        let set = self.load_jwks().await?;
+        let keys: HashMap<_, _> = set
+            .keys
+            .iter()
+            .filter(|jwk| matches!(jwk.algorithm, AlgorithmParameters::RSA(_)))
+            .filter_map(|jwk| {
+                DecodingKey::from_jwk(jwk)
+                    .map(|key| (jwk.common.key_id.clone(), key))
+                    .ok()
+            })
+            .collect();
+        info!("Loaded {} RSA signing keys from JWKS", keys.len());
+
+        let mut rsa = self.rsa.write().unwrap();
+        rsa.keys = keys;
+        rsa.fetched_at = Instant::now();
+        Ok(())
+    }
+
+    fn rsa_key(&self, kid: &Option<String>) -> Option<DecodingKey> {
+        let rsa = self.rsa.read().unwrap();
+        // a token without a kid is accepted when the JWKS holds a single key
+        match (rsa.keys.get(kid), kid) {
+            (Some(key), _) => Some(key.clone()),
+            (None, None) if rsa.keys.len() == 1 => rsa.keys.values().next().cloned(),
+            _ => None,
+        }
+    }
+
+    async fn decoding_key(
+        &self,
+        alg: Algorithm,
+        kid: &Option<String>,
+    ) -> Result<DecodingKey, AppError> {
+        match alg {
+            Algorithm::HS256 => self
+                .hs256
+                .clone()
+                .ok_or_else(|| AppError::Unauthorized("HS256 tokens are not accepted".to_string())),
+            Algorithm::RS256 => {
+                if let Some(key) = self.rsa_key(kid) {
+                    return Ok(key);
+                }
+                // keys may have been rotated since the last fetch, whether published at
+                // the URL or written to the file
+                let stale = self.rsa.read().unwrap().fetched_at.elapsed() >= JWKS_REFRESH_INTERVAL;
+                if self.jwks.is_some() && stale {
+                    if let Err(error) = self.refresh_jwks().await {
+                        warn!("JWKS refresh failed: {}", error);
+                    }
+                }
+                self.rsa_key(kid).ok_or_else(|| {
+                    AppError::Unauthorized("Token signing key is not trusted".to_string())
+                })
+            }
+            _ => Err(AppError::Unauthorized(format!(
+                "Tokens signed with {:?} are not accepted",
+                alg
+            ))),
+        }
+    }
+
+    pub async fn verify(&self, token: &str) -> Result<Claims, AppError> {
+        let header = decode_header(token)
+            .map_err(|_| AppError::Unauthorized("Malformed bearer token".to_string()))?;
+        let key = self.decoding_key(header.alg, &header.kid).await?;
+
+        let mut validation = Validation::new(header.alg);
+        match &self.issuer {
+            Some(issuer) => validation.set_issuer(&[issuer]),
+            None => validation.iss = None,
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        decode::<Claims>(token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|error| AppError::Unauthorized(format!("Invalid bearer token: {}", error)))
+    }
+}
+
+// The subject matches users.azure_id; the email claim (or the subject itself) falls back to users.email
+async fn find_token_user<'e, E: Executor<'e, Database = Postgres>>(
+    executor: E,
+    claims: &Claims,
+) -> Result<User, AppError> {
+    let email = claims.email.as_deref().unwrap_or(&claims.sub);
+    sqlx::query_as!(
+        User,
+        "SELECT * FROM users WHERE azure_id = $1 OR lower(email) = lower($2)
+        ORDER BY azure_id = $1 DESC NULLS LAST LIMIT 1",
+        claims.sub,
+        email
+    )
+    .fetch_optional(executor)
+    .await?
+    .ok_or_else(|| {
+        AppError::Unauthorized(format!("Token subject {} is not a known user", claims.sub))
+    })
+}
+
+// The access_token query parameter of the paths that take one
+fn query_token(req: &ServiceRequest) -> Option<String> {
+    if !QUERY_TOKEN_PATHS.contains(&req.path()) {
+        return None;
+    }
+    Query::<HashMap<String, String>>::from_query(req.query_string())
+        .ok()?
+        .remove("access_token")
+}
+
+/// The request line for the access log, `%r` with an `access_token` query
+/// parameter masked, so tokens given to the live feed are not written to logs.
+pub fn logged_request_line(req: &ServiceRequest) -> String {
+    let query = req
+        .query_string()
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some(("access_token", _)) => "access_token=***",
+            _ => pair,
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    let target = if query.is_empty() {
+        req.path().to_string()
+    } else {
+        format!("{}?{}", req.path(), query)
+    };
+    format!("{} {} {:?}", req.method(), target, req.version())
+}
+
+/// Middleware rejecting requests without a valid bearer token and attaching
+/// the matching [`CurrentUser`] to the request.
+pub async fn authenticate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if PUBLIC_PATHS.contains(&req.path()) {
+        return next.call(req).await;
+    }
+
+    let data = req
+        .app_data::<Data<AppState>>()
+        .cloned()
+        .expect("AppState must be registered");
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(String::from)
+        .or_else(|| query_token(&req))
+        .ok_or_else(|| AppError::Unauthorized("Missing bearer token".to_string()))?;
+
+    let claims = data.auth.verify(token.trim()).await?;
+    let user = find_token_user(&data.db, &claims).await?;
+    req.extensions_mut().insert(CurrentUser(user));
+
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use openssl::rsa::Rsa;
+    use serde_json::json;
+    use sqlx::postgres::PgPoolOptions;
+    use uuid::Uuid;
+
+    use super::*;
+
+    const SECRET: &str = "test-secret";
+
+    struct RsaKey {
+        kid: String,
+        encoding: EncodingKey,
+        jwk: serde_json::Value,
+    }
+
+    fn rsa_key(kid: &str) -> RsaKey {
+        let rsa = Rsa::generate(2048).unwrap();
+        let encoding = EncodingKey::from_rsa_pem(&rsa.private_key_to_pem().unwrap()).unwrap();
+        let jwk = json!({
+            "kty": "RSA",
+            "kid": kid,
+            "use": "sig",
+            "alg": "RS256",
+            "n": URL_SAFE_NO_PAD.encode(rsa.n().to_vec()),
+            "e": URL_SAFE_NO_PAD.encode(rsa.e().to_vec()),
+        });
+        RsaKey {
+            kid: kid.to_string(),
+            encoding,
+            jwk,
+        }
+    }
+
+    fn token_claims(sub: &str, email: Option<&str>) -> serde_json::Value {
+        json!({
+            "sub": sub,
+            "email": email,
+            "exp": jsonwebtoken::get_current_timestamp() + 600,
+        })
+    }
+
+    fn hs256_token(secret: &str, sub: &str) -> String {
+        encode(
+            &Header::new(Algorithm::HS256),
+            &token_claims(sub, None),
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    fn rs256_token(key: &RsaKey, sub: &str) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(key.kid.clone());
+        encode(&header, &token_claims(sub, None), &key.encoding).unwrap()
+    }
+
+    fn write_jwks(path: &std::path::Path, keys: &[&RsaKey]) {
+        let set = json!({"keys": keys.iter().map(|key| &key.jwk).collect::<Vec<_>>()});
+        std::fs::write(path, set.to_string()).unwrap();
+    }
+
+    async fn hs256_authenticator() -> Authenticator {
+        let key = DecodingKey::from_secret(SECRET.as_bytes());
+        Authenticator::new(Some(key), None, None, None)
+            .await
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn verify_accepts_hs256_tokens_signed_with_the_secret() {
+        let authenticator = hs256_authenticator().await;
+
+        let claims = authenticator
+            .verify(&hs256_token(SECRET, "aad-alice"))
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, "aad-alice");
+        assert_eq!(claims.email, None);
+
+        let forged = authenticator
+            .verify(&hs256_token("another-secret", "aad-alice"))
+            .await;
+        assert!(matches!(forged, Err(AppError::Unauthorized(_))));
+
+        let rs256 = authenticator
+            .verify(&rs256_token(&rsa_key("k1"), "aad-alice"))
+            .await;
+        assert!(matches!(rs256, Err(AppError::Unauthorized(_))));
+    }
+
+    #[actix_web::test]
+    async fn verify_follows_kid_rotation_in_a_jwks_file() {
+        let path = std::env::temp_dir().join(format!("jwks-{}.json", Uuid::new_v4()));
+        let old_key = rsa_key("k1");
+        let new_key = rsa_key("k2");
+        write_jwks(&path, &[&old_key]);
+
+        let source = JwksSource::File(path.to_string_lossy().into_owned());
+        let authenticator = Authenticator::new(None, Some(source), None, None)
+            .await
+            .unwrap();
+        let claims = authenticator
+            .verify(&rs256_token(&old_key, "aad-alice"))
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, "aad-alice");
+        let unknown = authenticator
+            .verify(&rs256_token(&new_key, "aad-alice"))
+            .await;
+        assert!(matches!(unknown, Err(AppError::Unauthorized(_))));
+
+        // rotate: the file now only publishes the new key, picked up once the
+        // loaded keys are due for a refresh
+        write_jwks(&path, &[&new_key]);
+        authenticator.rsa.write().unwrap().fetched_at =
+            Instant::now().checked_sub(JWKS_REFRESH_INTERVAL).unwrap();
+        let claims = authenticator
+            .verify(&rs256_token(&new_key, "aad-bob"))
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, "aad-bob");
+        let retired = authenticator
+            .verify(&rs256_token(&old_key, "aad-alice"))
+            .await;
+        assert!(matches!(retired, Err(AppError::Unauthorized(_))));
+
+        // an HS256 token is refused when no secret is configured
+        let hs256 = authenticator
+            .verify(&hs256_token(SECRET, "aad-alice"))
+            .await;
+        assert!(matches!(hs256, Err(AppError::Unauthorized(_))));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    // run with `cargo test -- --ignored` against a migrated database
+    #[actix_web::test]
+    #[ignore = "needs DATABASE_URL pointing at a migrated database"]
+    async fn find_token_user_resolves_the_subject_by_azure_id_then_email() {
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let db = PgPoolOptions::new()
+            .max_connections(1)
+            .connect(&database_url)
+            .await
+            .unwrap();
+        let mut tx = db.begin().await.unwrap();
+
+        let azure_id = format!("aad-{}", Uuid::new_v4());
+        let email = format!("{}@example.com", Uuid::new_v4());
+        let by_azure_id: Uuid = sqlx::query_scalar(
+            "INSERT INTO users (name, azure_id, email) VALUES ('Alice', $1, NULL) RETURNING id",
+        )
+        .bind(&azure_id)
+        .fetch_one(&mut tx)
+        .await
+        .unwrap();
+        let by_email: Uuid = sqlx::query_scalar(
+            "INSERT INTO users (name, azure_id, email) VALUES ('Bob', NULL, $1) RETURNING id",
+        )
+        .bind(&email)
+        .fetch_one(&mut tx)
+        .await
+        .unwrap();
+
+        let authenticator = hs256_authenticator().await;
+        let claims = authenticator
+            .verify(&hs256_token(SECRET, &azure_id))
+            .await
+            .unwrap();
+        let user = find_token_user(&mut tx, &claims).await.unwrap();
+        assert_eq!(user.id, by_azure_id);
+
+        // a subject unknown as azure id falls back to the email claim, case insensitively
+        let token = encode(
+            &Header::new(Algorithm::HS256),
+            &token_claims("aad-unknown", Some(&email.to_uppercase())),
+            &EncodingKey::from_secret(SECRET.as_bytes()),
+        )
+        .unwrap();
+        let claims = authenticator.verify(&token).await.unwrap();
+        let user = find_token_user(&mut tx, &claims).await.unwrap();
+        assert_eq!(user.id, by_email);
+
+        // without an email claim the subject itself is tried as email
+        let claims = authenticator
+            .verify(&hs256_token(SECRET, &email))
+            .await
+            .unwrap();
+        let user = find_token_user(&mut tx, &claims).await.unwrap();
+        assert_eq!(user.id, by_email);
+
+        // the azure id wins over another user's email
+        let claims = Claims {
+            sub: azure_id.clone(),
+            email: Some(email.clone()),
+        };
+        let user = find_token_user(&mut tx, &claims).await.unwrap();
+        assert_eq!(user.id, by_azure_id);
+
+        let claims = authenticator
+            .verify(&hs256_token(SECRET, "aad-nobody"))
+            .await
+            .unwrap();
+        let unknown = find_token_user(&mut tx, &claims).await;
+        assert!(matches!(unknown, Err(AppError::Unauthorized(_))));
+
+        tx.rollback().await.unwrap();
+    }
+}
@@ -0,0 +1,307 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::{Client, RequestBuilder};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+
+const API_VERSION: &str = "7.0";
+const PAGE_SIZE: usize = 100;
+// Azure DevOps rejects work item batch requests with more than 200 ids
+const WORK_ITEM_BATCH: usize = 200;
+// Azure DevOps returns at most this many work items for one WIQL query
+const WIQL_MAX_RESULTS: usize = 20_000;
+
+#[derive(Debug, Deserialize)]
+pub struct AzureList<T> {
+    pub value: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AzureProject {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub url: Option<String>,
+    // only returned when a single project is read
+    #[serde(rename = "defaultTeam", default)]
+    pub default_team: Option<AzureTeamReference>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AzureTeamReference {
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AzureTeam {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AzureTeamMember {
+    pub identity: AzureIdentity,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AzureIdentity {
+    pub id: String,
+    pub display_name: Option<String>,
+    pub unique_name: Option<String>,
+}
+
+impl AzureIdentity {
+    // uniqueName is the sign-in address for AAD users and DOMAIN\user otherwise
+    pub fn email(&self) -> Option<&str> {
+        self.unique_name
+            .as_deref()
+            .filter(|name| name.contains('@'))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct WiqlResult {
+    #[serde(rename = "workItems")]
+    work_items: Vec<WiqlReference>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WiqlReference {
+    id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AzureWorkItem {
+    pub id: i64,
+    #[serde(default)]
+    pub rev: Option<i32>,
+    pub url: String,
+    pub fields: AzureWorkItemFields,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AzureWorkItemFields {
+    #[serde(rename = "System.Title")]
+    pub title: String,
+    #[serde(rename = "System.WorkItemType")]
+    pub work_item_type: String,
+    #[serde(rename = "System.State")]
+    pub state: String,
+    #[serde(rename = "System.TeamProject")]
+    pub team_project: Option<String>,
+    #[serde(rename = "System.AssignedTo")]
+    pub assigned_to: Option<AzureIdentity>,
+    #[serde(rename = "System.CreatedBy")]
+    pub created_by: AzureIdentity,
+    #[serde(rename = "System.CreatedDate")]
+    pub created_date: Option<DateTime<Utc>>,
+    #[serde(rename = "System.ChangedDate")]
+    pub changed_date: Option<DateTime<Utc>>,
+    #[serde(rename = "Microsoft.VSTS.Common.Priority")]
+    pub priority: Option<i32>,
+    #[serde(rename = "Microsoft.VSTS.Common.Severity")]
+    pub severity: Option<String>,
+    #[serde(rename = "System.Description")]
+    pub description: Option<String>,
+    #[serde(rename = "System.AreaPath")]
+    pub area_path: Option<String>,
+    #[serde(rename = "System.IterationPath")]
+    pub iteration_path: Option<String>,
+    #[serde(rename = "System.Parent")]
+    pub parent: Option<i64>,
+    #[serde(rename = "System.Tags")]
+    pub tags: Option<String>,
+}
+
+impl AzureWorkItemFields {
+    // System.Tags is a single "tag1; tag2" string
+    pub fn tag_list(&self) -> Option<Vec<String>> {
+        self.tags.as_ref().map(|tags| {
+            tags.split(';')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(String::from)
+                .collect()
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct AzureClient {
+    http: Client,
+    base_url: String,
+    pat: Option<String>,
+}
+
+impl AzureClient {
+    pub fn new(base_url: &str, pat: Option<String>) -> Self {
+        AzureClient {
+            http: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            pat,
+        }
+    }
+
+    // AZURE_DEVOPS_URL is the organization URL, e.g. https://dev.azure.com/my-org,
+    // or a local mock server when testing
+    pub fn from_env() -> Option<Self> {
+        let base_url = std::env::var("AZURE_DEVOPS_URL").ok()?;
+        let pat = std::env::var("AZURE_DEVOPS_PAT").ok();
+        Some(AzureClient::new(&base_url, pat))
+    }
+
+    fn request(&self, builder: RequestBuilder) -> RequestBuilder {
+        let builder = builder.query(&[("api-version", API_VERSION)]);
+        match &self.pat {
+            Some(pat) => builder.basic_auth("", Some(pat)),
+            None => builder,
+        }
+    }
+
+    async fn get(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let url = format!("{}/{}", self.base_url, path);
+        self.request(self.http.get(url).query(query))
+            .send()
+            .await?
+            .error_for_status()
+    }
+
+    async fn get_paged<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<T>, reqwest::Error> {
+        let mut items = Vec::new();
+        loop {
+            let page: AzureList<T> = self
+                .get(
+                    path,
+                    &[
+                        ("$top", PAGE_SIZE.to_string()),
+                        ("$skip", items.len().to_string()),
+                    ],
+                )
+                .await?
+                .json()
+                .await?;
+            let fetched = page.value.len();
+            items.extend(page.value);
+            if fetched < PAGE_SIZE {
+                return Ok(items);
+            }
+        }
+    }
+
+    pub async fn projects(&self) -> Result<Vec<AzureProject>, reqwest::Error> {
+        let mut projects = Vec::new();
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut query = vec![("$top", PAGE_SIZE.to_string())];
+            if let Some(token) = &continuation {
+                query.push(("continuationToken", token.clone()));
+            }
+            let response = self.get("_apis/projects", &query).await?;
+            continuation = response
+                .headers()
+                .get("x-ms-continuationtoken")
+                .and_then(|value| value.to_str().ok())
+                .map(String::from);
+            let page: AzureList<AzureProject> = response.json().await?;
+            projects.extend(page.value);
+            if continuation.is_none() {
+                return Ok(projects);
+            }
+        }
+    }
+
+    pub async fn project(&self, project_id: &str) -> Result<AzureProject, reqwest::Error> {
+        self.get(&format!("_apis/projects/{}", project_id), &[])
+            .await?
+            .json()
+            .await
+    }
+
+    pub async fn teams(&self, project_id: &str) -> Result<Vec<AzureTeam>, reqwest::Error> {
+        self.get_paged(&format!("_apis/projects/{}/teams", project_id))
+            .await
+    }
+
+    pub async fn team_members(
+        &self,
+        project_id: &str,
+        team_id: &str,
+    ) -> Result<Vec<AzureTeamMember>, reqwest::Error> {
+        self.get_paged(&format!(
+            "_apis/projects/{}/teams/{}/members",
+            project_id, team_id
+        ))
+        .await
+    }
+
+    // Ids of work items in the project changed after `since`, in id order. A WIQL query
+    // is capped at 20,000 results, so the ids are read in pages past the last id seen.
+    pub async fn changed_work_item_ids(
+        &self,
+        project_id: &str,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<i64>, reqwest::Error> {
+        let url = format!("{}/{}/_apis/wit/wiql", self.base_url, project_id);
+        let mut ids: Vec<i64> = Vec::new();
+        loop {
+            let mut wiql = String::from(
+                "SELECT [System.Id] FROM WorkItems WHERE [System.TeamProject] = @project",
+            );
+            if let Some(since) = since {
+                wiql.push_str(&format!(
+                    " AND [System.ChangedDate] > '{}'",
+                    since.to_rfc3339_opts(SecondsFormat::Millis, true)
+                ));
+            }
+            if let Some(last) = ids.last() {
+                wiql.push_str(&format!(" AND [System.Id] > {}", last));
+            }
+            wiql.push_str(" ORDER BY [System.Id] ASC");
+
+            let result: WiqlResult = self
+                .request(self.http.post(&url).query(&[
+                    ("timePrecision", "true".to_string()),
+                    ("$top", WIQL_MAX_RESULTS.to_string()),
+                ]))
+                .json(&json!({ "query": wiql }))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            let fetched = result.work_items.len();
+            ids.extend(result.work_items.into_iter().map(|item| item.id));
+            if fetched < WIQL_MAX_RESULTS {
+                return Ok(ids);
+            }
+        }
+    }
+
+    pub async fn work_items(&self, ids: &[i64]) -> Result<Vec<AzureWorkItem>, reqwest::Error> {
+        let mut items = Vec::with_capacity(ids.len());
+        for chunk in ids.chunks(WORK_ITEM_BATCH) {
+            let ids = chunk
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            // errorPolicy=omit returns null in place of deleted or inaccessible items
+            let page: AzureList<Option<AzureWorkItem>> = self
+                .get(
+                    "_apis/wit/workitems",
+                    &[("ids", ids), ("errorPolicy", "omit".to_string())],
+                )
+                .await?
+                .json()
+                .await?;
+            items.extend(page.value.into_iter().flatten());
+        }
+        Ok(items)
+    }
+}
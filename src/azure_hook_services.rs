@@ -0,0 +1,424 @@
+use actix_web::{
+    http::header,
+    post,
+    web::{Data, Payload},
+    HttpRequest, HttpResponse,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    azure_client::{AzureIdentity, AzureProject, AzureWorkItem},
+    azure_sync,
+    error::AppError,
+    model::{ProjectModel, WorkItem},
+    workitems_services::{
+        find_project_by_name, find_user_by_azure_id, find_user_by_email, lock_hierarchy,
+        remove_workitem,
+    },
+    AppState,
+};
+
+const MAX_HOOK_BYTES: usize = 2 * 1024 * 1024;
+// Set under "HTTP headers" of the service hook subscription, as `X-Azure-Hook-Secret: <secret>`
+const SECRET_HEADER: &str = "X-Azure-Hook-Secret";
+// Service hook payloads carry these as "Display Name <email>" strings rather than identity objects
+const IDENTITY_FIELDS: &[&str] = &["System.CreatedBy", "System.AssignedTo"];
+
+/// What Azure DevOps service hooks must present to be accepted: a shared
+/// secret header, basic auth credentials, or either of the two.
+///
+/// Configured through `AZURE_DEVOPS_HOOK_SECRET` and `AZURE_DEVOPS_HOOK_USERNAME` /
+/// `AZURE_DEVOPS_HOOK_PASSWORD`; without any of them the receiver is disabled.
+#[derive(Clone)]
+pub struct HookCredentials {
+    secret: Option<String>,
+    basic: Option<(String, String)>,
+}
+
+impl HookCredentials {
+    pub fn from_env() -> Option<Self> {
+        let secret = std::env::var("AZURE_DEVOPS_HOOK_SECRET")
+            .ok()
+            .filter(|secret| !secret.is_empty());
+        let basic = match (
+            std::env::var("AZURE_DEVOPS_HOOK_USERNAME"),
+            std::env::var("AZURE_DEVOPS_HOOK_PASSWORD"),
+        ) {
+            (Ok(username), Ok(password)) if !password.is_empty() => Some((username, password)),
+            _ => None,
+        };
+        (secret.is_some() || basic.is_some()).then_some(HookCredentials { secret, basic })
+    }
+
+    fn verify(&self, request: &HttpRequest) -> bool {
+        let secret_matches = match (&self.secret, request.headers().get(SECRET_HEADER)) {
+            (Some(secret), Some(given)) => constant_time_eq(secret.as_bytes(), given.as_bytes()),
+            _ => false,
+        };
+        let basic_matches = match (&self.basic, basic_credentials(request)) {
+            (Some((username, password)), Some((given_username, given_password))) => {
+                //both are compared so a wrong username takes as long as a wrong password
+                constant_time_eq(username.as_bytes(), given_username.as_bytes())
+                    & constant_time_eq(password.as_bytes(), given_password.as_bytes())
+            }
+            _ => false,
+        };
+        secret_matches || basic_matches
+    }
+}
+
+fn basic_credentials(request: &HttpRequest) -> Option<(String, String)> {
+    let value = request
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?;
+    let decoded = STANDARD.decode(value.strip_prefix("Basic ")?.trim()).ok()?;
+    let (username, password) = std::str::from_utf8(&decoded).ok()?.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+// Looks at every byte, so the time taken does not tell how much of a secret matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ServiceHookEvent {
+    event_type: String,
+    resource: Value,
+    resource_containers: Option<ResourceContainers>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResourceContainers {
+    project: Option<ResourceContainer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResourceContainer {
+    id: String,
+}
+
+impl ServiceHookEvent {
+    fn project_azure_id(&self) -> Option<&str> {
+        self.resource_containers
+            .as_ref()?
+            .project
+            .as_ref()
+            .map(|project| project.id.as_str())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HookProject {
+    #[serde(alias = "projectId")]
+    id: Option<String>,
+    #[serde(alias = "projectName")]
+    name: Option<String>,
+    description: Option<String>,
+    url: Option<String>,
+}
+
+fn invalid_payload(error: serde_json::Error) -> AppError {
+    AppError::BadRequest(format!("Service hook payload is not valid: {}", error))
+}
+
+// Reads the work item of a created, updated or deleted event into the shape the REST API returns
+fn hook_work_item(mut resource: Value) -> Result<AzureWorkItem, AppError> {
+    if let Some(fields) = resource["fields"].as_object_mut() {
+        for field in IDENTITY_FIELDS {
+            if let Some(Value::String(display)) = fields.get(*field) {
+                let (name, unique_name) = match display.rsplit_once('<') {
+                    Some((name, rest)) => (name.trim(), rest.trim_end_matches('>')),
+                    None => (display.as_str(), display.as_str()),
+                };
+                //no Azure DevOps id is sent, so these users are found by email
+                let identity = json!({"id": "", "displayName": name, "uniqueName": unique_name});
+                fields.insert(field.to_string(), identity);
+            }
+        }
+    }
+    serde_json::from_value(resource).map_err(invalid_payload)
+}
+
+// The lookups of creating a work item: the Azure DevOps id first, then the email
+async fn resolve_user(
+    tx: &mut Transaction<'_, Postgres>,
+    identity: &AzureIdentity,
+) -> Result<Uuid, AppError> {
+    if !identity.id.is_empty() {
+        match find_user_by_azure_id(tx, &identity.id).await {
+            Ok(user) => return Ok(user.id),
+            Err(AppError::Unprocessable(_)) if identity.email().is_some() => {}
+            Err(error) => return Err(error),
+        }
+    }
+    let email = identity.email().ok_or_else(|| {
+        AppError::Unprocessable(format!(
+            "User {} not found",
+            identity.display_name.as_deref().unwrap_or(&identity.id)
+        ))
+    })?;
+    Ok(find_user_by_email(tx, email).await?.id)
+}
+
+async fn resolve_project(
+    tx: &mut Transaction<'_, Postgres>,
+    project_azure_id: Option<&str>,
+    item: &AzureWorkItem,
+) -> Result<Uuid, AppError> {
+    if let Some(azure_id) = project_azure_id {
+        let project_id =
+            sqlx::query_scalar!("SELECT id FROM projects WHERE azure_id = $1", azure_id)
+                .fetch_optional(&mut *tx)
+                .await?;
+        if let Some(project_id) = project_id {
+            return Ok(project_id);
+        }
+    }
+    let name = item.fields.team_project.as_deref().ok_or_else(|| {
+        AppError::Unprocessable(format!("Work item {} has no known project", item.id))
+    })?;
+    Ok(find_project_by_name(tx, name).await?.id)
+}
+
+async fn apply_work_item(
+    tx: &mut Transaction<'_, Postgres>,
+    item: &AzureWorkItem,
+    project_azure_id: Option<&str>,
+) -> Result<(&'static str, Value), AppError> {
+    //before any work item is locked, the parent link below changes the hierarchy
+    lock_hierarchy(tx).await?;
+
+    let stored = sqlx::query_scalar!(
+        "SELECT azure_rev FROM work_items WHERE azure_id = $1 FOR UPDATE",
+        item.id.to_string()
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    //hooks are not guaranteed to arrive in order, and may be delivered twice: a revision
+    //that is not newer than the mirrored one never overwrites it
+    if let (Some(Some(stored)), Some(incoming)) = (stored, item.rev) {
+        if stored >= incoming {
+            return Ok(("ignored", json!({"azure_id": item.id, "reason": "stale"})));
+        }
+    }
+
+    let project_id = resolve_project(tx, project_azure_id, item).await?;
+    let created_by_id = resolve_user(tx, &item.fields.created_by).await?;
+    let assigned_to_id = match &item.fields.assigned_to {
+        Some(identity) => Some(resolve_user(tx, identity).await?),
+        None => None,
+    };
+
+    let upserted =
+        azure_sync::upsert_work_item(tx, item, project_id, created_by_id, assigned_to_id).await?;
+    let Some(workitem_id) = upserted else {
+        return Ok(("ignored", json!({"azure_id": item.id, "reason": "stale"})));
+    };
+    azure_sync::link_parent(tx, item).await?;
+
+    let workitem = sqlx::query_as!(
+        WorkItem,
+        "SELECT * FROM work_items WHERE id = $1",
+        workitem_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let result = match stored {
+        Some(_) => "updated",
+        None => "created",
+    };
+    Ok((result, json!(workitem)))
+}
+
+async fn delete_work_item(
+    tx: &mut Transaction<'_, Postgres>,
+    resource: &Value,
+) -> Result<(&'static str, Value), AppError> {
+    let azure_id = resource["id"]
+        .as_i64()
+        .ok_or_else(|| AppError::BadRequest("Work item payload has no id".to_string()))?;
+
+    //the tombstone keeps late updates of the deleted revisions from bringing it back
+    sqlx::query!(
+        "INSERT INTO deleted_work_items (azure_id, azure_rev) VALUES ($1, $2)
+        ON CONFLICT (azure_id) DO UPDATE
+        SET azure_rev = GREATEST(deleted_work_items.azure_rev, EXCLUDED.azure_rev), deleted_at = NOW()",
+        azure_id.to_string(),
+        resource["rev"].as_i64().and_then(|rev| i32::try_from(rev).ok())
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let workitem = sqlx::query_as!(
+        WorkItem,
+        "SELECT * FROM work_items WHERE azure_id = $1 FOR UPDATE",
+        azure_id.to_string()
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    //a redelivered or never synced deletion has nothing left to do
+    let Some(workitem) = workitem else {
+        return Ok((
+            "ignored",
+            json!({"azure_id": azure_id, "reason": "not_found"}),
+        ));
+    };
+    remove_workitem(tx, None, &workitem).await?;
+
+    Ok(("deleted", json!(workitem)))
+}
+
+async fn apply_work_item_event(
+    tx: &mut Transaction<'_, Postgres>,
+    event: &ServiceHookEvent,
+) -> Result<(&'static str, Value), AppError> {
+    match event.event_type.as_str() {
+        "workitem.created" => {
+            let item = hook_work_item(event.resource.clone())?;
+            apply_work_item(tx, &item, event.project_azure_id()).await
+        }
+        //an update carries the changed fields next to the whole work item as of this revision
+        "workitem.updated" => {
+            let item = hook_work_item(event.resource["revision"].clone())?;
+            apply_work_item(tx, &item, event.project_azure_id()).await
+        }
+        "workitem.deleted" => delete_work_item(tx, &event.resource).await,
+        other => Err(AppError::BadRequest(format!(
+            "Event type {} is not supported",
+            other
+        ))),
+    }
+}
+
+async fn apply_project(
+    tx: &mut Transaction<'_, Postgres>,
+    project: &AzureProject,
+) -> Result<(&'static str, Value), AppError> {
+    let existed = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM projects WHERE azure_id = $1) AS "existed!""#,
+        project.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let project_id = azure_sync::upsert_project(tx, project).await?;
+    let project = sqlx::query_as!(
+        ProjectModel,
+        "SELECT * FROM projects WHERE id = $1",
+        project_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let result = match existed {
+        true => "updated",
+        false => "created",
+    };
+    Ok((result, json!(project)))
+}
+
+async fn fetch_project(
+    data: &AppState,
+    event: &ServiceHookEvent,
+) -> Result<AzureProject, AppError> {
+    let project: HookProject =
+        serde_json::from_value(event.resource.clone()).map_err(invalid_payload)?;
+    let id = project
+        .id
+        .or_else(|| event.project_azure_id().map(String::from))
+        .ok_or_else(|| AppError::BadRequest("Project payload has no id".to_string()))?;
+
+    match project.name {
+        Some(name) => Ok(AzureProject {
+            id,
+            name,
+            description: project.description,
+            url: project.url,
+            default_team: None,
+        }),
+        //some payloads only reference the project, the rest is read from Azure DevOps
+        None => {
+            let client = data.azure.as_ref().ok_or_else(|| {
+                AppError::Unprocessable(format!(
+                    "Project {} has no name in the payload and AZURE_DEVOPS_URL is not set",
+                    id
+                ))
+            })?;
+            client.project(&id).await.map_err(|error| {
+                AppError::ServiceUnavailable(format!(
+                    "Could not read project {} from Azure DevOps: {}",
+                    id, error
+                ))
+            })
+        }
+    }
+}
+
+// Target of Azure DevOps "Web Hooks" service hook subscriptions. It sits outside the
+// bearer token check and authenticates the subscription's secret header or basic auth.
+#[post("/integrations/azure/hooks")]
+async fn receive_azure_hook(
+    request: HttpRequest,
+    payload: Payload,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let credentials = data.azure_hooks.as_ref().ok_or_else(|| {
+        AppError::ServiceUnavailable(
+            "Azure DevOps service hooks are not configured, set AZURE_DEVOPS_HOOK_SECRET"
+                .to_string(),
+        )
+    })?;
+    if !credentials.verify(&request) {
+        return Err(AppError::Unauthorized(
+            "Service hook credentials are not valid".to_string(),
+        ));
+    }
+
+    let body = payload
+        .to_bytes_limited(MAX_HOOK_BYTES)
+        .await
+        .map_err(|_| {
+            AppError::BadRequest(format!(
+                "Service hook body must not exceed {} bytes",
+                MAX_HOOK_BYTES
+            ))
+        })?
+        .map_err(|error| AppError::BadRequest(error.to_string()))?;
+    let event: ServiceHookEvent = serde_json::from_slice(&body).map_err(invalid_payload)?;
+
+    let (result, changed) = match event.event_type.as_str() {
+        "project.created" => {
+            //read before the transaction, it may call Azure DevOps
+            let project = fetch_project(&data, &event).await?;
+            let mut tx = data.db.begin().await?;
+            let applied = apply_project(&mut tx, &project).await?;
+            tx.commit().await?;
+            applied
+        }
+        _ => {
+            let mut tx = data.db.begin().await?;
+            let applied = apply_work_item_event(&mut tx, &event).await?;
+            tx.commit().await?;
+            applied
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "event": event.event_type,
+        "result": result,
+        "data": changed
+    })))
+}
@@ -0,0 +1,647 @@
+use std::{collections::HashSet, fmt, time::Duration};
+
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use sqlx::{Pool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    azure_client::{AzureClient, AzureIdentity, AzureProject, AzureTeam, AzureWorkItem},
+    model::{ProjectModel, SyncRun, WorkItem},
+    projects_services, revision_services,
+    tag_services::normalize_tags,
+    webhooks, workitems_services,
+};
+
+// Key for the advisory lock that keeps sync runs from overlapping across instances
+const SYNC_LOCK_KEY: i64 = 0x5359_4e43;
+
+#[derive(Debug)]
+pub enum SyncError {
+    Azure(reqwest::Error),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Azure(error) => write!(f, "Azure DevOps request failed: {}", error),
+            SyncError::Database(error) => write!(f, "Database error: {}", error),
+        }
+    }
+}
+
+impl From<reqwest::Error> for SyncError {
+    fn from(error: reqwest::Error) -> Self {
+        SyncError::Azure(error)
+    }
+}
+
+impl From<sqlx::Error> for SyncError {
+    fn from(error: sqlx::Error) -> Self {
+        SyncError::Database(error)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SyncStats {
+    pub projects: i32,
+    pub teams: i32,
+    pub users: i32,
+    pub work_items: i32,
+}
+
+// Everything pulled from Azure DevOps for one project before it is written
+struct ProjectSnapshot {
+    project: AzureProject,
+    teams: Vec<(AzureTeam, Vec<AzureIdentity>)>,
+    work_items: Vec<AzureWorkItem>,
+}
+
+/// Starts a sync run in the background and returns its log row, or `None`
+/// when another run is already in progress.
+pub async fn try_start_sync(
+    db: &Pool<Postgres>,
+    client: &AzureClient,
+    full: bool,
+) -> Result<Option<SyncRun>, sqlx::Error> {
+    // The transaction only holds the lock; it is released on commit or drop
+    let mut lock = db.begin().await?;
+    let acquired = sqlx::query_scalar!("SELECT pg_try_advisory_xact_lock($1)", SYNC_LOCK_KEY)
+        .fetch_one(&mut lock)
+        .await?;
+    if acquired != Some(true) {
+        return Ok(None);
+    }
+
+    let run = sqlx::query_as!(
+        SyncRun,
+        "INSERT INTO sync_runs (full_sync) VALUES ($1) RETURNING *",
+        full
+    )
+    .fetch_one(db)
+    .await?;
+
+    let db = db.clone();
+    let client = client.clone();
+    let run_id = run.id;
+    actix_web::rt::spawn(async move {
+        let result = run_sync(&db, &client, full).await;
+        if let Err(error) = finish_run(&db, run_id, &result).await {
+            error!("Failed to record sync run {}: {}", run_id, error);
+        }
+        let _ = lock.commit().await;
+    });
+
+    Ok(Some(run))
+}
+
+/// Runs an incremental sync every `interval`, skipping ticks while a run is active.
+pub fn spawn_periodic_sync(db: Pool<Postgres>, client: AzureClient, interval: Duration) {
+    actix_web::rt::spawn(async move {
+        let mut ticker = actix_web::rt::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match try_start_sync(&db, &client, false).await {
+                Ok(Some(run)) => info!("Started scheduled Azure DevOps sync run {}", run.id),
+                Ok(None) => info!("Skipping scheduled Azure DevOps sync, a run is in progress"),
+                Err(error) => error!("Failed to start scheduled Azure DevOps sync: {}", error),
+            }
+        }
+    });
+}
+
+async fn finish_run(
+    db: &Pool<Postgres>,
+    run_id: i32,
+    result: &Result<SyncStats, SyncError>,
+) -> Result<(), sqlx::Error> {
+    match result {
+        Ok(stats) => {
+            info!("Azure DevOps sync run {} finished: {:?}", run_id, stats);
+            sqlx::query!(
+                "UPDATE sync_runs SET status = 'succeeded', finished_at = NOW(),
+                    projects = $2, teams = $3, users = $4, work_items = $5
+                WHERE id = $1",
+                run_id,
+                stats.projects,
+                stats.teams,
+                stats.users,
+                stats.work_items
+            )
+            .execute(db)
+            .await?;
+        }
+        Err(sync_error) => {
+            error!("Azure DevOps sync run {} failed: {}", run_id, sync_error);
+            sqlx::query!(
+                "UPDATE sync_runs SET status = 'failed', finished_at = NOW(), error = $2 WHERE id = $1",
+                run_id,
+                sync_error.to_string()
+            )
+            .execute(db)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn run_sync(
+    db: &Pool<Postgres>,
+    client: &AzureClient,
+    full: bool,
+) -> Result<SyncStats, SyncError> {
+    let mut stats = SyncStats::default();
+    let mut seen_users = HashSet::new();
+
+    for listed in client.projects().await? {
+        //the project list leaves out the default team
+        let project = client.project(&listed.id).await?;
+        let since = if full {
+            None
+        } else {
+            project_watermark(db, &project.id).await?
+        };
+
+        let mut teams = Vec::new();
+        for team in client.teams(&project.id).await? {
+            let members = client
+                .team_members(&project.id, &team.id)
+                .await?
+                .into_iter()
+                .map(|member| member.identity)
+                .collect();
+            teams.push((team, members));
+        }
+
+        let ids = client.changed_work_item_ids(&project.id, since).await?;
+        let work_items = client.work_items(&ids).await?;
+
+        let snapshot = ProjectSnapshot {
+            project,
+            teams,
+            work_items,
+        };
+
+        // Each project is written atomically so a failure never leaves it half-synced
+        let mut tx = db.begin().await?;
+        workitems_services::lock_hierarchy(&mut tx).await?;
+        write_project(&mut tx, &snapshot, &mut stats, &mut seen_users).await?;
+        tx.commit().await?;
+    }
+
+    stats.users = seen_users.len() as i32;
+    Ok(stats)
+}
+
+async fn project_watermark(
+    db: &Pool<Postgres>,
+    azure_project_id: &str,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let watermark = sqlx::query_scalar!(
+        "SELECT w.last_changed_date FROM sync_watermarks w
+        JOIN projects p ON p.id = w.project_id
+        WHERE p.azure_id = $1",
+        azure_project_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(watermark.map(|date| DateTime::from_naive_utc_and_offset(date, Utc)))
+}
+
+async fn write_project(
+    tx: &mut Transaction<'_, Postgres>,
+    snapshot: &ProjectSnapshot,
+    stats: &mut SyncStats,
+    seen_users: &mut HashSet<String>,
+) -> Result<(), sqlx::Error> {
+    let project_id = upsert_project(tx, &snapshot.project).await?;
+    stats.projects += 1;
+
+    for (team, members) in &snapshot.teams {
+        let team_id = upsert_team(tx, team).await?;
+        let mut member_ids = Vec::with_capacity(members.len());
+        for identity in members {
+            member_ids.push(upsert_identity(tx, identity).await?);
+            seen_users.insert(identity.id.clone());
+        }
+        replace_team_members(tx, team_id, &member_ids).await?;
+        stats.teams += 1;
+    }
+
+    // The project belongs to its default team; a project without one keeps its team
+    if let Some(default_team) = &snapshot.project.default_team {
+        sqlx::query!(
+            "UPDATE projects SET team_id = COALESCE((SELECT id FROM teams WHERE azure_id = $2), team_id)
+            WHERE id = $1",
+            project_id,
+            default_team.id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let mut watermark = None;
+    let mut mirrored = Vec::with_capacity(snapshot.work_items.len());
+    for item in &snapshot.work_items {
+        let fields = &item.fields;
+        let created_by_id = upsert_identity(tx, &fields.created_by).await?;
+        seen_users.insert(fields.created_by.id.clone());
+        let assigned_to_id = match &fields.assigned_to {
+            Some(identity) => {
+                seen_users.insert(identity.id.clone());
+                Some(upsert_identity(tx, identity).await?)
+            }
+            None => None,
+        };
+
+        //a hook may already have mirrored a newer revision
+        if upsert_work_item(tx, item, project_id, created_by_id, assigned_to_id)
+            .await?
+            .is_some()
+        {
+            stats.work_items += 1;
+            mirrored.push(item);
+        }
+        watermark = watermark.max(fields.changed_date);
+    }
+
+    // Parents may arrive in the same batch as their children, so link them last
+    for item in mirrored {
+        link_parent(tx, item).await?;
+    }
+
+    if let Some(watermark) = watermark {
+        sqlx::query!(
+            "INSERT INTO sync_watermarks (project_id, last_changed_date) VALUES ($1, $2)
+            ON CONFLICT (project_id) DO UPDATE
+            SET last_changed_date = GREATEST(sync_watermarks.last_changed_date, EXCLUDED.last_changed_date)",
+            project_id,
+            watermark.naive_utc()
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn upsert_project(
+    tx: &mut Transaction<'_, Postgres>,
+    project: &AzureProject,
+) -> Result<Uuid, sqlx::Error> {
+    let before = sqlx::query_as!(
+        ProjectModel,
+        "SELECT * FROM projects WHERE azure_id = $1 FOR UPDATE",
+        project.id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    match before {
+        Some(before) => {
+            let after = sqlx::query_as!(
+                ProjectModel,
+                "UPDATE projects SET name = $2, description = $3, url = $4 WHERE id = $1 RETURNING *",
+                before.id,
+                project.name,
+                project.description,
+                project.url
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            projects_services::move_project_paths(tx, None, &before, &after).await?;
+            Ok(after.id)
+        }
+        None => {
+            sqlx::query_scalar!(
+                "INSERT INTO projects (azure_id, name, description, url) VALUES ($1,$2,$3,$4) RETURNING id",
+                project.id,
+                project.name,
+                project.description,
+                project.url
+            )
+            .fetch_one(&mut *tx)
+            .await
+        }
+    }
+}
+
+async fn upsert_team(
+    tx: &mut Transaction<'_, Postgres>,
+    team: &AzureTeam,
+) -> Result<Uuid, sqlx::Error> {
+    let updated = sqlx::query_scalar!(
+        "UPDATE teams SET name = $2, description = $3 WHERE azure_id = $1 RETURNING id",
+        team.id,
+        team.name,
+        team.description
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    match updated {
+        Some(id) => Ok(id),
+        None => {
+            sqlx::query_scalar!(
+                "INSERT INTO teams (azure_id, name, description) VALUES ($1,$2,$3) RETURNING id",
+                team.id,
+                team.name,
+                team.description
+            )
+            .fetch_one(&mut *tx)
+            .await
+        }
+    }
+}
+
+// Matches by azure_id first, then claims a manually created user with the same email
+async fn upsert_identity(
+    tx: &mut Transaction<'_, Postgres>,
+    identity: &AzureIdentity,
+) -> Result<Uuid, sqlx::Error> {
+    let updated = sqlx::query_scalar!(
+        "UPDATE users SET name = COALESCE($2, name), email = COALESCE($3, email)
+        WHERE azure_id = $1 RETURNING id",
+        identity.id,
+        identity.display_name,
+        identity.email()
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    if let Some(id) = updated {
+        return Ok(id);
+    }
+
+    if let Some(email) = identity.email() {
+        let claimed = sqlx::query_scalar!(
+            "UPDATE users SET azure_id = $1, name = COALESCE($2, name)
+            WHERE id = (SELECT id FROM users WHERE email = $3 AND azure_id IS NULL LIMIT 1)
+            RETURNING id",
+            identity.id,
+            identity.display_name,
+            email
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(id) = claimed {
+            return Ok(id);
+        }
+    }
+
+    sqlx::query_scalar!(
+        "INSERT INTO users (azure_id, name, email) VALUES ($1,$2,$3) RETURNING id",
+        identity.id,
+        identity.display_name,
+        identity.email()
+    )
+    .fetch_one(&mut *tx)
+    .await
+}
+
+async fn replace_team_members(
+    tx: &mut Transaction<'_, Postgres>,
+    team_id: Uuid,
+    user_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    let removed = sqlx::query_scalar!(
+        "DELETE FROM team_users WHERE team_id = $1 AND NOT (user_id = ANY($2)) RETURNING user_id",
+        team_id,
+        user_ids
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let added = sqlx::query_scalar!(
+        "INSERT INTO team_users (team_id, user_id) SELECT $1, UNNEST($2::uuid[])
+        ON CONFLICT DO NOTHING RETURNING user_id",
+        team_id,
+        user_ids
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    webhooks::enqueue_member_events(tx, None, team_id, &added, &removed).await
+}
+
+/// Mirrors a work item as of its Azure DevOps revision. Returns None, leaving the
+/// work item alone, when a newer revision is stored or Azure DevOps deleted it since.
+pub async fn upsert_work_item(
+    tx: &mut Transaction<'_, Postgres>,
+    item: &AzureWorkItem,
+    project_id: Uuid,
+    created_by_id: Uuid,
+    assigned_to_id: Option<Uuid>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let fields = &item.fields;
+    let azure_id = item.id.to_string();
+    let tags = fields.tag_list().map(|tags| normalize_tags(&tags));
+    let created_date = fields.created_date.map(|date| date.naive_utc());
+    let changed_date = fields.changed_date.map(|date| date.naive_utc());
+
+    //a deleted work item only comes back with a newer revision, e.g. restored from the recycle bin
+    let deleted = sqlx::query_scalar!(
+        "SELECT azure_rev FROM deleted_work_items WHERE azure_id = $1 FOR UPDATE",
+        azure_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    if let Some(deleted_rev) = deleted {
+        match (deleted_rev, item.rev) {
+            (Some(deleted_rev), Some(rev)) if rev > deleted_rev => {
+                sqlx::query!(
+                    "DELETE FROM deleted_work_items WHERE azure_id = $1",
+                    azure_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            _ => return Ok(None),
+        }
+    }
+
+    let before = sqlx::query_as!(
+        WorkItem,
+        "SELECT * FROM work_items WHERE azure_id = $1 FOR UPDATE",
+        azure_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let after = match &before {
+        Some(_) => {
+            let updated = sqlx::query_as!(
+                WorkItem,
+                "UPDATE work_items SET title = $2, w_type = $3, state = $4, project = $5,
+                    assigned_to_id = $6, created_by_id = $7, created_date = $8, changed_date = $9,
+                    priority = $10, severity = $11, description = $12, area_path = $13,
+                    iteration_path = $14, tags = $15, url = $16, azure_rev = $17,
+                    iteration_id = (SELECT id FROM iterations WHERE project_id = $5 AND lower(path) = lower($14::varchar))
+                WHERE azure_id = $1 AND (azure_rev IS NULL OR azure_rev < $17) RETURNING *",
+                azure_id,
+                fields.title,
+                fields.work_item_type,
+                fields.state,
+                project_id,
+                assigned_to_id,
+                created_by_id,
+                created_date,
+                changed_date,
+                fields.priority,
+                fields.severity,
+                fields.description,
+                fields.area_path,
+                fields.iteration_path,
+                tags.as_deref(),
+                item.url,
+                item.rev
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+            match updated {
+                Some(after) => after,
+                //a newer revision is stored
+                None => return Ok(None),
+            }
+        }
+        None => {
+            sqlx::query_as!(
+                WorkItem,
+                "INSERT INTO work_items (azure_id, title, w_type, state, project, assigned_to_id,
+                    created_by_id, created_date, changed_date, priority, severity, description,
+                    area_path, iteration_path, tags, url, azure_rev, iteration_id)
+                VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15,$16,$17,
+                    (SELECT id FROM iterations WHERE project_id = $5 AND lower(path) = lower($14::varchar)))
+                RETURNING *",
+                azure_id,
+                fields.title,
+                fields.work_item_type,
+                fields.state,
+                project_id,
+                assigned_to_id,
+                created_by_id,
+                created_date,
+                changed_date,
+                fields.priority,
+                fields.severity,
+                fields.description,
+                fields.area_path,
+                fields.iteration_path,
+                tags.as_deref(),
+                item.url,
+                item.rev
+            )
+            .fetch_one(&mut *tx)
+            .await?
+        }
+    };
+
+    // Syncs are recorded without a user
+    revision_services::record_and_publish(tx, None, before.as_ref(), Some(&after)).await?;
+    Ok(Some(after.id))
+}
+
+async fn set_parent(
+    tx: &mut Transaction<'_, Postgres>,
+    before: &WorkItem,
+    parent_id: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    let after = sqlx::query_as!(
+        WorkItem,
+        "UPDATE work_items SET parent_id = $2 WHERE id = $1 RETURNING *",
+        before.id,
+        parent_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    revision_services::record_and_publish(tx, None, Some(before), Some(&after)).await
+}
+
+/// Points an upserted work item at its Azure DevOps parent. A parent that is not
+/// synced yet is kept as a pending link and set once it arrives, which also links
+/// the work items already waiting for this one. Links that would close a loop with
+/// the local hierarchy are skipped. The caller holds the hierarchy lock, taken with
+/// [`workitems_services::lock_hierarchy`] before any work item was locked.
+pub async fn link_parent(
+    tx: &mut Transaction<'_, Postgres>,
+    item: &AzureWorkItem,
+) -> Result<(), sqlx::Error> {
+    let before = sqlx::query_as!(
+        WorkItem,
+        "SELECT * FROM work_items WHERE azure_id = $1 FOR UPDATE",
+        item.id.to_string()
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let parent_azure_id = item.fields.parent.map(|parent| parent.to_string());
+    let parent_id = match &parent_azure_id {
+        Some(parent_azure_id) => {
+            sqlx::query_scalar!(
+                "SELECT id FROM work_items WHERE azure_id = $1",
+                parent_azure_id
+            )
+            .fetch_optional(&mut *tx)
+            .await?
+        }
+        None => None,
+    };
+
+    match (&parent_azure_id, parent_id) {
+        (Some(parent_azure_id), None) => {
+            sqlx::query!(
+                "INSERT INTO pending_parent_links (work_item_id, parent_azure_id) VALUES ($1, $2)
+                ON CONFLICT (work_item_id) DO UPDATE SET parent_azure_id = EXCLUDED.parent_azure_id",
+                before.id,
+                parent_azure_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        _ => {
+            sqlx::query!(
+                "DELETE FROM pending_parent_links WHERE work_item_id = $1",
+                before.id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    match parent_id {
+        Some(parent_id) if workitems_services::closes_cycle(tx, before.id, parent_id).await? => {
+            warn!(
+                "Not linking work item {} to parent {}, it would create a cycle",
+                item.id,
+                parent_azure_id.unwrap_or_default()
+            );
+        }
+        parent_id => set_parent(tx, &before, parent_id).await?,
+    }
+
+    let waiting = sqlx::query_scalar!(
+        "DELETE FROM pending_parent_links WHERE parent_azure_id = $1 RETURNING work_item_id",
+        item.id.to_string()
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    for child_id in waiting {
+        let child = sqlx::query_as!(
+            WorkItem,
+            "SELECT * FROM work_items WHERE id = $1 FOR UPDATE",
+            child_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if workitems_services::closes_cycle(tx, child.id, before.id).await? {
+            warn!(
+                "Not linking work item {} to parent {}, it would create a cycle",
+                child.azure_id.as_deref().unwrap_or_default(),
+                item.id
+            );
+            continue;
+        }
+        set_parent(tx, &child, Some(before.id)).await?;
+    }
+    Ok(())
+}
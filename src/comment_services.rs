@@ -0,0 +1,373 @@
+use actix_web::{
+    delete, get, patch, post,
+    web::{Data, Json, Path, Query},
+    HttpResponse,
+};
+use serde_json::json;
+use sqlx::{Executor, Postgres, Transaction};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    auth::CurrentUser,
+    error::AppError,
+    listing::{Listing, Page, SortField},
+    model::{Comment, WorkItem},
+    notification_services,
+    policy::Permission,
+    revision_services,
+    schema::{CommentFilterOptions, CreateCommentRequest, UpdateCommentRequest},
+    AppState,
+};
+
+const COMMENT_SORT_FIELDS: &[SortField] = &[
+    SortField::new("created_at", "c.created_at", "timestamptz"),
+    SortField::new("updated_at", "c.updated_at", "timestamptz").nullable(),
+];
+
+// Handles written as @email or @azure_id, e.g. "@alice@example.com can you check?"
+fn mention_handles(text: &str) -> Vec<String> {
+    let mut handles = Vec::new();
+    let mut previous = ' ';
+    for (index, character) in text.char_indices() {
+        if character == '@' && !(previous.is_alphanumeric() || previous == '@') {
+            let handle: String = text[index + 1..]
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || "._-+@".contains(*c))
+                .collect();
+            // trailing punctuation belongs to the sentence, not the handle
+            let handle = handle.trim_end_matches(['.', '-', '@']).to_lowercase();
+            if !handle.is_empty() && !handles.contains(&handle) {
+                handles.push(handle);
+            }
+        }
+        previous = character;
+    }
+    handles
+}
+
+// Replaces the comment's mentions and returns the users that were not mentioned before
+async fn save_mentions(
+    tx: &mut Transaction<'_, Postgres>,
+    comment_id: Uuid,
+    text: &str,
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    //unknown handles are plain text, not an error
+    let mentioned = sqlx::query_scalar!(
+        "SELECT id FROM users WHERE lower(email) = ANY($1) OR lower(azure_id) = ANY($1)",
+        &mention_handles(text)
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let previous = sqlx::query_scalar!(
+        "DELETE FROM comment_mentions WHERE comment_id = $1 RETURNING user_id",
+        comment_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO comment_mentions (comment_id, user_id) SELECT $1, UNNEST($2::uuid[])",
+        comment_id,
+        &mentioned
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(mentioned
+        .into_iter()
+        .filter(|user_id| !previous.contains(user_id))
+        .collect())
+}
+
+async fn notify_mentions(
+    tx: &mut Transaction<'_, Postgres>,
+    author: &CurrentUser,
+    workitem: &WorkItem,
+    mentioned: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    let author_name = author
+        .0
+        .name
+        .as_deref()
+        .or(author.0.email.as_deref())
+        .unwrap_or("Someone");
+    for user_id in mentioned {
+        notification_services::notify(
+            tx,
+            author.0.id,
+            *user_id,
+            "Mentioned in a comment",
+            &format!(
+                "{} mentioned you on {} '{}'",
+                author_name, workitem.w_type, workitem.title
+            ),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+async fn fetch_comment<'e, E>(executor: E, comment_id: Uuid) -> Result<Comment, AppError>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    sqlx::query_as!(
+        Comment,
+        r#"SELECT c.id, c.work_item_id, c.parent_id, c.author_id, c.text,
+            c.created_at, c.updated_at, c.deleted_at,
+            ARRAY(SELECT m.user_id FROM comment_mentions m WHERE m.comment_id = c.id) AS "mentions!"
+        FROM comments c WHERE c.id = $1"#,
+        comment_id
+    )
+    .fetch_optional(executor)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Comment {} not found", comment_id)))
+}
+
+// The work item is locked so concurrent comments record their revisions one after another
+async fn find_workitem(
+    tx: &mut Transaction<'_, Postgres>,
+    workitem_id: Uuid,
+) -> Result<WorkItem, AppError> {
+    sqlx::query_as!(
+        WorkItem,
+        "SELECT * FROM work_items WHERE id = $1 FOR UPDATE",
+        workitem_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Work item {} not found", workitem_id)))
+}
+
+// Locks the comment's work item and then the comment, the order create_comment takes
+// them in, so an edit and a delete of the same comment see each other's result
+async fn lock_comment(
+    tx: &mut Transaction<'_, Postgres>,
+    comment_id: Uuid,
+) -> Result<(WorkItem, Comment), AppError> {
+    let workitem_id = sqlx::query_scalar!(
+        "SELECT work_item_id FROM comments WHERE id = $1",
+        comment_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Comment {} not found", comment_id)))?;
+    let workitem = find_workitem(tx, workitem_id).await?;
+    sqlx::query!(
+        "SELECT id FROM comments WHERE id = $1 FOR UPDATE",
+        comment_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let comment = fetch_comment(&mut *tx, comment_id).await?;
+    Ok((workitem, comment))
+}
+
+#[post("/workitems/{id}/comments")]
+async fn create_comment(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    body: Json<CreateCommentRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
+    let mut tx = data.db.begin().await?;
+
+    let workitem = find_workitem(&mut tx, path.into_inner()).await?;
+    current
+        .authorize(&data.db, Permission::WriteProject(workitem.project))
+        .await?;
+
+    if let Some(parent_id) = body.parent_id {
+        let parent_workitem =
+            sqlx::query_scalar!("SELECT work_item_id FROM comments WHERE id = $1", parent_id)
+                .fetch_optional(&mut tx)
+                .await?;
+        if parent_workitem != Some(workitem.id) {
+            return Err(AppError::Unprocessable(format!(
+                "Comment {} not found on work item {}",
+                parent_id, workitem.id
+            )));
+        }
+    }
+
+    let comment_id = sqlx::query_scalar!(
+        "INSERT INTO comments (work_item_id, parent_id, author_id, text) VALUES ($1,$2,$3,$4) RETURNING id",
+        workitem.id,
+        body.parent_id,
+        current.0.id,
+        body.text
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    let mentioned = save_mentions(&mut tx, comment_id, &body.text).await?;
+    notify_mentions(&mut tx, &current, &workitem, &mentioned).await?;
+
+    let comment = fetch_comment(&mut tx, comment_id).await?;
+    revision_services::record_comment(&mut tx, current.0.id, &workitem, None, Some(&comment))
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Created().json(json!({"status":"success", "data":comment})))
+}
+
+#[get("/workitems/{id}/comments")]
+async fn get_workitem_comments(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    opts: Query<CommentFilterOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let workitem_id = path.into_inner();
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM work_items WHERE id = $1) AS "exists!""#,
+        workitem_id
+    )
+    .fetch_one(&data.db)
+    .await?;
+    if !exists {
+        return Err(AppError::NotFound(format!(
+            "Work item {} not found",
+            workitem_id
+        )));
+    }
+
+    let mut listing = Listing::new(
+        "c.id, c.work_item_id, c.parent_id, c.author_id, c.text,
+            c.created_at, c.updated_at, c.deleted_at,
+            ARRAY(SELECT m.user_id FROM comment_mentions m WHERE m.comment_id = c.id) AS mentions",
+        "comments c",
+        "c.id",
+        "uuid",
+        COMMENT_SORT_FIELDS,
+        "created_at",
+    );
+    listing
+        .filter("c.work_item_id = $", Some(workitem_id))
+        .filter("c.author_id = $", opts.author_id)
+        .filter("c.parent_id = $", opts.parent_id)
+        .filter("(c.parent_id IS NULL) = $", opts.top_level);
+
+    let page: Page<Comment> = listing
+        .fetch(
+            &data.db,
+            opts.sort.as_deref(),
+            opts.cursor.as_deref(),
+            opts.limit,
+        )
+        .await?;
+
+    Ok(page.into_response("comments"))
+}
+
+#[get("/comments/{id}")]
+async fn get_comment_by_id(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let comment = fetch_comment(&data.db, path.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":comment})))
+}
+
+#[patch("/comments/{id}")]
+async fn update_comment(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    body: Json<UpdateCommentRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
+    let comment_id = path.into_inner();
+
+    let mut tx = data.db.begin().await?;
+
+    let (workitem, before) = lock_comment(&mut tx, comment_id).await?;
+    //comments are edited by their author only
+    current
+        .authorize(&data.db, Permission::ActAsUser(before.author_id))
+        .await?;
+    let deleted = || AppError::Conflict(format!("Comment {} has been deleted", comment_id));
+    if before.deleted_at.is_some() {
+        return Err(deleted());
+    }
+
+    let updated = sqlx::query!(
+        "UPDATE comments SET text = $1, updated_at = NOW() WHERE id = $2 AND deleted_at IS NULL",
+        body.text,
+        comment_id
+    )
+    .execute(&mut tx)
+    .await?;
+    if updated.rows_affected() == 0 {
+        return Err(deleted());
+    }
+
+    let mentioned = save_mentions(&mut tx, comment_id, &body.text).await?;
+    notify_mentions(&mut tx, &current, &workitem, &mentioned).await?;
+
+    let comment = fetch_comment(&mut tx, comment_id).await?;
+    revision_services::record_comment(
+        &mut tx,
+        current.0.id,
+        &workitem,
+        Some(&before),
+        Some(&comment),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":comment})))
+}
+
+#[delete("/comments/{id}")]
+async fn delete_comment(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let comment_id = path.into_inner();
+
+    let mut tx = data.db.begin().await?;
+
+    let (workitem, before) = lock_comment(&mut tx, comment_id).await?;
+    current
+        .authorize(&data.db, Permission::ActAsUser(before.author_id))
+        .await?;
+    if before.deleted_at.is_some() {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+
+    //the text goes, the comment stays as a tombstone holding its replies
+    sqlx::query!(
+        "UPDATE comments SET text = NULL, deleted_at = NOW() WHERE id = $1",
+        comment_id
+    )
+    .execute(&mut tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM comment_mentions WHERE comment_id = $1",
+        comment_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    revision_services::record_comment(&mut tx, current.0.id, &workitem, Some(&before), None)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,196 @@
+use std::fmt;
+
+use actix_web::{
+    http::{header, StatusCode},
+    HttpResponse, ResponseError,
+};
+use log::error;
+use serde_json::{json, Map, Value};
+use validator::ValidationErrors;
+
+// Postgres SQLSTATE codes we translate into client errors
+const UNIQUE_VIOLATION: &str = "23505";
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+const NOT_NULL_VIOLATION: &str = "23502";
+const CHECK_VIOLATION: &str = "23514";
+// Class 22 covers data exceptions such as invalid text representation or overflow
+const DATA_EXCEPTION_CLASS: &str = "22";
+
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    Unauthorized(String),
+    Forbidden(String),
+    BadRequest(String),
+    Validation(String),
+    // Field-level failures from validating a request body
+    InvalidFields(ValidationErrors),
+    Conflict(String),
+    Unprocessable(String),
+    ServiceUnavailable(String),
+    Internal(String),
+}
+
+impl AppError {
+    /// Stable machine-readable code returned in the `code` field of error bodies.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Validation(_) | AppError::InvalidFields(_) => "validation_failed",
+            AppError::Conflict(_) => "conflict",
+            AppError::Unprocessable(_) => "unprocessable_entity",
+            AppError::ServiceUnavailable(_) => "service_unavailable",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            AppError::NotFound(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::BadRequest(message)
+            | AppError::Validation(message)
+            | AppError::Conflict(message)
+            | AppError::Unprocessable(message)
+            | AppError::ServiceUnavailable(message)
+            | AppError::Internal(message) => message,
+            AppError::InvalidFields(_) => "One or more fields are invalid",
+        }
+    }
+
+    /// The `code`, `message` and field `errors` of the error body, for reporting
+    /// an error inside a successful response.
+    pub fn details(&self) -> Value {
+        let mut details = json!({
+            "code": self.code(),
+            "message": self.message()
+        });
+        if let AppError::InvalidFields(errors) = self {
+            let mut fields = Map::new();
+            Self::field_errors(errors, "", &mut fields);
+            details["errors"] = Value::Object(fields);
+        }
+        details
+    }
+
+    // Flattens validator errors into {field: [{code, message}]}, using dotted paths for nested structs
+    fn field_errors(errors: &ValidationErrors, prefix: &str, out: &mut Map<String, Value>) {
+        for (field, kind) in errors.errors() {
+            let path = if prefix.is_empty() {
+                field.to_string()
+            } else {
+                format!("{}.{}", prefix, field)
+            };
+            match kind {
+                validator::ValidationErrorsKind::Field(field_errors) => {
+                    let entries = field_errors
+                        .iter()
+                        .map(|error| {
+                            json!({
+                                "code": error.code,
+                                "message": error
+                                    .message
+                                    .clone()
+                                    .unwrap_or_else(|| format!("failed the {} check", error.code).into())
+                            })
+                        })
+                        .collect();
+                    out.insert(path, Value::Array(entries));
+                }
+                validator::ValidationErrorsKind::Struct(nested) => {
+                    Self::field_errors(nested, &path, out);
+                }
+                validator::ValidationErrorsKind::List(items) => {
+                    for (index, nested) in items {
+                        Self::field_errors(nested, &format!("{}[{}]", path, index), out);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.message())
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::BadRequest(_) | AppError::Validation(_) | AppError::InvalidFields(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut body = json!({"status": "error"});
+        if let (Value::Object(body), Value::Object(details)) = (&mut body, self.details()) {
+            body.extend(details);
+        }
+        let mut response = HttpResponse::build(self.status_code());
+        if let AppError::Unauthorized(_) = self {
+            response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+        }
+        response.json(body)
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(errors: ValidationErrors) -> Self {
+        AppError::InvalidFields(errors)
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(error: sqlx::Error) -> Self {
+        if let sqlx::Error::RowNotFound = error {
+            return AppError::NotFound("Resource not found".to_string());
+        }
+
+        if let sqlx::Error::Database(db_error) = &error {
+            match db_error.code().as_deref() {
+                Some(UNIQUE_VIOLATION) => {
+                    return AppError::Conflict(
+                        "A record with the same unique value already exists".to_string(),
+                    );
+                }
+                Some(FOREIGN_KEY_VIOLATION) => {
+                    return AppError::Unprocessable(
+                        "The request references a record that does not exist or is still referenced"
+                            .to_string(),
+                    );
+                }
+                Some(NOT_NULL_VIOLATION) | Some(CHECK_VIOLATION) => {
+                    return AppError::Validation(
+                        "The request is missing a required value or contains an invalid one"
+                            .to_string(),
+                    );
+                }
+                Some(code) if code.starts_with(DATA_EXCEPTION_CLASS) => {
+                    return AppError::Validation(
+                        "The request contains a value of the wrong type or format".to_string(),
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        // Anything else is a server fault; keep the details in the log only
+        error!("Database error: {:?}", error);
+        AppError::Internal("An unexpected database error occurred".to_string())
+    }
+}
@@ -0,0 +1,405 @@
+use std::io;
+
+use actix_web::{
+    get,
+    http::header::ContentDisposition,
+    web::{Bytes, Data, Query},
+    HttpResponse,
+};
+use futures::{channel::mpsc, SinkExt, Stream, TryStreamExt};
+use log::error;
+use rust_xlsxwriter::{Format, Workbook, XlsxError};
+use serde_json::{Map, Value};
+use sqlx::{Pool, Postgres, QueryBuilder};
+use validator::Validate;
+
+use crate::{
+    auth::CurrentUser,
+    error::AppError,
+    listing::Listing,
+    policy::Permission,
+    projects_services::project_listing,
+    schema::{
+        ExportOptions, ProjectFilterOptions, TeamFilterOptions, UserFilterOptions,
+        WorkItemFilterOptions,
+    },
+    team_services::team_listing,
+    user_services::user_listing,
+    workitems_services::workitem_listing,
+    AppState,
+};
+
+// rows are sent to the client in chunks of about this size
+const CHUNK_BYTES: usize = 64 * 1024;
+// an xlsx file is a zip archive that is only readable once complete, so unlike csv and
+// jsonl it is built in memory before it is sent
+const MAX_XLSX_ROWS: u32 = 100_000;
+// Excel refuses cells with more characters than this; longer text is cut and marked
+const MAX_XLSX_CELL_CHARS: usize = 32_767;
+const TRUNCATED_MARKER: &str = "... [truncated]";
+
+/// What one export endpoint writes: the file name, the alias of the listed
+/// table and the columns, in order.
+struct Export {
+    name: &'static str,
+    table: &'static str,
+    columns: &'static [&'static str],
+}
+
+const WORKITEM_EXPORT: Export = Export {
+    name: "workitems",
+    table: "work_items",
+    columns: &[
+        "id",
+        "azure_id",
+        "title",
+        "w_type",
+        "state",
+        "priority",
+        "severity",
+        "project",
+        "project_name",
+        "area_path",
+        "iteration_path",
+        "iteration_id",
+        "parent_id",
+        "assigned_to_id",
+        "assigned_to_name",
+        "assigned_to_email",
+        "created_by_id",
+        "created_by_name",
+        "created_by_email",
+        "created_date",
+        "changed_date",
+        "tags",
+        "url",
+        "description",
+    ],
+};
+
+const PROJECT_EXPORT: Export = Export {
+    name: "projects",
+    table: "projects",
+    columns: &[
+        "id",
+        "azure_id",
+        "name",
+        "description",
+        "template",
+        "team_id",
+        "team_name",
+        "begin_date",
+        "end_date",
+        "url",
+    ],
+};
+
+const TEAM_EXPORT: Export = Export {
+    name: "teams",
+    table: "teams",
+    columns: &[
+        "id",
+        "azure_id",
+        "name",
+        "description",
+        "member_count",
+        "members",
+    ],
+};
+
+const USER_EXPORT: Export = Export {
+    name: "users",
+    table: "users",
+    columns: &["id", "azure_id", "name", "email", "role", "teams"],
+};
+
+// The listed tables with the names of the rows they reference, aliased like the
+// table so the list filters apply unchanged
+const WORKITEM_SOURCE: &str = "(SELECT w.*, p.name AS project_name,
+        a.name AS assigned_to_name, a.email AS assigned_to_email,
+        c.name AS created_by_name, c.email AS created_by_email
+    FROM work_items w
+        JOIN projects p ON p.id = w.project
+        LEFT JOIN users a ON a.id = w.assigned_to_id
+        LEFT JOIN users c ON c.id = w.created_by_id) work_items";
+
+const PROJECT_SOURCE: &str = "(SELECT p.*, t.name AS team_name
+    FROM projects p LEFT JOIN teams t ON t.id = p.team_id) projects";
+
+const TEAM_SOURCE: &str = "(SELECT t.*,
+        (SELECT COUNT(*) FROM team_users tu WHERE tu.team_id = t.id) AS member_count,
+        ARRAY(SELECT u.name FROM team_users tu JOIN users u ON u.id = tu.user_id
+            WHERE tu.team_id = t.id ORDER BY u.name) AS members
+    FROM teams t) teams";
+
+const USER_SOURCE: &str = "(SELECT u.*,
+        ARRAY(SELECT t.name FROM team_users tu JOIN teams t ON t.id = tu.team_id
+            WHERE tu.user_id = u.id ORDER BY t.name) AS teams
+    FROM users u) users";
+
+// Spreadsheet cell text. Lists such as tags are joined with semicolons, as the import reads them
+fn cell_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items.iter().map(cell_text).collect::<Vec<_>>().join(";"),
+        other => other.to_string(),
+    }
+}
+
+fn xlsx_cell_text(value: &Value) -> String {
+    let text = cell_text(value);
+    if text.chars().count() <= MAX_XLSX_CELL_CHARS {
+        return text;
+    }
+    let mut truncated: String = text
+        .chars()
+        .take(MAX_XLSX_CELL_CHARS - TRUNCATED_MARKER.len())
+        .collect();
+    truncated.push_str(TRUNCATED_MARKER);
+    truncated
+}
+
+/// Encodes rows for the streamed formats into a buffer that is taken chunk by chunk.
+enum RowWriter {
+    Csv(Box<csv::Writer<Vec<u8>>>),
+    Jsonl(Vec<u8>),
+}
+
+impl RowWriter {
+    fn new(format: &str) -> Self {
+        match format {
+            "jsonl" => RowWriter::Jsonl(Vec::new()),
+            _ => RowWriter::Csv(Box::new(csv::Writer::from_writer(Vec::new()))),
+        }
+    }
+
+    fn write_header(&mut self, columns: &[&str]) -> io::Result<()> {
+        if let RowWriter::Csv(writer) = self {
+            writer.write_record(columns)?;
+        }
+        Ok(())
+    }
+
+    fn write_row(&mut self, columns: &[&str], row: &Value) -> io::Result<()> {
+        match self {
+            RowWriter::Csv(writer) => {
+                writer.write_record(columns.iter().map(|column| cell_text(&row[*column])))?;
+            }
+            RowWriter::Jsonl(buffer) => {
+                let object: Map<String, Value> = columns
+                    .iter()
+                    .map(|column| (column.to_string(), row[*column].clone()))
+                    .collect();
+                serde_json::to_writer(&mut *buffer, &object)?;
+                buffer.push(b'\n');
+            }
+        }
+        Ok(())
+    }
+
+    fn take(&mut self) -> io::Result<Bytes> {
+        let buffer = match self {
+            RowWriter::Csv(writer) => {
+                std::mem::replace(&mut **writer, csv::Writer::from_writer(Vec::new()))
+                    .into_inner()
+                    .map_err(|error| error.into_error())?
+            }
+            RowWriter::Jsonl(buffer) => std::mem::take(buffer),
+        };
+        Ok(Bytes::from(buffer))
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            RowWriter::Csv(writer) => writer.get_ref().len(),
+            RowWriter::Jsonl(buffer) => buffer.len(),
+        }
+    }
+}
+
+// Sends the rows as they are read, stopping early once the client has gone away
+async fn send_rows(
+    db: &Pool<Postgres>,
+    query: &mut QueryBuilder<'static, Postgres>,
+    columns: &[&str],
+    mut writer: RowWriter,
+    sender: &mut mpsc::Sender<io::Result<Bytes>>,
+) -> io::Result<()> {
+    writer.write_header(columns)?;
+    let mut rows = query.build_query_as::<(Value,)>().fetch(db);
+    while let Some((row,)) = rows.try_next().await.map_err(io::Error::other)? {
+        writer.write_row(columns, &row)?;
+        if writer.len() >= CHUNK_BYTES && sender.send(Ok(writer.take()?)).await.is_err() {
+            return Ok(());
+        }
+    }
+    let _ = sender.send(Ok(writer.take()?)).await;
+    Ok(())
+}
+
+fn stream_rows(
+    db: Pool<Postgres>,
+    mut query: QueryBuilder<'static, Postgres>,
+    columns: &'static [&'static str],
+    writer: RowWriter,
+) -> impl Stream<Item = io::Result<Bytes>> {
+    let (mut sender, receiver) = mpsc::channel(4);
+    actix_web::rt::spawn(async move {
+        if let Err(err) = send_rows(&db, &mut query, columns, writer, &mut sender).await {
+            error!("Export failed: {}", err);
+            //the status is already sent, failing the body tells the client the file is incomplete
+            let _ = sender.send(Err(err)).await;
+        }
+    });
+    receiver
+}
+
+fn xlsx_error(error: XlsxError) -> AppError {
+    AppError::Internal(format!("Could not write xlsx: {}", error))
+}
+
+async fn build_workbook(
+    db: &Pool<Postgres>,
+    mut query: QueryBuilder<'static, Postgres>,
+    columns: &[&str],
+) -> Result<Vec<u8>, AppError> {
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet();
+    let bold = Format::new().set_bold();
+    for (index, column) in columns.iter().enumerate() {
+        worksheet
+            .write_string_with_format(0, index as u16, *column, &bold)
+            .map_err(xlsx_error)?;
+    }
+    worksheet.set_freeze_panes(1, 0).map_err(xlsx_error)?;
+
+    let mut rows = query.build_query_as::<(Value,)>().fetch(db);
+    let mut line = 0;
+    while let Some((row,)) = rows.try_next().await? {
+        line += 1;
+        if line > MAX_XLSX_ROWS {
+            return Err(AppError::BadRequest(format!(
+                "xlsx exports are limited to {} rows, narrow the filters or use csv or jsonl",
+                MAX_XLSX_ROWS
+            )));
+        }
+        for (index, column) in columns.iter().enumerate() {
+            match &row[*column] {
+                Value::Null => continue,
+                Value::Number(number) => {
+                    worksheet.write_number(line, index as u16, number.as_f64().unwrap_or_default())
+                }
+                value => worksheet.write_string(line, index as u16, xlsx_cell_text(value)),
+            }
+            .map_err(xlsx_error)?;
+        }
+    }
+
+    workbook.save_to_buffer().map_err(xlsx_error)
+}
+
+async fn export_response(
+    data: &AppState,
+    listing: Listing,
+    sort: Option<&str>,
+    opts: &ExportOptions,
+    export: &Export,
+) -> Result<HttpResponse, AppError> {
+    let format = opts.format.as_deref().unwrap_or("csv");
+    //every row as a JSON object, so one writer serves all entities
+    let query = listing.query_all(&format!("to_jsonb({})", export.table), sort)?;
+
+    let mut response = HttpResponse::Ok();
+    response.insert_header(ContentDisposition::attachment(format!(
+        "{}.{}",
+        export.name, format
+    )));
+    if format == "xlsx" {
+        let workbook = build_workbook(&data.db, query, export.columns).await?;
+        return Ok(response
+            .content_type("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")
+            .body(workbook));
+    }
+
+    let content_type = match format {
+        "jsonl" => "application/x-ndjson",
+        _ => "text/csv; charset=utf-8",
+    };
+    Ok(response.content_type(content_type).streaming(stream_rows(
+        data.db.clone(),
+        query,
+        export.columns,
+        RowWriter::new(format),
+    )))
+}
+
+#[get("/export/workitems")]
+async fn export_workitems(
+    current: CurrentUser,
+    filters: Query<WorkItemFilterOptions>,
+    opts: Query<ExportOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    opts.validate()?;
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let listing = workitem_listing(WORKITEM_SOURCE, &filters);
+    export_response(
+        &data,
+        listing,
+        filters.sort.as_deref(),
+        &opts,
+        &WORKITEM_EXPORT,
+    )
+    .await
+}
+
+#[get("/export/projects")]
+async fn export_projects(
+    current: CurrentUser,
+    filters: Query<ProjectFilterOptions>,
+    opts: Query<ExportOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    opts.validate()?;
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let listing = project_listing(PROJECT_SOURCE, &filters);
+    export_response(
+        &data,
+        listing,
+        filters.sort.as_deref(),
+        &opts,
+        &PROJECT_EXPORT,
+    )
+    .await
+}
+
+#[get("/export/teams")]
+async fn export_teams(
+    current: CurrentUser,
+    filters: Query<TeamFilterOptions>,
+    opts: Query<ExportOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    opts.validate()?;
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let listing = team_listing(TEAM_SOURCE, &filters);
+    export_response(&data, listing, filters.sort.as_deref(), &opts, &TEAM_EXPORT).await
+}
+
+#[get("/export/users")]
+async fn export_users(
+    current: CurrentUser,
+    filters: Query<UserFilterOptions>,
+    opts: Query<ExportOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    opts.validate()?;
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let listing = user_listing(USER_SOURCE, &filters);
+    export_response(&data, listing, filters.sort.as_deref(), &opts, &USER_EXPORT).await
+}
@@ -0,0 +1,224 @@
+use actix_web::{
+    http::StatusCode,
+    post,
+    web::{Data, Payload, Query},
+    HttpMessage, HttpRequest, HttpResponse,
+};
+use serde_json::{json, Map, Value};
+use sqlx::{Connection, Pool, Postgres, Transaction};
+use validator::Validate;
+
+use crate::{
+    auth::CurrentUser,
+    error::AppError,
+    model::WorkItem,
+    policy::Permission,
+    schema::{CreateWorkItemRequest, ImportOptions},
+    workitems_services::insert_workitem,
+    AppState,
+};
+
+const MAX_IMPORT_BYTES: usize = 10 * 1024 * 1024;
+const MAX_IMPORT_ROWS: usize = 5000;
+
+// CSV cells are text, these columns are converted to the JSON types the request expects
+const CSV_NUMBER_COLUMNS: &[&str] = &["priority"];
+const CSV_LIST_COLUMNS: &[&str] = &["tags"];
+
+// One object per data row keyed by the header row. Empty cells are left out and
+// tags are separated by semicolons as in Azure DevOps. A record that cannot be read
+// fails only its own row.
+fn parse_csv(body: &[u8]) -> Result<Vec<Result<Value, AppError>>, AppError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(body);
+    let headers = reader
+        .headers()
+        .map_err(|error| AppError::BadRequest(format!("CSV header is not valid: {}", error)))?
+        .clone();
+
+    let rows = reader
+        .records()
+        .map(|record| {
+            let record = record.map_err(|error| {
+                AppError::BadRequest(format!("CSV record is not valid: {}", error))
+            })?;
+            //trailing empty cells may be left out, cells without a column may not
+            if record.len() > headers.len() {
+                return Err(AppError::BadRequest(format!(
+                    "CSV record has {} fields, the header has {}",
+                    record.len(),
+                    headers.len()
+                )));
+            }
+            let mut row = Map::new();
+            for (column, cell) in headers.iter().zip(record.iter()) {
+                if cell.is_empty() {
+                    continue;
+                }
+                let value = if CSV_LIST_COLUMNS.contains(&column) {
+                    Value::from(cell.split(';').map(str::trim).collect::<Vec<_>>())
+                } else if CSV_NUMBER_COLUMNS.contains(&column) {
+                    // anything but a number fails the row's own deserialization
+                    cell.parse::<i64>()
+                        .map(Value::from)
+                        .unwrap_or_else(|_| Value::from(cell))
+                } else {
+                    Value::from(cell)
+                };
+                row.insert(column.to_string(), value);
+            }
+            Ok(Value::Object(row))
+        })
+        .collect();
+    Ok(rows)
+}
+
+fn parse_json(body: &[u8]) -> Result<Vec<Value>, AppError> {
+    serde_json::from_slice(body).map_err(|error| {
+        AppError::BadRequest(format!(
+            "Body must be a JSON array of work items: {}",
+            error
+        ))
+    })
+}
+
+// Creates one row inside a savepoint, so a failed row leaves the others untouched
+async fn import_row(
+    tx: &mut Transaction<'_, Postgres>,
+    current: &CurrentUser,
+    db: &Pool<Postgres>,
+    row: Value,
+) -> Result<WorkItem, AppError> {
+    let body: CreateWorkItemRequest =
+        serde_json::from_value(row).map_err(|error| AppError::BadRequest(error.to_string()))?;
+    body.validate()?;
+
+    let mut savepoint = tx.begin().await?;
+    match insert_workitem(&mut savepoint, current, db, &body).await {
+        Ok(workitem) => {
+            savepoint.commit().await?;
+            Ok(workitem)
+        }
+        Err(error) => {
+            savepoint.rollback().await?;
+            Err(error)
+        }
+    }
+}
+
+#[post("/workitems/import")]
+async fn import_workitems(
+    current: CurrentUser,
+    request: HttpRequest,
+    payload: Payload,
+    opts: Query<ImportOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    opts.validate()?;
+    //each row is checked against its own project when it is created
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let body = payload
+        .to_bytes_limited(MAX_IMPORT_BYTES)
+        .await
+        .map_err(|_| {
+            AppError::BadRequest(format!(
+                "Import body must not exceed {} bytes",
+                MAX_IMPORT_BYTES
+            ))
+        })?
+        .map_err(|error| AppError::BadRequest(error.to_string()))?;
+
+    let rows = match request.content_type() {
+        "text/csv" => parse_csv(&body)?,
+        "application/json" => parse_json(&body)?.into_iter().map(Ok).collect(),
+        other => {
+            return Err(AppError::BadRequest(format!(
+                "Content-Type {} is not supported, expected text/csv or application/json",
+                other
+            )))
+        }
+    };
+    if rows.is_empty() || rows.len() > MAX_IMPORT_ROWS {
+        return Err(AppError::BadRequest(format!(
+            "Import must contain between 1 and {} rows",
+            MAX_IMPORT_ROWS
+        )));
+    }
+
+    let dry_run = opts.dry_run.unwrap_or(false);
+    //without a batch size the import is all or nothing. A dry run keeps everything in one
+    //transaction, so rows can refer to the rows before them as they would when imported.
+    let batch_size = match opts.batch_size {
+        Some(batch_size) if !dry_run => batch_size,
+        _ => rows.len(),
+    };
+
+    let mut report = Vec::with_capacity(rows.len());
+    let (mut imported, mut failed) = (0, 0);
+    let mut rows = rows.into_iter().enumerate().peekable();
+    while rows.peek().is_some() {
+        let mut tx = data.db.begin().await?;
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut batch_failed = false;
+
+        for (index, row) in rows.by_ref().take(batch_size) {
+            let result = match row {
+                Ok(row) => import_row(&mut tx, &current, &data.db, row).await,
+                Err(error) => Err(error),
+            };
+            match result {
+                Ok(workitem) => batch.push(json!({
+                    "row": index + 1,
+                    "status": if dry_run { "valid" } else { "created" },
+                    "id": (!dry_run).then_some(workitem.id),
+                    "azure_id": workitem.azure_id
+                })),
+                Err(error) => {
+                    batch_failed = true;
+                    failed += 1;
+                    batch.push(json!({
+                        "row": index + 1,
+                        "status": "error",
+                        "error": error.details()
+                    }));
+                }
+            }
+        }
+
+        if dry_run || batch_failed {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        for entry in &mut batch {
+            if entry["status"] == "error" {
+                continue;
+            }
+            //valid rows of a failed batch were not kept
+            if batch_failed && !dry_run {
+                entry["status"] = json!("rolled_back");
+                entry["id"] = Value::Null;
+            } else {
+                imported += 1;
+            }
+        }
+        report.append(&mut batch);
+    }
+
+    let status = if failed == 0 {
+        StatusCode::OK
+    } else {
+        StatusCode::UNPROCESSABLE_ENTITY
+    };
+    Ok(HttpResponse::build(status).json(json!({
+        "status": if failed == 0 { "success" } else { "error" },
+        "dry_run": dry_run,
+        "total": report.len(),
+        "imported": imported,
+        "failed": failed,
+        "rows": report
+    })))
+}
@@ -0,0 +1,537 @@
+use actix_web::{
+    delete, get, patch, post,
+    web::{Data, Json, Path, Query},
+    HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    auth::CurrentUser,
+    error::AppError,
+    listing::{contains_pattern, Listing, Page, SortField},
+    model::{Iteration, IterationResponse, ProjectModel, WorkItem},
+    policy::Permission,
+    projects_services::manage_permission,
+    revision_services,
+    schema::{
+        CarryOverRequest, CreateIterationRequest, IterationFilterOptions, UpdateIterationRequest,
+    },
+    AppState,
+};
+
+const ITERATION_SORT_FIELDS: &[SortField] = &[
+    SortField::new("name", "name", "varchar"),
+    SortField::new("path", "path", "varchar"),
+    SortField::new("start_date", "start_date", "timestamptz").nullable(),
+    SortField::new("end_date", "end_date", "timestamptz").nullable(),
+];
+
+// Same rule as Iteration::state, for filtering in SQL
+const TIMEFRAME_CONDITION: &str = "(CASE
+        WHEN start_date IS NULL OR end_date IS NULL THEN 'unscheduled'
+        WHEN NOW() < start_date THEN 'future'
+        WHEN NOW() > end_date THEN 'past'
+        ELSE 'current'
+    END) = $";
+const TIMEFRAMES: &[&str] = &["current", "past", "future"];
+
+impl Iteration {
+    pub fn state(&self, now: DateTime<Utc>) -> &'static str {
+        match (self.start_date, self.end_date) {
+            (Some(start), Some(_)) if now < start => "future",
+            (Some(_), Some(end)) if now > end => "past",
+            (Some(_), Some(_)) => "current",
+            _ => "unscheduled",
+        }
+    }
+}
+
+impl From<Iteration> for IterationResponse {
+    fn from(iteration: Iteration) -> Self {
+        IterationResponse {
+            state: iteration.state(Utc::now()).to_string(),
+            iteration,
+        }
+    }
+}
+
+fn check_date_range(
+    start_date: Option<DateTime<Utc>>,
+    end_date: Option<DateTime<Utc>>,
+) -> Result<(), AppError> {
+    match (start_date, end_date) {
+        (Some(start), Some(end)) if end < start => Err(AppError::Validation(
+            "end_date must not be before start_date".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+async fn find_project(
+    tx: &mut Transaction<'_, Postgres>,
+    project_id: Uuid,
+) -> Result<ProjectModel, AppError> {
+    sqlx::query_as!(
+        ProjectModel,
+        "SELECT * FROM projects WHERE id = $1",
+        project_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Project {} not found", project_id)))
+}
+
+// Locks the iteration, so an update or delete works from its current row
+async fn find_iteration(
+    tx: &mut Transaction<'_, Postgres>,
+    iteration_id: Uuid,
+) -> Result<Iteration, AppError> {
+    sqlx::query_as!(
+        Iteration,
+        "SELECT * FROM iterations WHERE id = $1 FOR UPDATE",
+        iteration_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Iteration {} not found", iteration_id)))
+}
+
+// Points the project's work items at the iteration: those already linked follow a
+// path change, those carrying its path as text get linked.
+async fn link_workitems(
+    tx: &mut Transaction<'_, Postgres>,
+    changed_by: Uuid,
+    iteration: &Iteration,
+) -> Result<(), sqlx::Error> {
+    let workitems = sqlx::query_as!(
+        WorkItem,
+        "SELECT * FROM work_items
+        WHERE project = $1
+            AND (iteration_id = $2 OR (iteration_id IS NULL AND lower(iteration_path) = lower($3)))
+            AND (iteration_id IS DISTINCT FROM $2 OR iteration_path IS DISTINCT FROM $3)
+        FOR UPDATE",
+        iteration.project_id,
+        iteration.id,
+        iteration.path
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for before in &workitems {
+        let after = sqlx::query_as!(
+            WorkItem,
+            "UPDATE work_items SET iteration_id = $1, iteration_path = $2, changed_date = NOW()
+            WHERE id = $3 RETURNING *",
+            iteration.id,
+            iteration.path,
+            before.id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        revision_services::record_and_publish(tx, Some(changed_by), Some(before), Some(&after))
+            .await?;
+    }
+    Ok(())
+}
+
+// Rewrites the iterations under a path prefix and the iteration paths of the work
+// items, for a project renamed under them
+pub async fn move_paths(
+    tx: &mut Transaction<'_, Postgres>,
+    changed_by: Option<Uuid>,
+    project_id: Uuid,
+    old_path: &str,
+    new_path: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r"UPDATE iterations SET path = $3 || substr(path, length($2) + 1)
+        WHERE project_id = $1
+            AND (lower(path) = lower($2) OR starts_with(lower(path), lower($2) || ''))",
+        project_id,
+        old_path,
+        new_path
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let workitems = sqlx::query_as!(
+        WorkItem,
+        r"SELECT * FROM work_items
+        WHERE project = $1
+            AND (lower(iteration_path) = lower($2) OR starts_with(lower(iteration_path), lower($2) || ''))
+        FOR UPDATE",
+        project_id,
+        old_path
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for before in &workitems {
+        let after = sqlx::query_as!(
+            WorkItem,
+            "UPDATE work_items SET iteration_path = $1 || substr(iteration_path, length($2) + 1),
+                changed_date = NOW()
+            WHERE id = $3 RETURNING *",
+            new_path,
+            old_path,
+            before.id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        revision_services::record_and_publish(tx, changed_by, Some(before), Some(&after)).await?;
+    }
+    Ok(())
+}
+
+#[post("/projects/{id}/iterations")]
+async fn create_iteration(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    body: Json<CreateIterationRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+    check_date_range(body.start_date, body.end_date)?;
+
+    let mut tx = data.db.begin().await?;
+
+    let project = find_project(&mut tx, path.into_inner()).await?;
+    current
+        .authorize(&data.db, manage_permission(project.team_id))
+        .await?;
+
+    let iteration_path = match &body.path {
+        Some(path) => path.clone(),
+        None => format!(
+            "{}\\{}",
+            project.name.as_deref().unwrap_or_default(),
+            body.name
+        ),
+    };
+
+    let iteration = sqlx::query_as!(
+        Iteration,
+        "INSERT INTO iterations (project_id, azure_id, name, path, start_date, end_date)
+        VALUES ($1,$2,$3,$4,$5,$6) RETURNING *",
+        project.id,
+        body.azure_id,
+        body.name,
+        iteration_path,
+        body.start_date,
+        body.end_date
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    link_workitems(&mut tx, current.0.id, &iteration).await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Created()
+        .json(json!({"status":"success", "data":IterationResponse::from(iteration)})))
+}
+
+async fn list_iterations(
+    data: &AppState,
+    project_id: Uuid,
+    timeframe: Option<String>,
+    opts: &IterationFilterOptions,
+) -> Result<HttpResponse, AppError> {
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM projects WHERE id = $1) AS "exists!""#,
+        project_id
+    )
+    .fetch_one(&data.db)
+    .await?;
+    if !exists {
+        return Err(AppError::NotFound(format!(
+            "Project {} not found",
+            project_id
+        )));
+    }
+
+    let mut listing = Listing::new(
+        "*",
+        "iterations",
+        "id",
+        "uuid",
+        ITERATION_SORT_FIELDS,
+        "start_date",
+    );
+    listing
+        .filter("project_id = $", Some(project_id))
+        .filter("name ILIKE $", opts.name.as_deref().map(contains_pattern))
+        .filter(TIMEFRAME_CONDITION, timeframe);
+
+    let page: Page<Iteration> = listing
+        .fetch(
+            &data.db,
+            opts.sort.as_deref(),
+            opts.cursor.as_deref(),
+            opts.limit,
+        )
+        .await?;
+
+    Ok(page
+        .map(IterationResponse::from)
+        .into_response("iterations"))
+}
+
+#[get("/projects/{id}/iterations")]
+async fn get_project_iterations(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    opts: Query<IterationFilterOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    list_iterations(&data, path.into_inner(), None, &opts).await
+}
+
+#[get("/projects/{id}/iterations/{timeframe}")]
+async fn get_project_iterations_by_timeframe(
+    current: CurrentUser,
+    path: Path<(Uuid, String)>,
+    opts: Query<IterationFilterOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let (project_id, timeframe) = path.into_inner();
+    if !TIMEFRAMES.contains(&timeframe.as_str()) {
+        return Err(AppError::NotFound(format!(
+            "Unknown timeframe {}, expected one of: {}",
+            timeframe,
+            TIMEFRAMES.join(", ")
+        )));
+    }
+
+    list_iterations(&data, project_id, Some(timeframe), &opts).await
+}
+
+#[get("/iterations/{id}")]
+async fn get_iteration_by_id(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let iteration_id = path.into_inner();
+
+    let iteration = sqlx::query_as!(
+        Iteration,
+        "SELECT * FROM iterations WHERE id = $1",
+        iteration_id
+    )
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Iteration {} not found", iteration_id)))?;
+
+    Ok(HttpResponse::Ok()
+        .json(json!({"status":"success", "data":IterationResponse::from(iteration)})))
+}
+
+#[patch("/iterations/{id}")]
+async fn update_iteration_by_id(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    body: Json<UpdateIterationRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
+    let iteration_id = path.into_inner();
+
+    let mut tx = data.db.begin().await?;
+
+    let before = find_iteration(&mut tx, iteration_id).await?;
+    let project = find_project(&mut tx, before.project_id).await?;
+    current
+        .authorize(&data.db, manage_permission(project.team_id))
+        .await?;
+
+    let start_date = body.start_date.unwrap_or(before.start_date);
+    let end_date = body.end_date.unwrap_or(before.end_date);
+    check_date_range(start_date, end_date)?;
+
+    let iteration = sqlx::query_as!(
+        Iteration,
+        "UPDATE iterations SET
+            name = COALESCE($1, name),
+            path = COALESCE($2, path),
+            start_date = $3,
+            end_date = $4
+        WHERE id = $5 RETURNING *",
+        body.name,
+        body.path,
+        start_date,
+        end_date,
+        iteration_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    if iteration.path != before.path {
+        link_workitems(&mut tx, current.0.id, &iteration).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok()
+        .json(json!({"status":"success", "data":IterationResponse::from(iteration)})))
+}
+
+#[delete("/iterations/{id}")]
+async fn delete_iteration(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let iteration_id = path.into_inner();
+
+    let mut tx = data.db.begin().await?;
+
+    let iteration = find_iteration(&mut tx, iteration_id).await?;
+    let project = find_project(&mut tx, iteration.project_id).await?;
+    current
+        .authorize(&data.db, manage_permission(project.team_id))
+        .await?;
+
+    //work items have to be moved to another iteration first, as in Azure DevOps
+    let workitems = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM work_items WHERE iteration_id = $1"#,
+        iteration_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    if workitems > 0 {
+        return Err(AppError::Conflict(format!(
+            "Iteration {} still has {} work items",
+            iteration_id, workitems
+        )));
+    }
+
+    sqlx::query!("DELETE FROM iterations WHERE id = $1", iteration_id)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/iterations/{id}/carry-over")]
+async fn carry_over_iteration(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    body: Json<CarryOverRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
+    let iteration_id = path.into_inner();
+
+    let mut tx = data.db.begin().await?;
+
+    let iteration = find_iteration(&mut tx, iteration_id).await?;
+    current
+        .authorize(&data.db, Permission::WriteProject(iteration.project_id))
+        .await?;
+
+    let target = match body.target_iteration_id {
+        Some(target_id) => {
+            let target = find_iteration(&mut tx, target_id).await?;
+            if target.project_id != iteration.project_id {
+                return Err(AppError::Unprocessable(format!(
+                    "Iteration {} belongs to another project",
+                    target_id
+                )));
+            }
+            target
+        }
+        //the first iteration starting after this one; an iteration without dates moves
+        //its items to the sprint running now, or the next one when none is
+        None => sqlx::query_as!(
+            Iteration,
+            "SELECT * FROM iterations
+            WHERE project_id = $1 AND id <> $2 AND COALESCE(start_date, end_date) IS NOT NULL
+                AND CASE WHEN $3::timestamptz IS NOT NULL THEN COALESCE(start_date, end_date) > $3
+                    ELSE COALESCE(end_date, start_date) >= NOW() END
+            ORDER BY COALESCE(start_date, end_date), end_date NULLS LAST, path LIMIT 1",
+            iteration.project_id,
+            iteration.id,
+            iteration.start_date.or(iteration.end_date)
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| {
+            AppError::Unprocessable(format!(
+                "Iteration {} has no next iteration, pass target_iteration_id",
+                iteration_id
+            ))
+        })?,
+    };
+    if target.id == iteration.id {
+        return Err(AppError::Validation(
+            "target_iteration_id must differ from the iteration".to_string(),
+        ));
+    }
+
+    //finished means a Completed or Removed state in the project's process, or one of
+    //the usual names when the project has none
+    let unfinished = sqlx::query_as!(
+        WorkItem,
+        "SELECT w.* FROM work_items w
+        JOIN projects p ON p.id = w.project
+        WHERE w.iteration_id = $1
+            AND lower(w.state) NOT IN ('closed', 'done', 'removed', 'completed')
+            AND NOT EXISTS (
+                SELECT 1 FROM process_states s
+                WHERE lower(s.template) = lower(p.template)
+                    AND lower(s.w_type) = lower(w.w_type)
+                    AND lower(s.name) = lower(w.state)
+                    AND s.category IN ('Completed', 'Removed')
+            )
+        FOR UPDATE OF w",
+        iteration_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    let mut moved = Vec::with_capacity(unfinished.len());
+    for before in &unfinished {
+        let after = sqlx::query_as!(
+            WorkItem,
+            "UPDATE work_items SET iteration_id = $1, iteration_path = $2, changed_date = NOW()
+            WHERE id = $3 RETURNING *",
+            target.id,
+            target.path,
+            before.id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        revision_services::record_and_publish(
+            &mut tx,
+            Some(current.0.id),
+            Some(before),
+            Some(&after),
+        )
+        .await?;
+        moved.push(after);
+    }
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status":"success",
+        "result": moved.len(),
+        "iteration": IterationResponse::from(target),
+        "workitems": moved
+    })))
+}
@@ -0,0 +1,427 @@
+use actix_web::HttpResponse;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{postgres::PgRow, FromRow, Pool, Postgres, QueryBuilder, Row};
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 100;
+
+/// A column clients may sort on with `sort=`.
+pub struct SortField {
+    name: &'static str,
+    column: &'static str,
+    // type the cursor value is cast back to
+    sql_type: &'static str,
+    nullable: bool,
+}
+
+impl SortField {
+    pub const fn new(name: &'static str, column: &'static str, sql_type: &'static str) -> Self {
+        SortField {
+            name,
+            column,
+            sql_type,
+            nullable: false,
+        }
+    }
+
+    pub const fn nullable(self) -> Self {
+        SortField {
+            nullable: true,
+            ..self
+        }
+    }
+}
+
+/// A value bound into a filter condition.
+#[derive(Debug, Clone)]
+pub enum FilterValue {
+    Text(String),
+    TextList(Vec<String>),
+    Int(i64),
+    Uuid(Uuid),
+    Timestamp(DateTime<Utc>),
+    Bool(bool),
+}
+
+impl From<String> for FilterValue {
+    fn from(value: String) -> Self {
+        FilterValue::Text(value)
+    }
+}
+
+impl From<Vec<String>> for FilterValue {
+    fn from(value: Vec<String>) -> Self {
+        FilterValue::TextList(value)
+    }
+}
+
+impl From<i64> for FilterValue {
+    fn from(value: i64) -> Self {
+        FilterValue::Int(value)
+    }
+}
+
+impl From<Uuid> for FilterValue {
+    fn from(value: Uuid) -> Self {
+        FilterValue::Uuid(value)
+    }
+}
+
+impl From<DateTime<Utc>> for FilterValue {
+    fn from(value: DateTime<Utc>) -> Self {
+        FilterValue::Timestamp(value)
+    }
+}
+
+impl From<bool> for FilterValue {
+    fn from(value: bool) -> Self {
+        FilterValue::Bool(value)
+    }
+}
+
+impl FilterValue {
+    fn push_bind(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        match self.clone() {
+            FilterValue::Text(value) => query.push_bind(value),
+            FilterValue::TextList(value) => query.push_bind(value),
+            FilterValue::Int(value) => query.push_bind(value),
+            FilterValue::Uuid(value) => query.push_bind(value),
+            FilterValue::Timestamp(value) => query.push_bind(value),
+            FilterValue::Bool(value) => query.push_bind(value),
+        };
+    }
+}
+
+// Splits a comma separated query parameter, e.g. state=New,Active
+pub fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+// Turns user input into a LIKE pattern matching it anywhere, with wildcards escaped
+pub fn contains_pattern(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+/// One page of a listing with the metadata returned next to the items.
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            total: self.total,
+            next_cursor: self.next_cursor,
+            prev_cursor: self.prev_cursor,
+        }
+    }
+}
+
+impl<T: Serialize> Page<T> {
+    /// The list response body, with the items under `key`.
+    pub fn into_response(self, key: &str) -> HttpResponse {
+        let mut body = json!({
+            "status":"success",
+            "result": self.items.len(),
+            "total": self.total,
+            "next_cursor": self.next_cursor,
+            "prev_cursor": self.prev_cursor
+        });
+        body[key] = json!(self.items);
+        HttpResponse::Ok().json(body)
+    }
+}
+
+// Opaque to clients; ties the position to the sort it was produced with
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    sort: String,
+    values: Vec<Option<String>>,
+    backward: bool,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(cursor: &str) -> Result<Self, AppError> {
+        URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| AppError::BadRequest("cursor is not valid".to_string()))
+    }
+}
+
+struct SortKey {
+    expression: String,
+    descending: bool,
+    sql_type: &'static str,
+}
+
+/// Builds filtered, sorted and keyset-paginated list queries.
+///
+/// Conditions are SQL fragments where `$` marks the bound value. Sorting
+/// always ends on the id column so every row has a unique position, and
+/// nullable columns sort their NULLs last in both directions.
+pub struct Listing {
+    select: &'static str,
+    from: &'static str,
+    id_column: &'static str,
+    id_type: &'static str,
+    sort_fields: &'static [SortField],
+    default_sort: &'static str,
+    conditions: Vec<(&'static str, FilterValue)>,
+}
+
+impl Listing {
+    pub fn new(
+        select: &'static str,
+        from: &'static str,
+        id_column: &'static str,
+        id_type: &'static str,
+        sort_fields: &'static [SortField],
+        default_sort: &'static str,
+    ) -> Self {
+        Listing {
+            select,
+            from,
+            id_column,
+            id_type,
+            sort_fields,
+            default_sort,
+            conditions: Vec::new(),
+        }
+    }
+
+    pub fn filter(
+        &mut self,
+        condition: &'static str,
+        value: Option<impl Into<FilterValue>>,
+    ) -> &mut Self {
+        if let Some(value) = value {
+            self.conditions.push((condition, value.into()));
+        }
+        self
+    }
+
+    fn push_conditions(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        query.push(" WHERE TRUE");
+        for (condition, value) in &self.conditions {
+            let (before, after) = condition.split_once('$').unwrap_or((condition, ""));
+            query.push(" AND ").push(before);
+            value.push_bind(query);
+            query.push(after);
+        }
+    }
+
+    // Parses sort=name,-created_date into keys, rejecting unknown fields
+    fn sort_keys(&self, sort: &str) -> Result<Vec<SortKey>, AppError> {
+        let mut keys = Vec::new();
+        for term in sort
+            .split(',')
+            .map(str::trim)
+            .filter(|term| !term.is_empty())
+        {
+            let (name, descending) = match term.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (term.trim_start_matches('+'), false),
+            };
+            let field = self
+                .sort_fields
+                .iter()
+                .find(|field| field.name == name)
+                .ok_or_else(|| {
+                    let allowed: Vec<_> = self.sort_fields.iter().map(|field| field.name).collect();
+                    AppError::BadRequest(format!(
+                        "cannot sort by {}, expected one of: {}",
+                        name,
+                        allowed.join(", ")
+                    ))
+                })?;
+            if field.nullable {
+                keys.push(SortKey {
+                    expression: format!("({} IS NULL)", field.column),
+                    descending: false,
+                    sql_type: "boolean",
+                });
+            }
+            keys.push(SortKey {
+                expression: field.column.to_string(),
+                descending,
+                sql_type: field.sql_type,
+            });
+        }
+        keys.push(SortKey {
+            expression: self.id_column.to_string(),
+            descending: false,
+            sql_type: self.id_type,
+        });
+        Ok(keys)
+    }
+
+    // Rows strictly after the cursor: (k1 > v1) OR (k1 = v1 AND k2 > v2) OR ...
+    fn push_after(query: &mut QueryBuilder<'_, Postgres>, keys: &[SortKey], cursor: &Cursor) {
+        query.push(" AND (");
+        for (index, key) in keys.iter().enumerate() {
+            if index > 0 {
+                query.push(" OR ");
+            }
+            query.push("(");
+            for (previous, value) in keys.iter().zip(&cursor.values).take(index) {
+                query.push(format!("{} IS NOT DISTINCT FROM ", previous.expression));
+                query.push_bind(value.clone());
+                query.push(format!("::{} AND ", previous.sql_type));
+            }
+            let operator = if key.descending != cursor.backward {
+                "<"
+            } else {
+                ">"
+            };
+            query.push(format!("{} {} ", key.expression, operator));
+            query.push_bind(cursor.values[index].clone());
+            query.push(format!("::{})", key.sql_type));
+        }
+        query.push(")");
+    }
+
+    /// Every matching row in sort order with `select` in place of the listing's
+    /// columns. Exports stream this instead of paging through the listing.
+    pub fn query_all(
+        &self,
+        select: &str,
+        sort: Option<&str>,
+    ) -> Result<QueryBuilder<'static, Postgres>, AppError> {
+        let keys = self.sort_keys(sort.unwrap_or(self.default_sort))?;
+
+        let mut query = QueryBuilder::new(format!("SELECT {} FROM {}", select, self.from));
+        self.push_conditions(&mut query);
+        let order: Vec<_> = keys
+            .iter()
+            .map(|key| {
+                let direction = if key.descending { "DESC" } else { "ASC" };
+                format!("{} {}", key.expression, direction)
+            })
+            .collect();
+        query.push(format!(" ORDER BY {}", order.join(", ")));
+        Ok(query)
+    }
+
+    pub async fn fetch<T>(
+        &self,
+        db: &Pool<Postgres>,
+        sort: Option<&str>,
+        cursor: Option<&str>,
+        limit: Option<i64>,
+    ) -> Result<Page<T>, AppError>
+    where
+        T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+    {
+        let sort = sort.unwrap_or(self.default_sort);
+        let keys = self.sort_keys(sort)?;
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+        let cursor = cursor.map(Cursor::decode).transpose()?;
+        if let Some(cursor) = &cursor {
+            if cursor.sort != sort || cursor.values.len() != keys.len() {
+                return Err(AppError::BadRequest(
+                    "cursor was issued for a different sort".to_string(),
+                ));
+            }
+        }
+        let backward = cursor.as_ref().is_some_and(|cursor| cursor.backward);
+
+        let mut count = QueryBuilder::new(format!("SELECT COUNT(*) FROM {}", self.from));
+        self.push_conditions(&mut count);
+        let (total,): (i64,) = count.build_query_as().fetch_one(db).await?;
+
+        let mut query = QueryBuilder::new(format!("SELECT {}", self.select));
+        for (index, key) in keys.iter().enumerate() {
+            query.push(format!(
+                ", ({})::text AS sort_key_{}",
+                key.expression, index
+            ));
+        }
+        query.push(format!(" FROM {}", self.from));
+        self.push_conditions(&mut query);
+        if let Some(cursor) = &cursor {
+            Self::push_after(&mut query, &keys, cursor);
+        }
+        let order: Vec<_> = keys
+            .iter()
+            .map(|key| {
+                let direction = if key.descending != backward {
+                    "DESC"
+                } else {
+                    "ASC"
+                };
+                format!("{} {}", key.expression, direction)
+            })
+            .collect();
+        query.push(format!(" ORDER BY {}", order.join(", ")));
+        query.push(" LIMIT ").push_bind(limit + 1);
+
+        let mut rows = query.build().fetch_all(db).await?;
+        let has_more = rows.len() as i64 > limit;
+        rows.truncate(limit as usize);
+        if backward {
+            rows.reverse();
+        }
+
+        let position = |row: &PgRow, backward: bool| -> Result<String, AppError> {
+            let values = (0..keys.len())
+                .map(|index| {
+                    row.try_get::<Option<String>, _>(format!("sort_key_{}", index).as_str())
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Cursor {
+                sort: sort.to_string(),
+                values,
+                backward,
+            }
+            .encode())
+        };
+        // walking backward there is always a next page, the one the cursor came from
+        let next_cursor = match rows.last() {
+            Some(row) if has_more || backward => Some(position(row, false)?),
+            _ => None,
+        };
+        let prev_cursor = match rows.first() {
+            Some(row) if (has_more && backward) || (!backward && cursor.is_some()) => {
+                Some(position(row, true)?)
+            }
+            _ => None,
+        };
+
+        let items = rows
+            .iter()
+            .map(T::from_row)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Page {
+            items,
+            total,
+            next_cursor,
+            prev_cursor,
+        })
+    }
+}
@@ -0,0 +1,296 @@
+use std::{collections::HashMap, time::Duration};
+
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message};
+use actix_web::web::Bytes;
+use futures::channel::mpsc;
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{postgres::PgListener, Executor, Pool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{model::WorkItem, notification_services::fetch_notification};
+
+/// Postgres channel every instance listens on; changes are announced inside the
+/// transaction that makes them, so a rolled back change announces nothing.
+pub const CHANNEL: &str = "live_feed";
+/// How often subscribers get a heartbeat. Closed subscriptions are dropped on it.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+// Events a slow subscriber may fall behind by before it is disconnected
+const SUBSCRIBER_BUFFER: usize = 64;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// What a NOTIFY payload carries. It stays small, well under the 8000 bytes
+/// Postgres allows; the listening instance reads the changed row itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    // projects and teams the work item belonged to before or after the change,
+    // so a board also hears about items leaving it
+    Workitem {
+        event: String,
+        id: Uuid,
+        project_ids: Vec<Uuid>,
+        team_ids: Vec<Uuid>,
+    },
+    Notification {
+        id: i32,
+        receiver_id: Uuid,
+    },
+}
+
+/// One message sent to a subscriber.
+#[derive(Debug, Clone)]
+pub enum LiveMessage {
+    Event { event: String, data: Value },
+    Heartbeat,
+}
+
+impl LiveMessage {
+    /// The message as a Server-Sent Events frame.
+    pub fn to_sse(&self) -> Bytes {
+        match self {
+            LiveMessage::Event { event, data } => {
+                Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+            }
+            LiveMessage::Heartbeat => Bytes::from_static(b": heartbeat\n\n"),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        match self {
+            LiveMessage::Event { event, data } => json!({"event": event, "data": data}),
+            LiveMessage::Heartbeat => json!({"event": "heartbeat"}),
+        }
+        .to_string()
+    }
+}
+
+/// What a connection receives: the work items of a project or team, and its
+/// user's own new notifications.
+#[derive(Debug, Clone)]
+pub struct LiveFilter {
+    pub user_id: Uuid,
+    pub project: Option<Uuid>,
+    pub team: Option<Uuid>,
+}
+
+impl LiveFilter {
+    fn matches(&self, change: &Change) -> bool {
+        match change {
+            Change::Workitem {
+                project_ids,
+                team_ids,
+                ..
+            } => {
+                self.project.is_some_and(|id| project_ids.contains(&id))
+                    || self.team.is_some_and(|id| team_ids.contains(&id))
+            }
+            Change::Notification { receiver_id, .. } => *receiver_id == self.user_id,
+        }
+    }
+}
+
+/// Hands the changes heard on [`CHANNEL`] to the connections of this instance.
+#[derive(Default)]
+pub struct LiveFeed {
+    subscribers: HashMap<u64, (LiveFilter, mpsc::Sender<LiveMessage>)>,
+    next_id: u64,
+}
+
+impl LiveFeed {
+    // A subscriber that is gone or too far behind is dropped, which ends its stream
+    fn send(&mut self, message: &LiveMessage, filter: impl Fn(&LiveFilter) -> bool) {
+        self.subscribers.retain(|_, (subscriber, sender)| {
+            !filter(subscriber) || sender.try_send(message.clone()).is_ok()
+        });
+    }
+}
+
+impl Actor for LiveFeed {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |feed, _| {
+            feed.send(&LiveMessage::Heartbeat, |_| true);
+        });
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Subscribe {
+    filter: LiveFilter,
+    sender: mpsc::Sender<LiveMessage>,
+}
+
+// Whether any subscriber wants the change, so unwatched rows are not read
+#[derive(Message)]
+#[rtype(result = "bool")]
+struct Interested(Change);
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Publish {
+    change: Change,
+    message: LiveMessage,
+}
+
+impl Handler<Subscribe> for LiveFeed {
+    type Result = ();
+
+    fn handle(&mut self, msg: Subscribe, _: &mut Self::Context) {
+        self.next_id += 1;
+        self.subscribers
+            .insert(self.next_id, (msg.filter, msg.sender));
+    }
+}
+
+impl Handler<Interested> for LiveFeed {
+    type Result = bool;
+
+    fn handle(&mut self, msg: Interested, _: &mut Self::Context) -> bool {
+        self.subscribers
+            .values()
+            .any(|(filter, _)| filter.matches(&msg.0))
+    }
+}
+
+impl Handler<Publish> for LiveFeed {
+    type Result = ();
+
+    fn handle(&mut self, msg: Publish, _: &mut Self::Context) {
+        self.send(&msg.message, |filter| filter.matches(&msg.change));
+    }
+}
+
+/// Registers a connection; the stream ends when the feed drops it.
+pub fn subscribe(feed: &Addr<LiveFeed>, filter: LiveFilter) -> mpsc::Receiver<LiveMessage> {
+    let (sender, receiver) = mpsc::channel(SUBSCRIBER_BUFFER);
+    feed.do_send(Subscribe { filter, sender });
+    receiver
+}
+
+async fn announce<'e, E: Executor<'e, Database = Postgres>>(
+    executor: E,
+    change: &Change,
+) -> Result<(), sqlx::Error> {
+    let payload = serde_json::to_string(change).unwrap_or_default();
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(CHANNEL)
+        .bind(payload)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// Announces a recorded work item change, `before` being None for a new work
+/// item and `after` None for a deleted one.
+pub async fn announce_workitem(
+    tx: &mut Transaction<'_, Postgres>,
+    before: Option<&WorkItem>,
+    after: Option<&WorkItem>,
+) -> Result<(), sqlx::Error> {
+    let (event, id) = match (before, after) {
+        (None, Some(after)) => ("workitem.created", after.id),
+        (Some(before), None) => ("workitem.deleted", before.id),
+        (Some(_), Some(after)) => ("workitem.updated", after.id),
+        (None, None) => return Ok(()),
+    };
+
+    let mut project_ids: Vec<Uuid> = Vec::with_capacity(2);
+    let mut area_paths: Vec<String> = Vec::with_capacity(2);
+    for workitem in [before, after].into_iter().flatten() {
+        if !project_ids.contains(&workitem.project) {
+            project_ids.push(workitem.project);
+        }
+        if let Some(area_path) = &workitem.area_path {
+            area_paths.push(area_path.clone());
+        }
+    }
+
+    //the teams owning the work item's areas, as the team backlog finds them
+    let team_ids = sqlx::query_scalar!(
+        r#"SELECT DISTINCT a.team_id AS "team_id!" FROM areas a, unnest($2::varchar[]) p(path)
+        WHERE a.team_id IS NOT NULL AND a.project_id = ANY($1)
+            AND (lower(p.path) = lower(a.path) OR starts_with(lower(p.path), lower(a.path) || '\'))"#,
+        &project_ids,
+        &area_paths
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let change = Change::Workitem {
+        event: event.to_string(),
+        id,
+        project_ids,
+        team_ids,
+    };
+    announce(&mut *tx, &change).await
+}
+
+pub async fn announce_notification<'e, E: Executor<'e, Database = Postgres>>(
+    executor: E,
+    id: i32,
+    receiver_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    announce(executor, &Change::Notification { id, receiver_id }).await
+}
+
+// The message for a change, or None when its row is already gone again
+async fn load(db: &Pool<Postgres>, change: &Change) -> Result<Option<LiveMessage>, sqlx::Error> {
+    let (event, data) = match change {
+        Change::Workitem { event, id, .. } if event == "workitem.deleted" => {
+            (event.clone(), json!({"id": id}))
+        }
+        Change::Workitem { event, id, .. } => {
+            let workitem = sqlx::query_as!(WorkItem, "SELECT * FROM work_items WHERE id = $1", id)
+                .fetch_optional(db)
+                .await?;
+            match workitem {
+                Some(workitem) => (event.clone(), json!(workitem)),
+                None => return Ok(None),
+            }
+        }
+        Change::Notification { id, .. } => match fetch_notification(db, *id).await {
+            Ok(notification) => ("notification.created".to_string(), json!(notification)),
+            Err(sqlx::Error::RowNotFound) => return Ok(None),
+            Err(error) => return Err(error),
+        },
+    };
+    Ok(Some(LiveMessage::Event { event, data }))
+}
+
+async fn listen(db: &Pool<Postgres>, feed: &Addr<LiveFeed>) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(db).await?;
+    listener.listen(CHANNEL).await?;
+    loop {
+        let notification = listener.recv().await?;
+        let change: Change = match serde_json::from_str(notification.payload()) {
+            Ok(change) => change,
+            Err(error) => {
+                warn!("Ignoring live feed notification: {}", error);
+                continue;
+            }
+        };
+        if !feed.send(Interested(change.clone())).await.unwrap_or(false) {
+            continue;
+        }
+        if let Some(message) = load(db, &change).await? {
+            feed.do_send(Publish { change, message });
+        }
+    }
+}
+
+/// Listens on [`CHANNEL`] for changes made by any instance, reconnecting when
+/// the connection is lost. Changes made while disconnected are not replayed.
+pub fn spawn_listener(db: Pool<Postgres>, feed: Addr<LiveFeed>) {
+    actix_web::rt::spawn(async move {
+        loop {
+            if let Err(error) = listen(&db, &feed).await {
+                error!("Live feed listener failed: {}", error);
+            }
+            actix_web::rt::time::sleep(RECONNECT_DELAY).await;
+        }
+    });
+}
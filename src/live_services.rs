@@ -0,0 +1,160 @@
+use std::{convert::Infallible, time::Instant};
+
+use actix::{Actor, ActorContext, AsyncContext, StreamHandler};
+use actix_web::{
+    get,
+    http::header::{CacheControl, CacheDirective},
+    web::{Bytes, Data, Payload, Query},
+    HttpRequest, HttpResponse,
+};
+use actix_web_actors::ws;
+use futures::{channel::mpsc, stream, StreamExt};
+use log::info;
+use uuid::Uuid;
+
+use crate::{
+    auth::CurrentUser,
+    error::AppError,
+    live_feed::{self, LiveFilter, LiveMessage, HEARTBEAT_INTERVAL},
+    policy::Permission,
+    schema::LiveFeedOptions,
+    AppState,
+};
+
+// EventSource waits this long before reconnecting a dropped stream
+const SSE_RETRY_MILLIS: u32 = 3000;
+
+async fn live_filter(
+    current: &CurrentUser,
+    opts: &LiveFeedOptions,
+    data: &AppState,
+) -> Result<LiveFilter, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    if let Some(project_id) = opts.project {
+        let exists = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM projects WHERE id = $1) AS "exists!""#,
+            project_id
+        )
+        .fetch_one(&data.db)
+        .await?;
+        if !exists {
+            return Err(AppError::NotFound(format!(
+                "Project {} not found",
+                project_id
+            )));
+        }
+    }
+    if let Some(team_id) = opts.team {
+        let exists = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM teams WHERE id = $1) AS "exists!""#,
+            team_id
+        )
+        .fetch_one(&data.db)
+        .await?;
+        if !exists {
+            return Err(AppError::NotFound(format!("Team {} not found", team_id)));
+        }
+    }
+
+    Ok(LiveFilter {
+        user_id: current.0.id,
+        project: opts.project,
+        team: opts.team,
+    })
+}
+
+// Server-Sent Events: one `event:`/`data:` frame per change and a comment line as heartbeat
+#[get("/live")]
+async fn live_events(
+    current: CurrentUser,
+    opts: Query<LiveFeedOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let filter = live_filter(&current, &opts, &data).await?;
+    let receiver = live_feed::subscribe(&data.live, filter);
+
+    let retry = Bytes::from(format!("retry: {}\n\n", SSE_RETRY_MILLIS));
+    let events = stream::once(async move { retry })
+        .chain(receiver.map(|message| message.to_sse()))
+        .map(Ok::<_, Infallible>);
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        //keeps reverse proxies such as nginx from buffering the stream
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(events))
+}
+
+// WebSocket: every change as a JSON text message {"event", "data"}
+#[get("/live/ws")]
+async fn live_socket(
+    current: CurrentUser,
+    opts: Query<LiveFeedOptions>,
+    request: HttpRequest,
+    payload: Payload,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let filter = live_filter(&current, &opts, &data).await?;
+    let session = LiveSession {
+        user_id: current.0.id,
+        receiver: Some(live_feed::subscribe(&data.live, filter)),
+        last_seen: Instant::now(),
+    };
+
+    ws::start(session, &request, payload).map_err(|error| AppError::BadRequest(error.to_string()))
+}
+
+/// One WebSocket connection, fed by the live feed. The client only has to
+/// answer pings; it is dropped once it has been silent for two heartbeats.
+struct LiveSession {
+    user_id: Uuid,
+    receiver: Option<mpsc::Receiver<LiveMessage>>,
+    last_seen: Instant,
+}
+
+impl Actor for LiveSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(receiver) = self.receiver.take() {
+            ctx.add_stream(receiver);
+        }
+    }
+}
+
+impl StreamHandler<LiveMessage> for LiveSession {
+    fn handle(&mut self, message: LiveMessage, ctx: &mut Self::Context) {
+        match message {
+            LiveMessage::Heartbeat if self.last_seen.elapsed() > HEARTBEAT_INTERVAL * 2 => {
+                info!("Closing silent live feed socket of user {}", self.user_id);
+                ctx.stop();
+            }
+            LiveMessage::Heartbeat => ctx.ping(b""),
+            event => ctx.text(event.to_json()),
+        }
+    }
+
+    // the feed dropped this connection, most likely because it fell behind
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        ctx.close(Some(ws::CloseCode::Again.into()));
+        ctx.stop();
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for LiveSession {
+    fn handle(&mut self, message: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        self.last_seen = Instant::now();
+        match message {
+            Ok(ws::Message::Ping(bytes)) => ctx.pong(&bytes),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            //the feed is one way, anything else the client sends only shows it is alive
+            Ok(_) => {}
+            Err(_) => ctx.stop(),
+        }
+    }
+}
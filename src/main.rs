@@ -1,18 +1,56 @@
+mod area_services;
+mod auth;
+mod azure_client;
+mod azure_hook_services;
+mod azure_sync;
+mod comment_services;
+mod error;
+mod export_services;
+mod import_services;
+mod iteration_services;
+mod listing;
+mod live_feed;
+mod live_services;
 mod model;
+mod notification_services;
+mod policy;
+mod process;
+mod process_services;
+mod revision_services;
 mod routes;
 mod schema;
+mod search_services;
+mod sync_services;
+mod tag_services;
 mod team_services;
 mod user_services;
+mod webhook_services;
+mod webhooks;
 mod projects_services;
 mod workitems_services;
 
+use std::sync::Arc;
+
+use actix::{Actor, Addr};
 use actix_cors::Cors;
-use actix_web::{middleware::Logger, web, App, HttpServer};
+use auth::Authenticator;
+use azure_client::AzureClient;
+use azure_hook_services::HookCredentials;
+use error::AppError;
+use live_feed::LiveFeed;
+use actix_web::{
+    middleware::{from_fn, Logger},
+    web, App, HttpServer,
+};
 use dotenv::dotenv;
 use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
 
 pub struct AppState {
     db: Pool<Postgres>,
+    azure: Option<AzureClient>,
+    azure_hooks: Option<HookCredentials>,
+    auth: Arc<Authenticator>,
+    live: Addr<LiveFeed>,
 }
 
 #[actix_web::main]
@@ -40,6 +78,56 @@ async fn main() -> std::io::Result<()> {
         .await
         .expect("Failed to create pool");
 
+    policy::promote_system_admins(&pool)
+        .await
+        .expect("Failed to promote SYSTEM_ADMINS");
+
+    // Azure DevOps sync is optional and only enabled when AZURE_DEVOPS_URL is set
+    let azure = AzureClient::from_env();
+    if let (Some(client), Ok(interval)) = (&azure, std::env::var("AZURE_DEVOPS_SYNC_INTERVAL_SECS")) {
+        let interval = interval
+            .parse()
+            .expect("AZURE_DEVOPS_SYNC_INTERVAL_SECS must be a number of seconds");
+        azure_sync::spawn_periodic_sync(
+            pool.clone(),
+            client.clone(),
+            std::time::Duration::from_secs(interval),
+        );
+    }
+
+    // Azure DevOps service hooks are only accepted once their credentials are set
+    let azure_hooks = HookCredentials::from_env();
+
+    // Webhook deliveries are queued in the database and sent by a background worker
+    let webhook_interval = std::env::var("WEBHOOK_POLL_INTERVAL_SECS")
+        .map(|interval| {
+            interval
+                .parse()
+                .expect("WEBHOOK_POLL_INTERVAL_SECS must be a number of seconds")
+        })
+        .unwrap_or(5);
+    // Receivers on private networks are refused unless explicitly allowed, e.g. for development
+    let webhook_allow_private = std::env::var("WEBHOOK_ALLOW_PRIVATE_TARGETS")
+        .map(|allow| allow == "true")
+        .unwrap_or(false);
+    webhooks::spawn_delivery_worker(
+        pool.clone(),
+        std::time::Duration::from_secs(webhook_interval),
+        webhooks::DeliveryClient::new(webhook_allow_private),
+    );
+
+    // Live feed connections of this instance hear about changes from every instance
+    // through Postgres LISTEN/NOTIFY
+    let live = LiveFeed::default().start();
+    live_feed::spawn_listener(pool.clone(), live.clone());
+
+    // Every route except the healthcheck and the service hook receiver requires a bearer token
+    let auth = Arc::new(
+        Authenticator::from_env()
+            .await
+            .unwrap_or_else(|error| panic!("Failed to configure authentication: {}", error)),
+    );
+
     // Configure CORS more securely
     // let cors = Cors::default()
     //     .allowed_origin("*")
@@ -51,13 +139,34 @@ async fn main() -> std::io::Result<()> {
 
     HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(AppState { db: pool.clone() }))
+            .app_data(web::Data::new(AppState {
+                db: pool.clone(),
+                azure: azure.clone(),
+                azure_hooks: azure_hooks.clone(),
+                auth: auth.clone(),
+                live: live.clone(),
+            }))
+            // malformed bodies, query strings and paths get the same error body as handlers
+            .app_data(web::JsonConfig::default().error_handler(|error, _| {
+                AppError::BadRequest(error.to_string()).into()
+            }))
+            .app_data(web::QueryConfig::default().error_handler(|error, _| {
+                AppError::BadRequest(error.to_string()).into()
+            }))
+            .app_data(web::PathConfig::default().error_handler(|error, _| {
+                AppError::BadRequest(error.to_string()).into()
+            }))
+            .wrap(from_fn(auth::authenticate))
             .wrap(Cors::default()
-            .allowed_origin("*")
-            .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
+            .allow_any_origin()
+            .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
             .allowed_headers(vec!["Authorization", "Content-Type"])
             .max_age(3600))
-            .wrap(Logger::default())
+            //the default format, with the request line from auth so query string tokens are masked
+            .wrap(
+                Logger::new(r#"%a "%{r}xi" %s %b "%{Referer}i" "%{User-Agent}i" %T"#)
+                    .custom_request_replace("r", auth::logged_request_line),
+            )
             .configure(routes::configure_routes)
     })
     .bind(format!("{}:{}", host, port))?
@@ -1,9 +1,11 @@
+use std::collections::BTreeMap;
+
 use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::prelude::FromRow;
+use sqlx::{postgres::PgRow, prelude::FromRow, Row};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, FromRow)]
 pub struct Team {
     pub id: Uuid,
     pub azure_id: Option<String>,
@@ -28,9 +30,11 @@ pub struct TeamResponse {
     pub users: Option<Vec<User>>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
 pub struct TeamUser {
     pub team_id: Uuid,
     pub user_id: Uuid,
+    pub role: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
@@ -39,8 +43,7 @@ pub struct User {
     pub azure_id: Option<String>,
     pub name: Option<String>,
     pub email: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub team_id: Option<Uuid>,
+    pub role: String,
     // Relationships
     // #[serde(skip_serializing_if = "Option::is_none")]
     // #[serde(default)]
@@ -59,6 +62,24 @@ pub struct User {
     // pub notifications_sent: Option<Vec<Notification>>,
 }
 
+/// A row written by `INSERT ... ON CONFLICT DO UPDATE RETURNING *, (xmax = 0) AS inserted`.
+/// `inserted` is false when the statement updated a row that already existed,
+/// including one a concurrent transaction inserted first.
+#[derive(Debug)]
+pub struct Upserted<T> {
+    pub row: T,
+    pub inserted: bool,
+}
+
+impl<'r, T: FromRow<'r, PgRow>> FromRow<'r, PgRow> for Upserted<T> {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Upserted {
+            row: T::from_row(row)?,
+            inserted: row.try_get("inserted")?,
+        })
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone,FromRow)]
 pub struct ProjectModel {
     pub id: Uuid,
@@ -67,18 +88,19 @@ pub struct ProjectModel {
     pub description: Option<String>,
     pub url: Option<String>,
     pub template: Option<String>,
-    // pub begin_date: Option<DateTime<Utc>>,
-    // pub end_date: Option<DateTime<Utc>>,
+    pub begin_date: Option<DateTime<Utc>>,
+    pub end_date: Option<DateTime<Utc>>,
     pub team_id: Option<Uuid>,
     // Relationships
     // #[serde(skip_serializing_if = "Option::is_none")]
     // pub team: Option<Team>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
 pub struct WorkItem {
     pub id: Uuid,
     pub azure_id: Option<String>,
+    pub azure_rev: Option<i32>, // Azure DevOps revision last mirrored
     pub title: String,
     pub w_type: String, // Using type_ because 'type' is a reserved word
     pub state: String,
@@ -92,6 +114,7 @@ pub struct WorkItem {
     pub description: Option<String>,
     pub area_path: Option<String>,
     pub iteration_path: Option<String>,
+    pub iteration_id: Option<Uuid>, // iteration matching iteration_path
     pub parent_id: Option<Uuid>, // parent workitem id
     pub tags: Option<Vec<String>>,
     pub url: String,
@@ -102,6 +125,147 @@ pub struct WorkItem {
     // pub created_by: Option<User>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct WorkItemRollup {
+    pub child_count: i64,
+    pub children_by_state: BTreeMap<String, i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WorkItemNode {
+    #[serde(flatten)]
+    pub workitem: WorkItem,
+    pub rollup: WorkItemRollup,
+    // None when the depth limit stopped the tree above this item's children
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub children: Option<Vec<WorkItemNode>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct Iteration {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    pub azure_id: Option<String>,
+    pub name: String,
+    pub path: String,
+    pub start_date: Option<DateTime<Utc>>,
+    pub end_date: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IterationResponse {
+    #[serde(flatten)]
+    pub iteration: Iteration,
+    // past, current, future or unscheduled, from the dates
+    pub state: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct Area {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    pub parent_id: Option<Uuid>,
+    pub azure_id: Option<String>,
+    pub name: String,
+    pub path: String,
+    pub team_id: Option<Uuid>, // owner of this area and the ones below it
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AreaNode {
+    #[serde(flatten)]
+    pub area: Area,
+    pub children: Vec<AreaNode>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct TagUsage {
+    pub name: String,
+    pub project_id: Uuid,
+    pub count: i64, // work items of the project carrying the tag
+}
+
+// Search results carry the rank and <mark>-highlighted snippets of the matched text,
+// HTML-escaped so they can be rendered as markup
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct WorkItemSearchHit {
+    pub id: Uuid,
+    pub azure_id: Option<String>,
+    pub title: String,
+    pub w_type: String,
+    pub state: String,
+    pub project: Uuid,
+    pub assigned_to_id: Option<Uuid>,
+    pub tags: Option<Vec<String>>,
+    pub rank: f32,
+    pub title_highlight: String,
+    pub description_highlight: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct ProjectSearchHit {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub rank: f32,
+    pub name_highlight: Option<String>,
+    pub description_highlight: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct UserSearchHit {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub rank: f32,
+    pub name_highlight: Option<String>,
+    pub email_highlight: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct WorkItemRevision {
+    pub work_item_id: Uuid,
+    pub revision: i32,
+    pub operation: String,
+    pub changed_by: Option<Uuid>, // None for Azure DevOps syncs
+    pub changed_at: DateTime<Utc>,
+    pub changes: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct Comment {
+    pub id: Uuid,
+    pub work_item_id: Uuid,
+    pub parent_id: Option<Uuid>, // comment this one replies to
+    pub author_id: Uuid,
+    pub text: Option<String>, // None once deleted
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub mentions: Vec<Uuid>, // mentioned user ids
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProcessTemplate {
+    pub name: String,
+    pub description: Option<String>,
+    pub work_item_types: Vec<ProcessWorkItemType>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProcessWorkItemType {
+    pub name: String,
+    pub states: Vec<ProcessState>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProcessState {
+    pub name: String,
+    pub category: String,
+    // states this one may move to
+    #[serde(default)]
+    pub transitions: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Notification {
     pub id: i32,
@@ -172,3 +336,48 @@ pub struct Notification {
 //     pub receiver_id: Uuid,
 //     pub message: Option<String>,
 // }
+
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct SyncRun {
+    pub id: i32,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub status: String,
+    pub full_sync: bool,
+    pub projects: i32,
+    pub teams: i32,
+    pub users: i32,
+    pub work_items: i32,
+    pub error: Option<String>,
+}
+
+// The secret only leaves the API once, in the response that created the webhook
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub events: Vec<String>,
+    pub description: Option<String>,
+    pub active: bool,
+    pub created_by_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub webhook_id: Uuid,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    pub response_status: Option<i32>,
+    pub response_body: Option<String>,
+    pub error: Option<String>,
+    pub replay_of: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,273 @@
+use actix_web::{
+    delete, get, patch, post,
+    web::{Data, Json, Path, Query},
+    HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    auth::CurrentUser,
+    error::AppError,
+    listing::{Listing, Page, SortField},
+    live_feed,
+    model::{Notification, User},
+    policy::Permission,
+    schema::{CreateNotificationRequest, NotificationFilterOptions},
+    AppState,
+};
+
+const NOTIFICATION_SORT_FIELDS: &[SortField] =
+    &[SortField::new("creation_time", "n.creation_time", "timestamptz").nullable()];
+
+// Flat row of a notification joined with its sender and receiver
+#[derive(sqlx::FromRow)]
+struct NotificationRow {
+    id: i32,
+    subject: Option<String>,
+    sender_id: Uuid,
+    receiver_id: Uuid,
+    message: Option<String>,
+    creation_time: DateTime<Utc>,
+    closed: bool,
+    sender_azure_id: Option<String>,
+    sender_name: Option<String>,
+    sender_email: Option<String>,
+    sender_role: String,
+    receiver_azure_id: Option<String>,
+    receiver_name: Option<String>,
+    receiver_email: Option<String>,
+    receiver_role: String,
+}
+
+impl From<NotificationRow> for Notification {
+    fn from(row: NotificationRow) -> Self {
+        Notification {
+            id: row.id,
+            subject: row.subject,
+            sender_id: row.sender_id,
+            receiver_id: row.receiver_id,
+            message: row.message,
+            creation_time: row.creation_time,
+            closed: row.closed,
+            sender: Some(User {
+                id: row.sender_id,
+                azure_id: row.sender_azure_id,
+                name: row.sender_name,
+                email: row.sender_email,
+                role: row.sender_role,
+            }),
+            receiver: Some(User {
+                id: row.receiver_id,
+                azure_id: row.receiver_azure_id,
+                name: row.receiver_name,
+                email: row.receiver_email,
+                role: row.receiver_role,
+            }),
+        }
+    }
+}
+
+pub async fn fetch_notification(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    notification_id: i32,
+) -> Result<Notification, sqlx::Error> {
+    let row = sqlx::query_as!(
+        NotificationRow,
+        r#"SELECT n.id, n.subject, n.sender_id, n.reciever_id AS receiver_id, n.message,
+            n.creation_time AS "creation_time!", n.closed AS "closed!",
+            s.azure_id AS sender_azure_id, s.name AS sender_name,
+            s.email AS sender_email, s.role AS sender_role,
+            r.azure_id AS receiver_azure_id, r.name AS receiver_name,
+            r.email AS receiver_email, r.role AS receiver_role
+        FROM notification n
+        JOIN users s ON s.id = n.sender_id
+        JOIN users r ON r.id = n.reciever_id
+        WHERE n.id = $1"#,
+        notification_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(row.into())
+}
+
+// Notifications belong to their receiver
+async fn find_notification_receiver(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    notification_id: i32,
+) -> Result<Uuid, AppError> {
+    sqlx::query_scalar!(
+        "SELECT reciever_id FROM notification WHERE id = $1",
+        notification_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Notification {} not found", notification_id)))
+}
+
+#[post("/notifications")]
+async fn create_notification(
+    current: CurrentUser,
+    body: Json<CreateNotificationRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+    current
+        .authorize(&data.db, Permission::ActAsUser(body.sender_id))
+        .await?;
+
+    let notification_id = sqlx::query_scalar!(
+        "INSERT INTO notification (subject, sender_id, reciever_id, message) VALUES ($1,$2,$3,$4) RETURNING id",
+        body.subject,
+        body.sender_id,
+        body.receiver_id,
+        body.message
+    )
+    .fetch_one(&data.db)
+    .await?;
+    live_feed::announce_notification(&data.db, notification_id, body.receiver_id).await?;
+
+    let notification = fetch_notification(&data.db, notification_id).await?;
+
+    Ok(HttpResponse::Created().json(json!({"status":"success", "data":notification})))
+}
+
+#[get("/users/{id}/notifications")]
+async fn get_user_notifications(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    opts: Query<NotificationFilterOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner();
+    current
+        .authorize(&data.db, Permission::ActAsUser(user_id))
+        .await?;
+
+    let mut listing = Listing::new(
+        "n.id, n.subject, n.sender_id, n.reciever_id AS receiver_id, n.message,
+            n.creation_time, COALESCE(n.closed, FALSE) AS closed,
+            s.azure_id AS sender_azure_id, s.name AS sender_name,
+            s.email AS sender_email, s.role AS sender_role,
+            r.azure_id AS receiver_azure_id, r.name AS receiver_name,
+            r.email AS receiver_email, r.role AS receiver_role",
+        "notification n
+        JOIN users s ON s.id = n.sender_id
+        JOIN users r ON r.id = n.reciever_id",
+        "n.id",
+        "int4",
+        NOTIFICATION_SORT_FIELDS,
+        "-creation_time",
+    );
+    listing
+        .filter("n.reciever_id = $", Some(user_id))
+        .filter("n.closed = $", opts.closed);
+
+    let page: Page<NotificationRow> = listing
+        .fetch(
+            &data.db,
+            opts.sort.as_deref(),
+            opts.cursor.as_deref(),
+            opts.limit,
+        )
+        .await?;
+
+    Ok(page.map(Notification::from).into_response("notifications"))
+}
+
+#[patch("/notifications/{id}/close")]
+async fn close_notification(
+    current: CurrentUser,
+    path: Path<i32>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let notification_id = path.into_inner();
+    let receiver_id = find_notification_receiver(&data.db, notification_id).await?;
+    current
+        .authorize(&data.db, Permission::ActAsUser(receiver_id))
+        .await?;
+
+    sqlx::query!(
+        "UPDATE notification SET closed = TRUE WHERE id = $1",
+        notification_id
+    )
+    .execute(&data.db)
+    .await?;
+
+    let notification = fetch_notification(&data.db, notification_id).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":notification})))
+}
+
+#[patch("/users/{id}/notifications/close")]
+async fn close_all_notifications(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let user_id = path.into_inner();
+    current
+        .authorize(&data.db, Permission::ActAsUser(user_id))
+        .await?;
+
+    let result = sqlx::query!(
+        "UPDATE notification SET closed = TRUE WHERE reciever_id = $1 AND closed IS NOT TRUE",
+        user_id
+    )
+    .execute(&data.db)
+    .await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status":"success",
+        "result": result.rows_affected()
+    })))
+}
+
+#[delete("/notifications/{id}")]
+async fn delete_notification(
+    current: CurrentUser,
+    path: Path<i32>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let notification_id = path.into_inner();
+    let receiver_id = find_notification_receiver(&data.db, notification_id).await?;
+    current
+        .authorize(&data.db, Permission::ActAsUser(receiver_id))
+        .await?;
+
+    sqlx::query!("DELETE FROM notification WHERE id = $1", notification_id)
+        .execute(&data.db)
+        .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Writes a notification inside the caller's transaction and announces it to the
+// receiver's live feed. Changes a user makes to their own work are not echoed back to them.
+pub async fn notify(
+    tx: &mut Transaction<'_, Postgres>,
+    sender_id: Uuid,
+    receiver_id: Uuid,
+    subject: &str,
+    message: &str,
+) -> Result<(), sqlx::Error> {
+    if sender_id == receiver_id {
+        return Ok(());
+    }
+
+    let notification_id = sqlx::query_scalar!(
+        "INSERT INTO notification (subject, sender_id, reciever_id, message) VALUES ($1,$2,$3,$4) RETURNING id",
+        subject,
+        sender_id,
+        receiver_id,
+        message
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    live_feed::announce_notification(&mut *tx, notification_id, receiver_id).await
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+
+use log::info;
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::{auth::CurrentUser, error::AppError};
+
+// Global roles stored in users.role
+pub const SYSTEM_ADMIN: &str = "admin";
+pub const SYSTEM_USER: &str = "user";
+// Team roles stored in team_users.role
+pub const TEAM_ADMIN: &str = "admin";
+pub const TEAM_MEMBER: &str = "member";
+
+/// Actions a handler must be allowed to perform before touching the database.
+///
+/// System admins hold every permission; the others are granted through the
+/// caller's own identity or their role in the owning team.
+#[derive(Debug, Clone, Copy)]
+pub enum Permission {
+    // any authenticated user
+    Read,
+    // create, import or delete users and change global roles
+    ManageUsers,
+    // edit a single user's profile, granted to that user
+    EditUser(Uuid),
+    // create a new team, whose creator becomes its admin
+    CreateTeam,
+    // edit a team, its membership and its projects
+    ManageTeam(Uuid),
+    // import teams and projects that have no owning team, and define process templates
+    ManageOrganization,
+    // create and edit work items of a project
+    WriteProject(Uuid),
+    // read or act on another user's notifications and comments, or create work items as them
+    ActAsUser(Uuid),
+    // trigger and inspect Azure DevOps sync runs
+    ManageSync,
+    // subscribe to events and inspect webhook deliveries
+    ManageWebhooks,
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Permission::Read => write!(f, "read"),
+            Permission::ManageUsers => write!(f, "users.manage"),
+            Permission::EditUser(id) => write!(f, "users.edit on user {}", id),
+            Permission::CreateTeam => write!(f, "teams.create"),
+            Permission::ManageTeam(id) => write!(f, "teams.manage on team {}", id),
+            Permission::ManageOrganization => write!(f, "organization.manage"),
+            Permission::WriteProject(id) => write!(f, "projects.write on project {}", id),
+            Permission::ActAsUser(id) => write!(f, "users.act_as on user {}", id),
+            Permission::ManageSync => write!(f, "sync.manage"),
+            Permission::ManageWebhooks => write!(f, "webhooks.manage"),
+        }
+    }
+}
+
+impl Permission {
+    // Who holds the permission, used to explain a denial
+    fn requirement(&self) -> &'static str {
+        match self {
+            Permission::Read | Permission::CreateTeam => "any authenticated user",
+            Permission::ManageUsers
+            | Permission::ManageOrganization
+            | Permission::ManageSync
+            | Permission::ManageWebhooks => "the system admin role",
+            Permission::EditUser(_) | Permission::ActAsUser(_) => {
+                "being that user or a system admin"
+            }
+            Permission::ManageTeam(_) => "the team admin role or the system admin role",
+            Permission::WriteProject(_) => {
+                "membership in the project's team or the system admin role"
+            }
+        }
+    }
+}
+
+impl CurrentUser {
+    pub fn is_system_admin(&self) -> bool {
+        self.0.role == SYSTEM_ADMIN
+    }
+
+    async fn team_role(
+        &self,
+        db: &Pool<Postgres>,
+        team_id: Uuid,
+    ) -> Result<Option<String>, AppError> {
+        Ok(sqlx::query_scalar!(
+            "SELECT role FROM team_users WHERE team_id = $1 AND user_id = $2",
+            team_id,
+            self.0.id
+        )
+        .fetch_optional(db)
+        .await?)
+    }
+
+    async fn is_project_member(
+        &self,
+        db: &Pool<Postgres>,
+        project_id: Uuid,
+    ) -> Result<bool, AppError> {
+        Ok(sqlx::query_scalar!(
+            r#"SELECT EXISTS(
+                SELECT 1 FROM projects p
+                JOIN team_users tu ON tu.team_id = p.team_id
+                WHERE p.id = $1 AND tu.user_id = $2
+            ) AS "exists!""#,
+            project_id,
+            self.0.id
+        )
+        .fetch_one(db)
+        .await?)
+    }
+
+    /// Fails with a 403 naming the missing permission unless the caller holds it.
+    pub async fn authorize(
+        &self,
+        db: &Pool<Postgres>,
+        permission: Permission,
+    ) -> Result<(), AppError> {
+        if self.is_system_admin() {
+            return Ok(());
+        }
+
+        let granted = match permission {
+            Permission::Read | Permission::CreateTeam => true,
+            Permission::ManageUsers
+            | Permission::ManageOrganization
+            | Permission::ManageSync
+            | Permission::ManageWebhooks => false,
+            Permission::EditUser(user_id) | Permission::ActAsUser(user_id) => user_id == self.0.id,
+            Permission::ManageTeam(team_id) => {
+                self.team_role(db, team_id).await?.as_deref() == Some(TEAM_ADMIN)
+            }
+            Permission::WriteProject(project_id) => self.is_project_member(db, project_id).await?,
+        };
+
+        if granted {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(format!(
+                "Missing permission {}: requires {}",
+                permission,
+                permission.requirement()
+            )))
+        }
+    }
+}
+
+/// Grants the system admin role to the users listed in `SYSTEM_ADMINS`
+/// (comma separated azure ids or emails) so a fresh install has an admin.
+pub async fn promote_system_admins(db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+    let Ok(admins) = std::env::var("SYSTEM_ADMINS") else {
+        return Ok(());
+    };
+    let admins: Vec<String> = admins
+        .split(',')
+        .map(|admin| admin.trim().to_lowercase())
+        .filter(|admin| !admin.is_empty())
+        .collect();
+
+    let result = sqlx::query!(
+        "UPDATE users SET role = $1
+        WHERE role <> $1 AND (lower(azure_id) = ANY($2) OR lower(email) = ANY($2))",
+        SYSTEM_ADMIN,
+        &admins
+    )
+    .execute(db)
+    .await?;
+    info!("Promoted {} users to system admin", result.rows_affected());
+    Ok(())
+}
@@ -0,0 +1,241 @@
+use sqlx::{Executor, Postgres};
+
+use crate::{
+    error::AppError,
+    model::{ProcessState, ProcessTemplate, ProcessWorkItemType, WorkItem},
+};
+
+/// State categories of Azure DevOps, which every state belongs to.
+pub const STATE_CATEGORIES: &[&str] =
+    &["Proposed", "InProgress", "Resolved", "Completed", "Removed"];
+
+/// Validates the work item types of a template definition before it is saved:
+/// names are unique per level ignoring case and transitions name states of the
+/// same type, which they are rewritten to.
+pub fn check_definition(work_item_types: &mut [ProcessWorkItemType]) -> Result<(), AppError> {
+    let mut type_names: Vec<String> = Vec::new();
+    for w_type in work_item_types.iter_mut() {
+        w_type.name = w_type.name.trim().to_string();
+        if w_type.name.is_empty() {
+            return Err(AppError::Validation(
+                "Work item type names must not be blank".to_string(),
+            ));
+        }
+        if type_names.contains(&w_type.name.to_lowercase()) {
+            return Err(AppError::Validation(format!(
+                "Work item type {} is defined twice",
+                w_type.name
+            )));
+        }
+        type_names.push(w_type.name.to_lowercase());
+        if w_type.states.is_empty() {
+            return Err(AppError::Validation(format!(
+                "Work item type {} needs at least one state",
+                w_type.name
+            )));
+        }
+
+        let mut state_names: Vec<String> = Vec::new();
+        for state in w_type.states.iter_mut() {
+            state.name = state.name.trim().to_string();
+            if state.name.is_empty() {
+                return Err(AppError::Validation(format!(
+                    "State names of {} must not be blank",
+                    w_type.name
+                )));
+            }
+            if state_names
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(&state.name))
+            {
+                return Err(AppError::Validation(format!(
+                    "State {} of {} is defined twice",
+                    state.name, w_type.name
+                )));
+            }
+            if !STATE_CATEGORIES.contains(&state.category.as_str()) {
+                return Err(AppError::Validation(format!(
+                    "State {} of {} has category {}, expected one of: {}",
+                    state.name,
+                    w_type.name,
+                    state.category,
+                    STATE_CATEGORIES.join(", ")
+                )));
+            }
+            state_names.push(state.name.clone());
+        }
+
+        for state in w_type.states.iter_mut() {
+            let mut transitions: Vec<String> = Vec::new();
+            for to_state in &state.transitions {
+                let to_state = state_names
+                    .iter()
+                    .find(|name| name.eq_ignore_ascii_case(to_state.trim()))
+                    .ok_or_else(|| {
+                        AppError::Validation(format!(
+                            "{} of {} moves to {}, which is not one of its states",
+                            state.name, w_type.name, to_state
+                        ))
+                    })?;
+                if *to_state != state.name && !transitions.contains(to_state) {
+                    transitions.push(to_state.clone());
+                }
+            }
+            state.transitions = transitions;
+        }
+    }
+    Ok(())
+}
+
+/// Loads a process template with its work item types, states and transitions.
+/// Names are matched case-insensitively.
+pub async fn find_process<'e, E>(
+    executor: E,
+    name: &str,
+) -> Result<Option<ProcessTemplate>, AppError>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let rows = sqlx::query!(
+        r#"SELECT pt.name AS template, pt.description, wt.name AS "w_type?",
+            s.name AS "state?", s.category AS "category?",
+            ARRAY(
+                SELECT tr.to_state FROM process_transitions tr
+                JOIN process_states ts
+                    ON ts.template = tr.template AND ts.w_type = tr.w_type AND ts.name = tr.to_state
+                WHERE tr.template = s.template AND tr.w_type = s.w_type AND tr.from_state = s.name
+                ORDER BY ts.position
+            ) AS "transitions!"
+        FROM process_templates pt
+        LEFT JOIN process_work_item_types wt ON wt.template = pt.name
+        LEFT JOIN process_states s ON s.template = wt.template AND s.w_type = wt.name
+        WHERE lower(pt.name) = lower($1)
+        ORDER BY wt.position, s.position"#,
+        name
+    )
+    .fetch_all(executor)
+    .await?;
+
+    let Some(first) = rows.first() else {
+        return Ok(None);
+    };
+    let mut process = ProcessTemplate {
+        name: first.template.clone(),
+        description: first.description.clone(),
+        work_item_types: Vec::new(),
+    };
+    for row in rows {
+        let Some(w_type) = row.w_type else {
+            continue;
+        };
+        if process.work_item_types.last().map(|last| &last.name) != Some(&w_type) {
+            process.work_item_types.push(ProcessWorkItemType {
+                name: w_type,
+                states: Vec::new(),
+            });
+        }
+        if let (Some(name), Some(category), Some(current)) =
+            (row.state, row.category, process.work_item_types.last_mut())
+        {
+            current.states.push(ProcessState {
+                name,
+                category,
+                transitions: row.transitions,
+            });
+        }
+    }
+    Ok(Some(process))
+}
+
+/// The stored spelling of a process template name, or a 422 when none matches.
+pub async fn find_template_name<'e, E>(executor: E, name: &str) -> Result<String, AppError>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    sqlx::query_scalar!(
+        "SELECT name FROM process_templates WHERE lower(name) = lower($1)",
+        name
+    )
+    .fetch_optional(executor)
+    .await?
+    .ok_or_else(|| AppError::Unprocessable(format!("Process template {} not found", name)))
+}
+
+impl ProcessTemplate {
+    fn work_item_type(&self, name: &str) -> Result<&ProcessWorkItemType, AppError> {
+        self.work_item_types
+            .iter()
+            .find(|w_type| w_type.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                let allowed: Vec<_> = self
+                    .work_item_types
+                    .iter()
+                    .map(|w_type| w_type.name.as_str())
+                    .collect();
+                AppError::Validation(format!(
+                    "Work item type '{}' is not part of the {} process, expected one of: {}",
+                    name,
+                    self.name,
+                    allowed.join(", ")
+                ))
+            })
+    }
+
+    /// Canonical type and state names for a new work item.
+    pub fn check_new(&self, w_type: &str, state: &str) -> Result<(String, String), AppError> {
+        let w_type = self.work_item_type(w_type)?;
+        let state = w_type
+            .states
+            .iter()
+            .find(|candidate| candidate.name.eq_ignore_ascii_case(state))
+            .ok_or_else(|| {
+                let allowed: Vec<_> = w_type
+                    .states
+                    .iter()
+                    .map(|state| state.name.as_str())
+                    .collect();
+                AppError::Validation(format!(
+                    "State '{}' is not valid for {} in the {} process, expected one of: {}",
+                    state,
+                    w_type.name,
+                    self.name,
+                    allowed.join(", ")
+                ))
+            })?;
+        Ok((w_type.name.clone(), state.name.clone()))
+    }
+
+    /// Canonical type and state names for an updated work item, rejecting
+    /// state changes the workflow does not allow.
+    pub fn check_change(
+        &self,
+        before: &WorkItem,
+        w_type: &str,
+        state: &str,
+    ) -> Result<(String, String), AppError> {
+        let (w_type, state) = self.check_new(w_type, state)?;
+        // a new type starts over in that type's workflow
+        if !w_type.eq_ignore_ascii_case(&before.w_type) || state.eq_ignore_ascii_case(&before.state)
+        {
+            return Ok((w_type, state));
+        }
+
+        let from = self
+            .work_item_type(&w_type)?
+            .states
+            .iter()
+            .find(|candidate| candidate.name.eq_ignore_ascii_case(&before.state));
+        match from {
+            Some(from) if !from.transitions.contains(&state) => Err(AppError::Validation(format!(
+                "{} cannot move from {} to {} in the {} process, allowed: {}",
+                w_type,
+                from.name,
+                state,
+                self.name,
+                from.transitions.join(", ")
+            ))),
+            // a state recorded before the process was enforced has no transitions to check
+            _ => Ok((w_type, state)),
+        }
+    }
+}
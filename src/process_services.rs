@@ -0,0 +1,208 @@
+use actix_web::{
+    delete, get, put,
+    web::{Data, Json, Path},
+    HttpResponse,
+};
+use serde_json::json;
+use validator::Validate;
+
+use crate::{
+    auth::CurrentUser, error::AppError, policy::Permission, process, schema::UpsertProcessRequest,
+    AppState,
+};
+
+#[get("/processes")]
+async fn get_all_processes(
+    current: CurrentUser,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let names = sqlx::query_scalar!("SELECT name FROM process_templates ORDER BY name")
+        .fetch_all(&data.db)
+        .await?;
+    let mut processes = Vec::with_capacity(names.len());
+    for name in names {
+        if let Some(process) = process::find_process(&data.db, &name).await? {
+            processes.push(process);
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "success",
+        "result": processes.len(),
+        "processes": processes
+    })))
+}
+
+#[get("/processes/{name}")]
+async fn get_process(
+    current: CurrentUser,
+    path: Path<String>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let name = path.into_inner();
+    let process = process::find_process(&data.db, &name)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Process template {} not found", name)))?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success","process":process})))
+}
+
+// Creates a template or replaces its whole definition. Work items keep their type and
+// state when these go away, and have to move to defined ones on their next change.
+#[put("/processes/{name}")]
+async fn upsert_process(
+    current: CurrentUser,
+    path: Path<String>,
+    body: Json<UpsertProcessRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+    current
+        .authorize(&data.db, Permission::ManageOrganization)
+        .await?;
+
+    let name = path.into_inner().trim().to_string();
+    if name.is_empty() {
+        return Err(AppError::Validation(
+            "Process template names must not be blank".to_string(),
+        ));
+    }
+    let mut body = body.into_inner();
+    process::check_definition(&mut body.work_item_types)?;
+
+    let mut tx = data.db.begin().await?;
+
+    //an existing template keeps its spelling, so projects naming it stay linked
+    let existing = sqlx::query_scalar!(
+        "SELECT name FROM process_templates WHERE lower(name) = lower($1) FOR UPDATE",
+        name
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    let created = existing.is_none();
+    let name = match existing {
+        Some(existing) => {
+            sqlx::query!(
+                "UPDATE process_templates SET description = $1 WHERE name = $2",
+                body.description,
+                existing
+            )
+            .execute(&mut tx)
+            .await?;
+            //states and transitions go with their types
+            sqlx::query!(
+                "DELETE FROM process_work_item_types WHERE template = $1",
+                existing
+            )
+            .execute(&mut tx)
+            .await?;
+            existing
+        }
+        None => {
+            sqlx::query!(
+                "INSERT INTO process_templates (name, description) VALUES ($1, $2)",
+                name,
+                body.description
+            )
+            .execute(&mut tx)
+            .await?;
+            name
+        }
+    };
+
+    for (type_position, w_type) in (1..).zip(&body.work_item_types) {
+        sqlx::query!(
+            "INSERT INTO process_work_item_types (template, name, position) VALUES ($1, $2, $3)",
+            name,
+            w_type.name,
+            type_position
+        )
+        .execute(&mut tx)
+        .await?;
+        for (state_position, state) in (1..).zip(&w_type.states) {
+            sqlx::query!(
+                "INSERT INTO process_states (template, w_type, name, category, position)
+                VALUES ($1, $2, $3, $4, $5)",
+                name,
+                w_type.name,
+                state.name,
+                state.category,
+                state_position
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        for state in &w_type.states {
+            sqlx::query!(
+                "INSERT INTO process_transitions (template, w_type, from_state, to_state)
+                SELECT $1, $2, $3, UNNEST($4::varchar[])",
+                name,
+                w_type.name,
+                state.name,
+                &state.transitions
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+    }
+
+    let process = process::find_process(&mut tx, &name)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Process template {} not found", name)))?;
+
+    tx.commit().await?;
+
+    let process_response = json!({"status":"success","process":process});
+    if created {
+        Ok(HttpResponse::Created().json(process_response))
+    } else {
+        Ok(HttpResponse::Ok().json(process_response))
+    }
+}
+
+#[delete("/processes/{name}")]
+async fn delete_process(
+    current: CurrentUser,
+    path: Path<String>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current
+        .authorize(&data.db, Permission::ManageOrganization)
+        .await?;
+
+    let name = path.into_inner();
+    let mut tx = data.db.begin().await?;
+
+    let template = sqlx::query_scalar!(
+        "SELECT name FROM process_templates WHERE lower(name) = lower($1) FOR UPDATE",
+        name
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Process template {} not found", name)))?;
+
+    let projects = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM projects WHERE lower(template) = lower($1)"#,
+        template
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    if projects > 0 {
+        return Err(AppError::Conflict(format!(
+            "Process template {} is used by {} projects",
+            template, projects
+        )));
+    }
+
+    sqlx::query!("DELETE FROM process_templates WHERE name = $1", template)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
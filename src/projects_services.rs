@@ -1,108 +1,424 @@
 use actix_web::{
-    get, post,
-    web::{Data, Json, Query},
-    HttpResponse, Responder,
+    delete, get, patch, post, put,
+    web::{Data, Json, Path, Query},
+    HttpResponse,
 };
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres, Transaction};
+use uuid::Uuid;
 
 use crate::{
-    model::{ProjectModel, Team},
-    schema::{CreateProjectRequest, FilterOptions},
-    AppState,
+    area_services,
+    auth::CurrentUser,
+    error::AppError,
+    iteration_services,
+    listing::{contains_pattern, Listing, Page, SortField},
+    model::{ProjectModel, Team, Upserted},
+    policy::Permission,
+    process,
+    schema::{CreateProjectRequest, ProjectFilterOptions, UpdateProjectRequest},
+    webhooks, AppState,
 };
 use serde_json::json;
+use validator::Validate;
+
+const PROJECT_SORT_FIELDS: &[SortField] = &[
+    SortField::new("name", "name", "varchar").nullable(),
+    SortField::new("begin_date", "begin_date", "timestamptz").nullable(),
+    SortField::new("end_date", "end_date", "timestamptz").nullable(),
+];
+
+//team admins manage their team's projects, projects without a team are organization-wide
+pub fn manage_permission(team_id: Option<Uuid>) -> Permission {
+    match team_id {
+        Some(team_id) => Permission::ManageTeam(team_id),
+        None => Permission::ManageOrganization,
+    }
+}
+
+async fn find_team(db: &Pool<Postgres>, team_id: Uuid) -> Result<Team, AppError> {
+    sqlx::query_as!(Team, "SELECT * FROM teams WHERE id = $1", team_id)
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| AppError::Unprocessable(format!("Team {} not found", team_id)))
+}
+
+fn check_date_range(
+    begin_date: Option<DateTime<Utc>>,
+    end_date: Option<DateTime<Utc>>,
+) -> Result<(), AppError> {
+    match (begin_date, end_date) {
+        (Some(begin), Some(end)) if end < begin => Err(AppError::Validation(
+            "end_date must not be before begin_date".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
 
 #[post("/projects")]
-async fn create_project(body: Json<CreateProjectRequest>, data: Data<AppState>) -> impl Responder {
-    let mut tx = match data.db.begin().await {
-        Ok(tx) => tx,
-        Err(error) => {
-            return HttpResponse::InternalServerError().json(json!({
-                "status":"error",
-                "message": format!("Failed to start transaction: {}",error)
-            }));
-        }
+async fn create_project(
+    current: CurrentUser,
+    body: Json<CreateProjectRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+    check_date_range(body.begin_date, body.end_date)?;
+    current
+        .authorize(&data.db, manage_permission(body.team_id))
+        .await?;
+
+    if let Some(team_id) = body.team_id {
+        find_team(&data.db, team_id).await?;
+    }
+    let template = match &body.template {
+        Some(template) => Some(process::find_template_name(&data.db, template).await?),
+        None => None,
     };
 
-    // let team = match sqlx::query_as!(Team, "SELECT * FROM teams WHERE id = $1", body.team_id)
-    //     .fetch_one(&mut tx)
-    //     .await
-    // {
-    //     Ok(team) => team,
-    //     Err(error) => {
-    //         return HttpResponse::InternalServerError().json(json!({
-    //             "status":"error",
-    //             "message": format!("{:?}",error)
-    //         }));
-    //     }
-    // };
+    let mut tx = data.db.begin().await?;
 
     //insert project
-    let project = match sqlx::query_as!(
+    let project = sqlx::query_as!(
         ProjectModel,
-        "INSERT INTO projects (azure_id, name, description, url, template) VALUES ($1,$2,$3,$4,$5) RETURNING *",
+        "INSERT INTO projects (azure_id, name, description, url, template, begin_date, end_date, team_id)
+        VALUES ($1,$2,$3,$4,$5,$6,$7,$8) RETURNING *",
         body.azure_id,
         body.name,
         body.description,
         body.url,
-        body.template
+        template,
+        body.begin_date,
+        body.end_date,
+        body.team_id
     ).fetch_one(&mut tx)
-    .await{
-        Ok(project)=> project,
-        Err(error) =>{
-            return HttpResponse::InternalServerError().json(json!({
-                "status":"error",
-                "message": format!("{:?}",error)
-            }));
+    .await?;
+    webhooks::enqueue(
+        &mut tx,
+        "project.created",
+        Some(current.0.id),
+        json!({"project": project}),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Created().json(json!({"status":"success","project":project})))
+}
+
+// Projects matching the list filters, read from `from`, which has to be aliased projects
+pub fn project_listing(from: &'static str, opts: &ProjectFilterOptions) -> Listing {
+    let mut listing = Listing::new("*", from, "id", "uuid", PROJECT_SORT_FIELDS, "name");
+    listing
+        .filter("name ILIKE $", opts.name.as_deref().map(contains_pattern))
+        .filter("team_id = $", opts.team_id)
+        .filter("begin_date >= $", opts.begins_after)
+        .filter("end_date <= $", opts.ends_before);
+    listing
+}
+
+#[get("/projects")]
+async fn get_all_projects(
+    current: CurrentUser,
+    opts: Query<ProjectFilterOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let page: Page<ProjectModel> = project_listing("projects", &opts)
+        .fetch(
+            &data.db,
+            opts.sort.as_deref(),
+            opts.cursor.as_deref(),
+            opts.limit,
+        )
+        .await?;
+
+    Ok(page.into_response("projects"))
+}
+
+#[get("/projects/{id}")]
+async fn get_project_by_id(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let project_id = path.into_inner();
+
+    let project = sqlx::query_as!(
+        ProjectModel,
+        "SELECT * FROM projects WHERE id = $1",
+        project_id
+    )
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Project {} not found", project_id)))?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success","project":project})))
+}
+
+#[get("/projects/{id}/process")]
+async fn get_project_process(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let project_id = path.into_inner();
+
+    let template = sqlx::query_scalar!("SELECT template FROM projects WHERE id = $1", project_id)
+        .fetch_optional(&data.db)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Project {} not found", project_id)))?
+        .ok_or_else(|| {
+            AppError::NotFound(format!("Project {} has no process template", project_id))
+        })?;
+
+    let process = process::find_process(&data.db, &template)
+        .await?
+        .ok_or_else(|| {
+            AppError::NotFound(format!("Process template {} is not defined", template))
+        })?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success","process":process})))
+}
+
+/// Area and iteration paths are rooted at the project name, so renaming a project
+/// moves them, and the paths its work items carry, to the new name.
+pub async fn move_project_paths(
+    tx: &mut Transaction<'_, Postgres>,
+    changed_by: Option<Uuid>,
+    before: &ProjectModel,
+    after: &ProjectModel,
+) -> Result<(), sqlx::Error> {
+    if let (Some(old_name), Some(new_name)) = (&before.name, &after.name) {
+        if old_name != new_name {
+            area_services::move_paths(tx, changed_by, after.id, old_name, new_name).await?;
+            iteration_services::move_paths(tx, changed_by, after.id, old_name, new_name).await?;
         }
-    };
+    }
+    Ok(())
+}
+
+#[patch("/projects/{id}")]
+async fn update_project_by_id(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    body: Json<UpdateProjectRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
+    let project_id = path.into_inner();
 
-    if let Err(e) = tx.commit().await {
-        return HttpResponse::InternalServerError().json(json!({
-            "status": "error",
-            "message": format!("Failed to commit transaction: {}", e)
-        }));
+    let mut tx = data.db.begin().await?;
+
+    let before = sqlx::query_as!(
+        ProjectModel,
+        "SELECT * FROM projects WHERE id = $1 FOR UPDATE",
+        project_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Project {} not found", project_id)))?;
+    current
+        .authorize(&data.db, manage_permission(before.team_id))
+        .await?;
+
+    //reassigning needs the right to manage the receiving team as well
+    if let Some(team_id) = body
+        .team_id
+        .flatten()
+        .filter(|team_id| before.team_id != Some(*team_id))
+    {
+        current
+            .authorize(&data.db, Permission::ManageTeam(team_id))
+            .await?;
+        find_team(&data.db, team_id).await?;
     }
 
-    let project_response = ProjectModel {
-        id: project.id,
-        azure_id: project.azure_id,
-        name: project.name,
-        description: project.description,
-        url: project.url,
-        template: project.template,
-        team_id: None,
+    //an omitted field keeps its value, an explicit null clears it
+    let begin_date = body.begin_date.unwrap_or(before.begin_date);
+    let end_date = body.end_date.unwrap_or(before.end_date);
+    let team_id = body.team_id.unwrap_or(before.team_id);
+    check_date_range(begin_date, end_date)?;
+    let template = match &body.template {
+        Some(template) => Some(process::find_template_name(&mut tx, template).await?),
+        None => None,
     };
 
-    HttpResponse::Ok().json(json!({"status":"success","project":project_response}))
+    let project = sqlx::query_as!(
+        ProjectModel,
+        "UPDATE projects SET
+            name = COALESCE($1, name),
+            description = COALESCE($2, description),
+            url = COALESCE($3, url),
+            template = COALESCE($4, template),
+            begin_date = $5,
+            end_date = $6,
+            team_id = $7
+        WHERE id = $8 RETURNING *",
+        body.name,
+        body.description,
+        body.url,
+        template,
+        begin_date,
+        end_date,
+        team_id,
+        project_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    move_project_paths(&mut tx, Some(current.0.id), &before, &project).await?;
+    webhooks::enqueue(
+        &mut tx,
+        "project.updated",
+        Some(current.0.id),
+        json!({"project": project, "previous": before}),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success","project":project})))
 }
 
-#[get("/project")]
-async fn get_all_projects(opts: Query<FilterOptions>, data: Data<AppState>) -> impl Responder {
-    let limit = opts.limit.unwrap_or(10);
-    let offset = (opts.page.unwrap_or(1) - 1) * limit;
+#[delete("/projects/{id}")]
+async fn delete_project(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let project_id = path.into_inner();
+
+    let mut tx = data.db.begin().await?;
 
-    match sqlx::query_as!(
+    let project = sqlx::query_as!(
         ProjectModel,
-        "SELECT * FROM projects ORDER by id LIMIT $1 OFFSET $2",
-        limit as i32,
-        offset as i32
+        "SELECT * FROM projects WHERE id = $1 FOR UPDATE",
+        project_id
     )
-    .fetch_all(&data.db)
-    .await
-    {
-        Ok(projects) => {
-            let projects_response = json!({
-                "status":"success",
-                "result": projects.len(),
-                "projects": projects
-            });
-            return HttpResponse::Ok().json(projects_response);
-        }
-        Err(error) => {
-            return HttpResponse::InternalServerError().json(json!({
-                "status":"error",
-                "message":format!("{:?}",error)
-            }));
-        }
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Project {} not found", project_id)))?;
+    current
+        .authorize(&data.db, manage_permission(project.team_id))
+        .await?;
+
+    //work items are never removed implicitly
+    let workitems = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM work_items WHERE project = $1"#,
+        project_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    if workitems > 0 {
+        return Err(AppError::Conflict(format!(
+            "Project {} still has {} work items",
+            project_id, workitems
+        )));
+    }
+
+    sqlx::query!("DELETE FROM projects WHERE id = $1", project_id)
+        .execute(&mut tx)
+        .await?;
+    webhooks::enqueue(
+        &mut tx,
+        "project.deleted",
+        Some(current.0.id),
+        json!({"project": project}),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[put("/projects/by-azure-id/{azure_id}")]
+async fn upsert_project_by_azure_id(
+    current: CurrentUser,
+    path: Path<String>,
+    body: Json<CreateProjectRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+    check_date_range(body.begin_date, body.end_date)?;
+    current
+        .authorize(&data.db, Permission::ManageOrganization)
+        .await?;
+
+    let azure_id = path.into_inner();
+    if body.azure_id.as_ref().is_some_and(|id| *id != azure_id) {
+        return Err(AppError::Validation(
+            "azure_id in the body does not match the path".to_string(),
+        ));
+    }
+
+    if let Some(team_id) = body.team_id {
+        find_team(&data.db, team_id).await?;
+    }
+    let template = match &body.template {
+        Some(template) => Some(process::find_template_name(&data.db, template).await?),
+        None => None,
+    };
+
+    let mut tx = data.db.begin().await?;
+
+    let before = sqlx::query_as!(
+        ProjectModel,
+        "SELECT * FROM projects WHERE azure_id = $1 FOR UPDATE",
+        azure_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let upserted = sqlx::query_as::<_, Upserted<ProjectModel>>(
+        "INSERT INTO projects (azure_id, name, description, url, template, begin_date, end_date, team_id)
+        VALUES ($1,$2,$3,$4,$5,$6,$7,$8)
+        ON CONFLICT (azure_id) DO UPDATE SET
+            name = EXCLUDED.name,
+            description = EXCLUDED.description,
+            url = EXCLUDED.url,
+            template = EXCLUDED.template,
+            begin_date = EXCLUDED.begin_date,
+            end_date = EXCLUDED.end_date,
+            team_id = EXCLUDED.team_id
+        RETURNING *, (xmax = 0) AS inserted",
+    )
+    .bind(&azure_id)
+    .bind(&body.name)
+    .bind(&body.description)
+    .bind(&body.url)
+    .bind(&template)
+    .bind(body.begin_date)
+    .bind(body.end_date)
+    .bind(body.team_id)
+    .fetch_one(&mut tx)
+    .await?;
+    let project = upserted.row;
+    if let Some(before) = &before {
+        move_project_paths(&mut tx, Some(current.0.id), before, &project).await?;
+    }
+    //a project inserted concurrently after the lookup has no previous state here
+    let (event, payload) = if upserted.inserted {
+        ("project.created", json!({"project": project}))
+    } else {
+        (
+            "project.updated",
+            json!({"project": project, "previous": before}),
+        )
+    };
+    webhooks::enqueue(&mut tx, event, Some(current.0.id), payload).await?;
+
+    tx.commit().await?;
+
+    let project_response = json!({"status":"success","project":project});
+    if upserted.inserted {
+        Ok(HttpResponse::Created().json(project_response))
+    } else {
+        Ok(HttpResponse::Ok().json(project_response))
     }
 }
@@ -0,0 +1,224 @@
+use actix_web::{
+    get,
+    web::{Data, Path, Query},
+    HttpResponse,
+};
+use serde_json::{json, Map, Value};
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    auth::CurrentUser,
+    error::AppError,
+    listing::{Listing, Page, SortField},
+    live_feed,
+    model::{Comment, WorkItem, WorkItemRevision},
+    policy::Permission,
+    schema::RevisionFilterOptions,
+    webhooks, AppState,
+};
+
+const REVISION_SORT_FIELDS: &[SortField] = &[
+    SortField::new("revision", "revision", "int4"),
+    SortField::new("changed_at", "changed_at", "timestamptz"),
+];
+
+// Not part of the diff: the id never changes, changed_date and azure_rev move on every write
+const UNTRACKED_FIELDS: &[&str] = &["id", "changed_date", "azure_rev"];
+
+fn fields(workitem: Option<&WorkItem>) -> Map<String, Value> {
+    match workitem.map(serde_json::to_value) {
+        Some(Ok(Value::Object(fields))) => fields,
+        _ => Map::new(),
+    }
+}
+
+// {"field": {"old": ..., "new": ...}} for every tracked field that differs
+fn diff(before: Option<&WorkItem>, after: Option<&WorkItem>) -> Map<String, Value> {
+    let before = fields(before);
+    let after = fields(after);
+    let mut names: Vec<&String> = before.keys().chain(after.keys()).collect();
+    names.sort();
+    names.dedup();
+
+    let mut changes = Map::new();
+    for name in names {
+        let old = before.get(name).unwrap_or(&Value::Null);
+        let new = after.get(name).unwrap_or(&Value::Null);
+        if old != new && !UNTRACKED_FIELDS.contains(&name.as_str()) {
+            changes.insert(name.clone(), json!({"old": old, "new": new}));
+        }
+    }
+    changes
+}
+
+async fn insert_revision(
+    tx: &mut Transaction<'_, Postgres>,
+    workitem_id: Uuid,
+    operation: &str,
+    changed_by: Option<Uuid>,
+    changes: Map<String, Value>,
+    snapshot: Option<Value>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO work_item_revisions (work_item_id, revision, operation, changed_by, changes, snapshot)
+        SELECT $1, COALESCE(MAX(revision), 0) + 1, $2, $3, $4, $5
+        FROM work_item_revisions WHERE work_item_id = $1",
+        workitem_id,
+        operation,
+        changed_by,
+        Value::Object(changes),
+        snapshot
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+// The single fan-out point for a work item write: every insert, update and delete of
+// a work item goes through here, inside the caller's transaction, to record the
+// revision, queue the matching webhook events and announce the change to live feeds.
+// `before` is None for a new work item and `after` is None for a deleted one.
+// A write that changes no tracked field (see UNTRACKED_FIELDS) records and publishes nothing.
+pub async fn record_and_publish(
+    tx: &mut Transaction<'_, Postgres>,
+    changed_by: Option<Uuid>,
+    before: Option<&WorkItem>,
+    after: Option<&WorkItem>,
+) -> Result<(), sqlx::Error> {
+    let Some(workitem_id) = after.or(before).map(|workitem| workitem.id) else {
+        return Ok(());
+    };
+    let changes = diff(before, after);
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let operation = match (before, after) {
+        (None, _) => "create",
+        (_, None) => "delete",
+        _ => "update",
+    };
+    let snapshot = after.and_then(|workitem| serde_json::to_value(workitem).ok());
+
+    webhooks::enqueue_workitem_events(tx, changed_by, before, after, &changes).await?;
+    live_feed::announce_workitem(tx, before, after).await?;
+    insert_revision(tx, workitem_id, operation, changed_by, changes, snapshot).await
+}
+
+// Records a comment being added, edited or deleted as a "comment" revision of
+// its work item, with the comment itself as the changed value.
+pub async fn record_comment(
+    tx: &mut Transaction<'_, Postgres>,
+    changed_by: Uuid,
+    workitem: &WorkItem,
+    before: Option<&Comment>,
+    after: Option<&Comment>,
+) -> Result<(), sqlx::Error> {
+    let mut changes = Map::new();
+    changes.insert("comment".to_string(), json!({"old": before, "new": after}));
+    let snapshot = serde_json::to_value(workitem).ok();
+
+    insert_revision(
+        tx,
+        workitem.id,
+        "comment",
+        Some(changed_by),
+        changes,
+        snapshot,
+    )
+    .await
+}
+
+#[get("/workitems/{id}/history")]
+async fn get_workitem_history(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    opts: Query<RevisionFilterOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let workitem_id = path.into_inner();
+
+    //deleted work items keep their history
+    let known = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM work_items WHERE id = $1)
+            OR EXISTS(SELECT 1 FROM work_item_revisions WHERE work_item_id = $1) AS "known!""#,
+        workitem_id
+    )
+    .fetch_one(&data.db)
+    .await?;
+    if !known {
+        return Err(AppError::NotFound(format!(
+            "Work item {} not found",
+            workitem_id
+        )));
+    }
+
+    let mut listing = Listing::new(
+        "work_item_id, revision, operation, changed_by, changed_at, changes",
+        "work_item_revisions",
+        "revision",
+        "int4",
+        REVISION_SORT_FIELDS,
+        "revision",
+    );
+    listing
+        .filter("work_item_id = $", Some(workitem_id))
+        .filter("changed_by = $", opts.changed_by)
+        .filter("changes ? $", opts.field.clone());
+
+    let page: Page<WorkItemRevision> = listing
+        .fetch(
+            &data.db,
+            opts.sort.as_deref(),
+            opts.cursor.as_deref(),
+            opts.limit,
+        )
+        .await?;
+
+    Ok(page.into_response("revisions"))
+}
+
+#[get("/workitems/{id}/revisions/{revision}")]
+async fn get_workitem_revision(
+    current: CurrentUser,
+    path: Path<(Uuid, i32)>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let (workitem_id, revision) = path.into_inner();
+
+    let row = sqlx::query!(
+        "SELECT work_item_id, revision, operation, changed_by, changed_at, changes, snapshot
+        FROM work_item_revisions WHERE work_item_id = $1 AND revision = $2",
+        workitem_id,
+        revision
+    )
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or_else(|| {
+        AppError::NotFound(format!(
+            "Revision {} of work item {} not found",
+            revision, workitem_id
+        ))
+    })?;
+
+    let meta = WorkItemRevision {
+        work_item_id: row.work_item_id,
+        revision: row.revision,
+        operation: row.operation,
+        changed_by: row.changed_by,
+        changed_at: row.changed_at,
+        changes: row.changes,
+    };
+
+    //data is the work item as it was after this revision, null once deleted
+    Ok(HttpResponse::Ok().json(json!({
+        "status":"success",
+        "revision": meta,
+        "data": row.snapshot
+    })))
+}
@@ -1,19 +1,103 @@
-use crate::{team_services, user_services,projects_services, workitems_services};
+use crate::{
+    area_services, azure_hook_services, comment_services, export_services, import_services,
+    iteration_services, live_services, notification_services, process_services, projects_services,
+    revision_services, search_services, sync_services, tag_services, team_services, user_services,
+    webhook_services, workitems_services,
+};
 use actix_web::web::{scope, ServiceConfig};
 
 pub fn configure_routes(conf: &mut ServiceConfig) {
     let scope = scope("/api")
         .service(user_services::health_check)
+        .service(user_services::get_current_user)
         .service(user_services::create_user)
         .service(user_services::get_all_users)
         .service(user_services::get_user_by_id)
         .service(user_services::delete_user)
         .service(user_services::update_user_by_id)
+        .service(user_services::upsert_user_by_azure_id)
+        .service(user_services::update_user_role)
         .service(team_services::create_team)
         .service(team_services::get_all_teams)
+        .service(team_services::get_team_by_id)
+        .service(team_services::update_team_by_id)
+        .service(team_services::delete_team)
+        .service(team_services::add_team_member)
+        .service(team_services::remove_team_member)
+        .service(team_services::upsert_team_by_azure_id)
+        .service(team_services::update_team_member_role)
         .service(projects_services::create_project)
         .service(projects_services::get_all_projects)
-        .service(workitems_services::create_workitem);
+        .service(projects_services::get_project_by_id)
+        .service(projects_services::get_project_process)
+        .service(projects_services::update_project_by_id)
+        .service(projects_services::delete_project)
+        .service(projects_services::upsert_project_by_azure_id)
+        .service(process_services::get_all_processes)
+        .service(process_services::get_process)
+        .service(process_services::upsert_process)
+        .service(process_services::delete_process)
+        .service(import_services::import_workitems)
+        .service(export_services::export_workitems)
+        .service(export_services::export_projects)
+        .service(export_services::export_teams)
+        .service(export_services::export_users)
+        .service(workitems_services::create_workitem)
+        .service(workitems_services::upsert_workitem_by_azure_id)
+        .service(workitems_services::get_all_workitem)
+        .service(workitems_services::get_workitem_by_id)
+        .service(workitems_services::get_workitem_children)
+        .service(workitems_services::get_workitem_ancestors)
+        .service(workitems_services::get_workitem_tree)
+        .service(workitems_services::update_workitem_parent)
+        .service(workitems_services::update_workitem_by_id)
+        .service(workitems_services::delete_workitem)
+        .service(workitems_services::get_team_backlog)
+        .service(iteration_services::create_iteration)
+        .service(iteration_services::get_project_iterations)
+        .service(iteration_services::get_project_iterations_by_timeframe)
+        .service(iteration_services::get_iteration_by_id)
+        .service(iteration_services::update_iteration_by_id)
+        .service(iteration_services::delete_iteration)
+        .service(iteration_services::carry_over_iteration)
+        .service(area_services::create_area)
+        .service(area_services::get_project_areas)
+        .service(area_services::get_project_area_tree)
+        .service(area_services::get_area_by_id)
+        .service(area_services::update_area_by_id)
+        .service(area_services::update_area_team)
+        .service(area_services::delete_area)
+        .service(revision_services::get_workitem_history)
+        .service(revision_services::get_workitem_revision)
+        .service(comment_services::create_comment)
+        .service(comment_services::get_workitem_comments)
+        .service(comment_services::get_comment_by_id)
+        .service(comment_services::update_comment)
+        .service(comment_services::delete_comment)
+        .service(tag_services::get_all_tags)
+        .service(tag_services::rename_tag)
+        .service(tag_services::merge_tags)
+        .service(search_services::search)
+        .service(notification_services::create_notification)
+        .service(notification_services::get_user_notifications)
+        .service(notification_services::close_notification)
+        .service(notification_services::close_all_notifications)
+        .service(notification_services::delete_notification)
+        .service(live_services::live_events)
+        .service(live_services::live_socket)
+        .service(sync_services::start_sync)
+        .service(sync_services::get_all_sync_runs)
+        .service(sync_services::get_sync_run_by_id)
+        .service(azure_hook_services::receive_azure_hook)
+        .service(webhook_services::create_webhook)
+        .service(webhook_services::get_all_webhooks)
+        .service(webhook_services::get_webhook_by_id)
+        .service(webhook_services::update_webhook)
+        .service(webhook_services::delete_webhook)
+        .service(webhook_services::ping_webhook)
+        .service(webhook_services::get_webhook_deliveries)
+        .service(webhook_services::get_webhook_delivery)
+        .service(webhook_services::replay_webhook_delivery);
 
     conf.service(scope);
 }
@@ -1,27 +1,140 @@
-use ::serde::{Deserialize, Serialize};
-use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use ::serde::{Deserialize, Deserializer, Serialize};
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
+use validator::{Validate, ValidationError};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::{model::ProcessWorkItemType, policy, webhooks};
+
+// For PATCH fields that can be cleared: a missing field stays None, an explicit null
+// becomes Some(None). Used with #[serde(default, deserialize_with = "double_option")].
+fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+fn validate_not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        let mut error = ValidationError::new("blank");
+        error.message = Some("must not be blank".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn validate_system_role(role: &str) -> Result<(), ValidationError> {
+    if role != policy::SYSTEM_ADMIN && role != policy::SYSTEM_USER {
+        let mut error = ValidationError::new("role");
+        error.message = Some("must be admin or user".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn validate_team_role(role: &str) -> Result<(), ValidationError> {
+    if role != policy::TEAM_ADMIN && role != policy::TEAM_MEMBER {
+        let mut error = ValidationError::new("role");
+        error.message = Some("must be admin or member".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn validate_tags(tags: &[String]) -> Result<(), ValidationError> {
+    if tags.iter().any(|tag| tag.trim().is_empty()) {
+        let mut error = ValidationError::new("blank_tag");
+        error.message = Some("tags must not be blank".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn validate_export_format(format: &str) -> Result<(), ValidationError> {
+    if !["csv", "jsonl", "xlsx"].contains(&format) {
+        let mut error = ValidationError::new("format");
+        error.message = Some("must be csv, jsonl or xlsx".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+// Area names are path segments, so they cannot contain the separator
+fn validate_area_name(name: &str) -> Result<(), ValidationError> {
+    validate_not_blank(name)?;
+    if name.contains('\\') {
+        let mut error = ValidationError::new("area_name");
+        error.message = Some("must not contain a backslash".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+// Receivers are reached over plain HTTP(S) only
+fn validate_webhook_url(url: &str) -> Result<(), ValidationError> {
+    if let Err(reason) = webhooks::check_url(url) {
+        let mut error = ValidationError::new("url");
+        error.message = Some(reason.into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn validate_webhook_events(events: &[String]) -> Result<(), ValidationError> {
+    if let Some(event) = events
+        .iter()
+        .find(|event| *event != "*" && !webhooks::EVENTS.contains(&event.as_str()))
+    {
+        let mut error = ValidationError::new("event");
+        error.message = Some(
+            format!(
+                "{} is not an event, expected * or one of: {}",
+                event,
+                webhooks::EVENTS.join(", ")
+            )
+            .into(),
+        );
+        return Err(error);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct CreateTeamRequest {
+    #[validate(custom = "validate_not_blank")]
     pub azure_id: Option<String>,
+    #[validate(custom = "validate_not_blank", length(max = 255))]
     pub name: String,
     pub description: Option<String>,
     pub user_ids: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateTeamRequest {
+    #[validate(custom = "validate_not_blank", length(max = 255))]
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct CreateUserRequest {
+    #[validate(custom = "validate_not_blank")]
     pub azure_id: Option<String>,
+    #[validate(custom = "validate_not_blank", length(max = 255))]
     pub name: Option<String>,
+    #[validate(email(message = "must be a valid email address"))]
     pub email: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct CreateProjectRequest {
+    #[validate(custom = "validate_not_blank")]
     pub azure_id: Option<String>,
+    #[validate(custom = "validate_not_blank", length(max = 255))]
     pub name: String,
     pub description: Option<String>,
+    #[validate(url(message = "must be a valid URL"))]
     pub url: Option<String>,
     pub template: Option<String>,
     pub begin_date: Option<DateTime<Utc>>,
@@ -29,37 +142,423 @@ pub struct CreateProjectRequest {
     pub team_id: Option<Uuid>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateProjectRequest {
+    #[validate(custom = "validate_not_blank", length(max = 255))]
+    pub name: Option<String>,
+    pub description: Option<String>,
+    #[validate(url(message = "must be a valid URL"))]
+    pub url: Option<String>,
+    pub template: Option<String>,
+    // null clears the dates or takes the project away from its team
+    #[serde(default, deserialize_with = "double_option")]
+    pub begin_date: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub end_date: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub team_id: Option<Option<Uuid>>,
+}
+
+// Priorities follow the Azure DevOps 1 (highest) to 4 (lowest) scale
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct CreateWorkItemRequest {
+    #[validate(custom = "validate_not_blank")]
     pub azure_id: Option<String>,
+    #[validate(custom = "validate_not_blank", length(max = 255))]
     pub title: String,
+    #[validate(custom = "validate_not_blank")]
     pub w_type: String,
+    #[validate(custom = "validate_not_blank")]
     pub state: String,
+    #[validate(custom = "validate_not_blank")]
     pub project: String,
+    #[validate(custom = "validate_not_blank")]
     pub assigned_to_id: Option<String>,
+    #[validate(email(message = "must be the creator's email address"))]
     pub created_by_id: String,
+    #[validate(range(min = 1, max = 4, message = "must be between 1 and 4"))]
     pub priority: Option<i32>,
     pub severity: Option<String>,
     pub description: Option<String>,
     pub area_path: Option<String>,
     pub iteration_path: Option<String>,
+    // takes precedence over iteration_path
+    pub iteration_id: Option<Uuid>,
+    #[validate(custom = "validate_not_blank")]
     pub parent_id: Option<String>,
+    #[validate(custom = "validate_tags")]
     pub tags: Option<Vec<String>>,
+    #[validate(url(message = "must be a valid URL"))]
     pub url: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateWorkItemRequest {
+    #[validate(custom = "validate_not_blank", length(max = 255))]
+    pub title: Option<String>,
+    #[validate(custom = "validate_not_blank")]
+    pub w_type: Option<String>,
+    #[validate(custom = "validate_not_blank")]
+    pub state: Option<String>,
+    #[validate(custom = "validate_not_blank")]
+    pub project: Option<String>,
+    // null unassigns the work item, and clears the other double options below
+    #[serde(default, deserialize_with = "double_option")]
+    #[validate(custom = "validate_not_blank")]
+    pub assigned_to_id: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    #[validate(range(min = 1, max = 4, message = "must be between 1 and 4"))]
+    pub priority: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub severity: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub description: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub area_path: Option<Option<String>>,
+    // null on either iteration field takes the work item out of its iteration
+    #[serde(default, deserialize_with = "double_option")]
+    pub iteration_path: Option<Option<String>>,
+    // takes precedence over iteration_path
+    #[serde(default, deserialize_with = "double_option")]
+    pub iteration_id: Option<Option<Uuid>>,
+    #[serde(default, deserialize_with = "double_option")]
+    #[validate(custom = "validate_not_blank")]
+    pub parent_id: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    #[validate(custom = "validate_tags")]
+    pub tags: Option<Option<Vec<String>>>,
+    #[validate(url(message = "must be a valid URL"))]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct CreateNotificationRequest {
+    #[validate(custom = "validate_not_blank", length(max = 255))]
     pub subject: Option<String>,
     pub sender_id: Uuid,
     pub receiver_id: Uuid,
     pub message: Option<String>,
 }
 
+// Every list endpoint takes sort (e.g. sort=name,-created_date), an opaque cursor and a limit
 #[derive(Serialize, Deserialize, Debug)]
-pub struct FilterOptions {
-    pub page: Option<i32>,
-    pub limit: Option<i32>,
+pub struct UserFilterOptions {
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
     pub name: Option<String>,
     pub email: Option<String>,
+    pub role: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TeamFilterOptions {
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub name: Option<String>,
+    pub member_id: Option<Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProjectFilterOptions {
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub name: Option<String>,
+    pub team_id: Option<Uuid>,
+    pub begins_after: Option<DateTime<Utc>>,
+    pub ends_before: Option<DateTime<Utc>>,
+}
+
+// state and w_type accept comma separated lists. tags and tags_all match items carrying all
+// given tags, tags_any items carrying at least one
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WorkItemFilterOptions {
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub state: Option<String>,
+    pub w_type: Option<String>,
+    pub assigned_to_id: Option<Uuid>,
+    pub created_by_id: Option<Uuid>,
+    pub project: Option<Uuid>,
+    pub parent_id: Option<Uuid>,
+    pub iteration_id: Option<Uuid>,
+    pub tags: Option<String>,
+    pub tags_all: Option<String>,
+    pub tags_any: Option<String>,
+    pub priority_min: Option<i64>,
+    pub priority_max: Option<i64>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub changed_after: Option<DateTime<Utc>>,
+    pub changed_before: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct WorkItemTreeOptions {
+    // levels below the root, defaults to 3
+    #[validate(range(min = 0, max = 10, message = "must be between 0 and 10"))]
+    pub depth: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateWorkItemParentRequest {
+    // null detaches the work item from its parent
+    pub parent_id: Option<Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RevisionFilterOptions {
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub changed_by: Option<Uuid>,
+    // only revisions that changed this field
+    pub field: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CommentFilterOptions {
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub author_id: Option<Uuid>,
+    // replies to this comment
+    pub parent_id: Option<Uuid>,
+    // only comments that start a thread
+    pub top_level: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IterationFilterOptions {
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AreaFilterOptions {
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub name: Option<String>,
+    pub parent_id: Option<Uuid>,
+    pub team_id: Option<Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct ImportOptions {
+    // validates every row and rolls everything back
+    pub dry_run: Option<bool>,
+    // commit every batch_size rows instead of all rows in one transaction, ignored by dry runs
+    #[validate(range(min = 1, max = 5000, message = "must be between 1 and 5000"))]
+    pub batch_size: Option<usize>,
+}
+
+// Read next to the filter options of the exported list, csv when left out
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct ExportOptions {
+    #[validate(custom = "validate_export_format")]
+    pub format: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TagFilterOptions {
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub name: Option<String>,
+    pub project: Option<Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchOptions {
+    // free text plus state:, type:, tag:, assigned: and project: filters
+    pub q: Option<String>,
+    // per entity type
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NotificationFilterOptions {
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub closed: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SyncRunFilterOptions {
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub status: Option<String>,
+    pub full_sync: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CreateCommentRequest {
+    #[validate(custom = "validate_not_blank", length(max = 10000))]
+    pub text: String,
+    // comment being replied to
+    pub parent_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateCommentRequest {
+    #[validate(custom = "validate_not_blank", length(max = 10000))]
+    pub text: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CreateIterationRequest {
+    #[validate(custom = "validate_not_blank")]
+    pub azure_id: Option<String>,
+    #[validate(custom = "validate_not_blank", length(max = 255))]
+    pub name: String,
+    // defaults to "<project name>\\<name>" like Azure DevOps
+    #[validate(custom = "validate_not_blank", length(max = 1024))]
+    pub path: Option<String>,
+    pub start_date: Option<DateTime<Utc>>,
+    pub end_date: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateIterationRequest {
+    #[validate(custom = "validate_not_blank", length(max = 255))]
+    pub name: Option<String>,
+    #[validate(custom = "validate_not_blank", length(max = 1024))]
+    pub path: Option<String>,
+    // null clears the date, leaving the iteration unscheduled
+    #[serde(default, deserialize_with = "double_option")]
+    pub start_date: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub end_date: Option<Option<DateTime<Utc>>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CarryOverRequest {
+    // defaults to the iteration starting next, or the current or next sprint for an
+    // iteration without dates
+    pub target_iteration_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CreateAreaRequest {
+    #[validate(custom = "validate_not_blank")]
+    pub azure_id: Option<String>,
+    #[validate(custom = "validate_area_name", length(max = 255))]
+    pub name: String,
+    // top-level areas have no parent, their path starts at the project's root area
+    pub parent_id: Option<Uuid>,
+    pub team_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateAreaRequest {
+    #[validate(custom = "validate_area_name", length(max = 255))]
+    pub name: Option<String>,
+    // moves the area and everything below it, null moves it to the top level
+    #[serde(default, deserialize_with = "double_option")]
+    pub parent_id: Option<Option<Uuid>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateAreaTeamRequest {
+    // null leaves the area without an owner
+    pub team_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct RenameTagRequest {
+    #[validate(custom = "validate_not_blank", length(max = 255))]
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct MergeTagsRequest {
+    #[validate(length(min = 1, message = "must name at least one tag"), custom = "validate_tags")]
+    pub tags: Vec<String>,
+    #[validate(custom = "validate_not_blank", length(max = 255))]
+    pub into: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateUserRoleRequest {
+    #[validate(custom = "validate_system_role")]
+    pub role: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateTeamRoleRequest {
+    #[validate(custom = "validate_team_role")]
+    pub role: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CreateWebhookRequest {
+    #[validate(custom = "validate_webhook_url")]
+    pub url: String,
+    // generated when left out
+    #[validate(length(min = 16, max = 255, message = "must be 16 to 255 characters"))]
+    pub secret: Option<String>,
+    #[validate(
+        length(min = 1, message = "must name at least one event"),
+        custom = "validate_webhook_events"
+    )]
+    pub events: Vec<String>,
+    pub description: Option<String>,
+    pub active: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateWebhookRequest {
+    #[validate(custom = "validate_webhook_url")]
+    pub url: Option<String>,
+    #[validate(length(min = 16, max = 255, message = "must be 16 to 255 characters"))]
+    pub secret: Option<String>,
+    #[validate(
+        length(min = 1, message = "must name at least one event"),
+        custom = "validate_webhook_events"
+    )]
+    pub events: Option<Vec<String>>,
+    pub description: Option<String>,
+    pub active: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WebhookFilterOptions {
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    // subscribed to this event, directly or through *
+    pub event: Option<String>,
+    pub active: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WebhookDeliveryFilterOptions {
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub status: Option<String>,
+    pub event: Option<String>,
+}
+
+// Work items of a project and/or a team; the caller's notifications are always sent
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LiveFeedOptions {
+    pub project: Option<Uuid>,
+    pub team: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpsertProcessRequest {
+    pub description: Option<String>,
+    // replaces all types, states and transitions of the template, in order
+    #[validate(length(min = 1, message = "must define at least one work item type"))]
+    pub work_item_types: Vec<ProcessWorkItemType>,
 }
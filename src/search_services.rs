@@ -0,0 +1,262 @@
+use actix_web::{
+    get,
+    web::{Data, Query},
+    HttpResponse,
+};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    auth::CurrentUser,
+    error::AppError,
+    model::{ProjectSearchHit, UserSearchHit, WorkItemSearchHit},
+    policy::Permission,
+    schema::SearchOptions,
+    AppState,
+};
+
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 50;
+
+/// A parsed `q` parameter: the free text for the tsquery and the
+/// `key:value` filters, which only apply to work items.
+#[derive(Default)]
+struct SearchQuery {
+    text: String,
+    states: Vec<String>,
+    types: Vec<String>,
+    tags: Vec<String>,
+    assigned: Vec<String>,
+    projects: Vec<String>,
+}
+
+impl SearchQuery {
+    fn has_filters(&self) -> bool {
+        !(self.states.is_empty()
+            && self.types.is_empty()
+            && self.tags.is_empty()
+            && self.assigned.is_empty()
+            && self.projects.is_empty())
+    }
+}
+
+// Splits on whitespace outside double quotes, so state:"In Progress" stays one term
+fn split_terms(q: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut term = String::new();
+    let mut quoted = false;
+    for character in q.chars() {
+        match character {
+            '"' => {
+                quoted = !quoted;
+                term.push(character);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !term.is_empty() {
+                    terms.push(std::mem::take(&mut term));
+                }
+            }
+            c => term.push(c),
+        }
+    }
+    if !term.is_empty() {
+        terms.push(term);
+    }
+    terms
+}
+
+fn parse_query(q: &str) -> SearchQuery {
+    let mut query = SearchQuery::default();
+    let mut text = Vec::new();
+    for term in split_terms(q) {
+        let filter = term.split_once(':').and_then(|(key, value)| {
+            let value = value.trim_matches('"').trim().to_lowercase();
+            let values = match key.to_lowercase().as_str() {
+                "state" => &mut query.states,
+                "type" => &mut query.types,
+                "tag" => &mut query.tags,
+                "assigned" => &mut query.assigned,
+                "project" => &mut query.projects,
+                _ => return None,
+            };
+            (!value.is_empty()).then(|| values.push(value))
+        });
+        //anything else, unknown keys included, is searched as text
+        if filter.is_none() {
+            text.push(term);
+        }
+    }
+    query.text = text.join(" ");
+    query
+}
+
+fn as_filter<T>(values: &[T]) -> Option<&[T]> {
+    (!values.is_empty()).then_some(values)
+}
+
+// assigned:me is the caller, anything else an email or Azure DevOps id
+async fn resolve_assignees(
+    data: &AppState,
+    current: &CurrentUser,
+    handles: &[String],
+) -> Result<Vec<Uuid>, AppError> {
+    let mut assignees = Vec::new();
+    for handle in handles {
+        if handle == "me" {
+            assignees.push(current.0.id);
+            continue;
+        }
+        let user_id = sqlx::query_scalar!(
+            "SELECT id FROM users WHERE lower(email) = $1 OR lower(azure_id) = $1",
+            handle
+        )
+        .fetch_optional(&data.db)
+        .await?
+        .ok_or_else(|| AppError::Unprocessable(format!("User {} not found", handle)))?;
+        assignees.push(user_id);
+    }
+    Ok(assignees)
+}
+
+#[get("/search")]
+async fn search(
+    current: CurrentUser,
+    opts: Query<SearchOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let query = parse_query(opts.q.as_deref().unwrap_or_default());
+    if query.text.is_empty() && !query.has_filters() {
+        return Err(AppError::BadRequest("q must not be empty".to_string()));
+    }
+    let limit = opts.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let assignees = resolve_assignees(&data, &current, &query.assigned).await?;
+
+    //without free text the filters alone select work items, most recently changed first
+    let workitems = sqlx::query_as!(
+        WorkItemSearchHit,
+        r#"SELECT w.id, w.azure_id, w.title, w.w_type, w.state, w.project, w.assigned_to_id, w.tags,
+            COALESCE(ts_rank(work_item_search_document(w.title, w.description, w.tags), q.query), 0) AS "rank!",
+            COALESCE(ts_headline('english', html_escape(w.title), q.query,
+                'HighlightAll=true, StartSel=<mark>, StopSel=</mark>'), html_escape(w.title)) AS "title_highlight!",
+            ts_headline('english', html_escape(w.description), q.query,
+                'StartSel=<mark>, StopSel=</mark>, MaxFragments=2, MaxWords=30, MinWords=10') AS description_highlight
+        FROM work_items w,
+            (SELECT NULLIF(websearch_to_tsquery('english', $1), ''::tsquery) AS query) q
+        WHERE ($1 = '' OR work_item_search_document(w.title, w.description, w.tags) @@ q.query)
+            AND ($2::varchar[] IS NULL OR lower(w.state) = ANY($2))
+            AND ($3::varchar[] IS NULL OR lower(w.w_type) = ANY($3))
+            AND ($4::varchar[] IS NULL OR ARRAY(SELECT lower(tag) FROM unnest(w.tags) tag)::varchar[] @> $4::varchar[])
+            AND ($5::uuid[] IS NULL OR w.assigned_to_id = ANY($5))
+            AND ($6::varchar[] IS NULL OR w.project IN (SELECT id FROM projects WHERE lower(name) = ANY($6)))
+        ORDER BY 9 DESC, w.changed_date DESC NULLS LAST, w.id
+        LIMIT $7"#,
+        query.text,
+        as_filter(&query.states),
+        as_filter(&query.types),
+        as_filter(&query.tags),
+        as_filter(&assignees),
+        as_filter(&query.projects),
+        limit
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    //filters are work item fields, so a filtered search leaves projects and users out
+    let (projects, users) = if query.has_filters() {
+        (Vec::new(), Vec::new())
+    } else {
+        let projects = sqlx::query_as!(
+            ProjectSearchHit,
+            r#"SELECT p.id, p.name,
+                ts_rank(project_search_document(p.name, p.description), q.query) AS "rank!",
+                ts_headline('english', html_escape(p.name), q.query,
+                    'HighlightAll=true, StartSel=<mark>, StopSel=</mark>') AS name_highlight,
+                ts_headline('english', html_escape(p.description), q.query,
+                    'StartSel=<mark>, StopSel=</mark>, MaxFragments=2, MaxWords=30, MinWords=10') AS description_highlight
+            FROM projects p, websearch_to_tsquery('english', $1) q(query)
+            WHERE project_search_document(p.name, p.description) @@ q.query
+            ORDER BY 3 DESC, p.name, p.id
+            LIMIT $2"#,
+            query.text,
+            limit
+        )
+        .fetch_all(&data.db)
+        .await?;
+
+        let users = sqlx::query_as!(
+            UserSearchHit,
+            r#"SELECT u.id, u.name, u.email,
+                ts_rank(user_search_document(u.name, u.email), q.query) AS "rank!",
+                ts_headline('english', html_escape(u.name), q.query,
+                    'HighlightAll=true, StartSel=<mark>, StopSel=</mark>') AS name_highlight,
+                ts_headline('english', html_escape(u.email), q.query,
+                    'HighlightAll=true, StartSel=<mark>, StopSel=</mark>') AS email_highlight
+            FROM users u, websearch_to_tsquery('english', $1) q(query)
+            WHERE user_search_document(u.name, u.email) @@ q.query
+            ORDER BY 4 DESC, u.name, u.id
+            LIMIT $2"#,
+            query.text,
+            limit
+        )
+        .fetch_all(&data.db)
+        .await?;
+
+        (projects, users)
+    };
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status":"success",
+        "result": workitems.len() + projects.len() + users.len(),
+        "workitems": workitems,
+        "projects": projects,
+        "users": users
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_terms_keeps_quoted_phrases_together() {
+        assert_eq!(
+            split_terms(r#"  state:"In Progress"   login "two words" "#),
+            vec![r#"state:"In Progress""#, "login", r#""two words""#]
+        );
+    }
+
+    #[test]
+    fn split_terms_runs_an_unclosed_quote_to_the_end() {
+        assert_eq!(
+            split_terms(r#"bug tag:"needs review"#),
+            vec!["bug", r#"tag:"needs review"#]
+        );
+        assert!(split_terms(" \t ").is_empty());
+    }
+
+    #[test]
+    fn parse_query_reads_known_filters() {
+        let query = parse_query(
+            r#"login State:"In Progress" type:Bug tag:UI tag:api assigned:me project:Web"#,
+        );
+        assert_eq!(query.text, "login");
+        assert_eq!(query.states, vec!["in progress"]);
+        assert_eq!(query.types, vec!["bug"]);
+        assert_eq!(query.tags, vec!["ui", "api"]);
+        assert_eq!(query.assigned, vec!["me"]);
+        assert_eq!(query.projects, vec!["web"]);
+        assert!(query.has_filters());
+    }
+
+    #[test]
+    fn parse_query_searches_unknown_keys_and_empty_values_as_text() {
+        let query = parse_query(r#"owner:alice state: http://example.com "exact phrase""#);
+        assert_eq!(
+            query.text,
+            r#"owner:alice state: http://example.com "exact phrase""#
+        );
+        assert!(!query.has_filters());
+    }
+}
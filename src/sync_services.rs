@@ -0,0 +1,99 @@
+use actix_web::{
+    get, post,
+    web::{Data, Path, Query},
+    HttpResponse,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    auth::CurrentUser,
+    azure_sync,
+    error::AppError,
+    listing::{Listing, Page, SortField},
+    model::SyncRun,
+    policy::Permission,
+    schema::SyncRunFilterOptions,
+    AppState,
+};
+
+const SYNC_RUN_SORT_FIELDS: &[SortField] = &[
+    SortField::new("started_at", "started_at", "timestamptz"),
+    SortField::new("finished_at", "finished_at", "timestamptz").nullable(),
+];
+
+#[derive(Debug, Deserialize)]
+pub struct SyncOptions {
+    pub full: Option<bool>,
+}
+
+#[post("/sync")]
+async fn start_sync(
+    current: CurrentUser,
+    opts: Query<SyncOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::ManageSync).await?;
+
+    let client = data.azure.as_ref().ok_or_else(|| {
+        AppError::ServiceUnavailable(
+            "Azure DevOps sync is not configured, set AZURE_DEVOPS_URL".to_string(),
+        )
+    })?;
+
+    let run = azure_sync::try_start_sync(&data.db, client, opts.full.unwrap_or(false))
+        .await?
+        .ok_or_else(|| AppError::Conflict("A sync run is already in progress".to_string()))?;
+
+    Ok(HttpResponse::Accepted().json(json!({"status":"success", "data":run})))
+}
+
+#[get("/sync/runs")]
+async fn get_all_sync_runs(
+    current: CurrentUser,
+    opts: Query<SyncRunFilterOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::ManageSync).await?;
+
+    let mut listing = Listing::new(
+        "*",
+        "sync_runs",
+        "id",
+        "int4",
+        SYNC_RUN_SORT_FIELDS,
+        "-started_at",
+    );
+    listing
+        .filter("status = $", opts.status.clone())
+        .filter("full_sync = $", opts.full_sync);
+
+    let page: Page<SyncRun> = listing
+        .fetch(
+            &data.db,
+            opts.sort.as_deref(),
+            opts.cursor.as_deref(),
+            opts.limit,
+        )
+        .await?;
+
+    Ok(page.into_response("runs"))
+}
+
+#[get("/sync/runs/{id}")]
+async fn get_sync_run_by_id(
+    current: CurrentUser,
+    path: Path<i32>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::ManageSync).await?;
+
+    let run_id = path.into_inner();
+
+    let run = sqlx::query_as!(SyncRun, "SELECT * FROM sync_runs WHERE id = $1", run_id)
+        .fetch_optional(&data.db)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Sync run {} not found", run_id)))?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":run})))
+}
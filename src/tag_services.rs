@@ -0,0 +1,195 @@
+use std::collections::HashSet;
+
+use actix_web::{
+    get, patch, post,
+    web::{Data, Json, Path, Query},
+    HttpResponse,
+};
+use serde_json::json;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    auth::CurrentUser,
+    error::AppError,
+    listing::{contains_pattern, split_list, Listing, Page, SortField},
+    model::{TagUsage, WorkItem},
+    policy::Permission,
+    revision_services,
+    schema::{MergeTagsRequest, RenameTagRequest, TagFilterOptions},
+    AppState,
+};
+
+const TAG_SORT_FIELDS: &[SortField] = &[
+    SortField::new("name", "name", "varchar"),
+    SortField::new("count", "count", "int8"),
+];
+
+/// The stored form of a tag: lowercase, trimmed, with single spaces.
+pub fn normalize_tag(tag: &str) -> String {
+    tag.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Normalizes every tag and drops blank and duplicate ones, keeping the first
+/// occurrence's position.
+pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+    for tag in tags.iter().map(|tag| normalize_tag(tag)) {
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+// A comma separated tags query parameter, normalized like stored tags
+pub fn split_tags(value: &str) -> Vec<String> {
+    normalize_tags(&split_list(value))
+}
+
+// Replaces the source tags with the target on every work item carrying one of them
+async fn replace_tags(
+    current: &CurrentUser,
+    data: &AppState,
+    sources: &[String],
+    target: &str,
+) -> Result<usize, AppError> {
+    let mut tx = data.db.begin().await?;
+
+    let workitems = sqlx::query_as!(
+        WorkItem,
+        "SELECT * FROM work_items WHERE tags && $1::varchar[] ORDER BY id FOR UPDATE",
+        sources
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    //tags are shared across projects, so every project touched must be writable
+    let projects: HashSet<Uuid> = workitems.iter().map(|workitem| workitem.project).collect();
+    for project_id in projects {
+        current
+            .authorize(&data.db, Permission::WriteProject(project_id))
+            .await?;
+    }
+
+    let mut updated = 0;
+    for before in &workitems {
+        let replaced: Vec<String> = before
+            .tags
+            .iter()
+            .flatten()
+            .map(|tag| match sources.contains(tag) {
+                true => target.to_string(),
+                false => tag.clone(),
+            })
+            .collect();
+        let tags = normalize_tags(&replaced);
+        if Some(&tags) == before.tags.as_ref() {
+            continue;
+        }
+
+        let after = sqlx::query_as!(
+            WorkItem,
+            "UPDATE work_items SET tags = $1, changed_date = NOW() WHERE id = $2 RETURNING *",
+            &tags,
+            before.id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        revision_services::record_and_publish(
+            &mut tx,
+            Some(current.0.id),
+            Some(before),
+            Some(&after),
+        )
+        .await?;
+        updated += 1;
+    }
+
+    tx.commit().await?;
+
+    Ok(updated)
+}
+
+#[get("/tags")]
+async fn get_all_tags(
+    current: CurrentUser,
+    opts: Query<TagFilterOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    //one row per tag and project
+    let mut listing = Listing::new(
+        "name, project_id, count",
+        "(SELECT t.name, w.project AS project_id, COUNT(*) AS count,
+                w.project::text || '/' || t.name AS key
+            FROM work_items w, unnest(w.tags) t(name)
+            GROUP BY w.project, t.name) tags",
+        "key",
+        "text",
+        TAG_SORT_FIELDS,
+        "-count",
+    );
+    listing
+        .filter("project_id = $", opts.project)
+        .filter("name ILIKE $", opts.name.as_deref().map(contains_pattern));
+
+    let page: Page<TagUsage> = listing
+        .fetch(
+            &data.db,
+            opts.sort.as_deref(),
+            opts.cursor.as_deref(),
+            opts.limit,
+        )
+        .await?;
+
+    Ok(page.into_response("tags"))
+}
+
+#[patch("/tags/{name}")]
+async fn rename_tag(
+    current: CurrentUser,
+    path: Path<String>,
+    body: Json<RenameTagRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
+    let name = normalize_tag(&path.into_inner());
+    let target = normalize_tag(&body.name);
+
+    let used = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM work_items WHERE tags @> ARRAY[$1]::varchar[]) AS "used!""#,
+        name
+    )
+    .fetch_one(&data.db)
+    .await?;
+    if !used {
+        return Err(AppError::NotFound(format!("Tag {} not found", name)));
+    }
+
+    //renaming onto a tag an item already carries merges the two
+    let updated = replace_tags(&current, &data, &[name], &target).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "result":updated, "tag":target})))
+}
+
+#[post("/tags/merge")]
+async fn merge_tags(
+    current: CurrentUser,
+    body: Json<MergeTagsRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
+    let sources = normalize_tags(&body.tags);
+    let target = normalize_tag(&body.into);
+
+    let updated = replace_tags(&current, &data, &sources, &target).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "result":updated, "tag":target})))
+}
@@ -1,60 +1,70 @@
 use actix_web::{
-    get, post,
-    web::{Data, Json, Query},
-    HttpResponse, Responder,
+    delete, get, patch, post, put,
+    web::{Data, Json, Path, Query},
+    HttpResponse,
 };
 
-use log::{info,error};
+use log::info;
 use serde_json::json;
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+use validator::Validate;
 
 use crate::{
-    model::{Team, TeamResponse, User},
-    schema::{CreateTeamRequest, FilterOptions},
-    AppState,
+    auth::CurrentUser,
+    error::AppError,
+    listing::{contains_pattern, Listing, Page, SortField},
+    model::{Team, TeamResponse, TeamUser, Upserted, User},
+    policy::{Permission, TEAM_ADMIN, TEAM_MEMBER},
+    schema::{CreateTeamRequest, TeamFilterOptions, UpdateTeamRequest, UpdateTeamRoleRequest},
+    webhooks, AppState,
 };
 
+const TEAM_SORT_FIELDS: &[SortField] = &[SortField::new("name", "name", "varchar")];
+
+// Resolves team members by azure_id, failing if any of them is unknown
+async fn find_team_members(
+    tx: &mut Transaction<'_, Postgres>,
+    user_ids: &[String],
+) -> Result<Vec<User>, AppError> {
+    let users = sqlx::query_as!(
+        User,
+        "SELECT * FROM users WHERE azure_id = ANY($1::varchar[])",
+        user_ids
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    if users.len() != user_ids.len() {
+        return Err(AppError::Unprocessable(
+            "One or more users not found".to_string(),
+        ));
+    }
+    Ok(users)
+}
+
 #[post("/teams")]
-async fn create_team(body: Json<CreateTeamRequest>, data: Data<AppState>) -> impl Responder {
+async fn create_team(
+    current: CurrentUser,
+    body: Json<CreateTeamRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+    current.authorize(&data.db, Permission::CreateTeam).await?;
+
     //start transaction
-    let mut tx = match data.db.begin().await {
-        Ok(tx) => tx,
-        Err(error) => {
-            return HttpResponse::InternalServerError().json(json!({
-                "status": "error",
-                "message": format!("Failed to start transaction: {}", error)
-            }));
-        }
-    };
+    let mut tx = data.db.begin().await?;
     info!("{:?}", &body.user_ids[..]);
     //find team members
-    let users = match sqlx::query_as!(
-        User,
-        "SELECT * FROM users WHERE azure_id = ANY($1::varchar[])",
-        &body.user_ids
-    )
-    .fetch_all(&mut tx)
-    .await
-    {
-        Ok(users) => {
-            if users.len() != body.user_ids.len() {
-                return HttpResponse::BadRequest().json(json!({
-                    "status": "error",
-                    "message": "One or more users not found"
-                }));
-            }
-            users
-        }
-        Err(error) => {
-            error!("{}", error);
-            return HttpResponse::InternalServerError().json(json!({
-                "status":"error",
-                "message": format!("{:?}",error)
-            }));
-        }
-    };
+    let mut users = find_team_members(&mut tx, &body.user_ids).await?;
+
+    //the creator administers the team, system admins already manage every team
+    if !current.is_system_admin() && !users.iter().any(|user| user.id == current.0.id) {
+        users.push(current.0.clone());
+    }
 
     //insert team
-    let team = match sqlx::query_as!(
+    let team = sqlx::query_as!(
         Team,
         "INSERT INTO teams (name, description, azure_id) VALUES ($1, $2, $3) RETURNING *",
         body.name,
@@ -62,40 +72,27 @@ async fn create_team(body: Json<CreateTeamRequest>, data: Data<AppState>) -> imp
         body.azure_id
     )
     .fetch_one(&mut tx)
-    .await
-    {
-        Ok(team) => team,
-        Err(error) => {
-            return HttpResponse::InternalServerError().json(json!({
-                "status":"error",
-                "message": format!("{:?}",error)
-            }));
-        }
-    };
+    .await?;
 
     for user in &users {
-        if let Err(e) = sqlx::query!(
-            "INSERT INTO team_users (team_id, user_id) VALUES ($1,$2)",
+        let role = if user.id == current.0.id {
+            TEAM_ADMIN
+        } else {
+            TEAM_MEMBER
+        };
+        sqlx::query!(
+            "INSERT INTO team_users (team_id, user_id, role) VALUES ($1,$2,$3)",
             team.id,
-            user.id
+            user.id,
+            role
         )
         .execute(&mut tx)
-        .await
-        {
-            let _ = tx.rollback().await;
-            return HttpResponse::InternalServerError().json(json!({
-                "status": "error",
-                "message":format!("Failed to associate users: {}",e)
-            }));
-        }
+        .await?;
     }
+    let member_ids: Vec<_> = users.iter().map(|user| user.id).collect();
+    webhooks::enqueue_member_events(&mut tx, Some(current.0.id), team.id, &member_ids, &[]).await?;
 
-    if let Err(e) = tx.commit().await {
-        return HttpResponse::InternalServerError().json(json!({
-            "status": "error",
-            "message": format!("Failed to commit transaction: {}", e)
-        }));
-    }
+    tx.commit().await?;
 
     let response = TeamResponse {
         id: team.id,
@@ -105,36 +102,324 @@ async fn create_team(body: Json<CreateTeamRequest>, data: Data<AppState>) -> imp
         users: Some(users),
     };
 
-    HttpResponse::Created().json(json!({"status":"success", "data":response}))
+    Ok(HttpResponse::Created().json(json!({"status":"success", "data":response})))
+}
+
+// Teams matching the list filters, read from `from`, which has to be aliased teams
+pub fn team_listing(from: &'static str, opts: &TeamFilterOptions) -> Listing {
+    let mut listing = Listing::new("*", from, "id", "uuid", TEAM_SORT_FIELDS, "name");
+    listing
+        .filter("name ILIKE $", opts.name.as_deref().map(contains_pattern))
+        .filter(
+            "id IN (SELECT team_id FROM team_users WHERE user_id = $)",
+            opts.member_id,
+        );
+    listing
 }
 
 #[get("/teams")]
-async fn get_all_teams(opts: Query<FilterOptions>, data: Data<AppState>) -> impl Responder {
-    let limit = opts.limit.unwrap_or(10);
-    let offset = (opts.page.unwrap_or(1) - 1) * limit;
+async fn get_all_teams(
+    current: CurrentUser,
+    opts: Query<TeamFilterOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
 
-    match sqlx::query_as!(
-        Team,
-        "SELECT * FROM teams ORDER by id LIMIT $1 OFFSET $2",
-        limit as i32,
-        offset as i32
+    let page: Page<Team> = team_listing("teams", &opts)
+        .fetch(
+            &data.db,
+            opts.sort.as_deref(),
+            opts.cursor.as_deref(),
+            opts.limit,
+        )
+        .await?;
+
+    Ok(page.into_response("teams"))
+}
+
+#[get("/teams/{id}")]
+async fn get_team_by_id(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let team_id = path.into_inner();
+
+    let team = sqlx::query_as!(Team, "SELECT * FROM teams WHERE id = $1", team_id)
+        .fetch_optional(&data.db)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Team {} not found", team_id)))?;
+
+    let users = sqlx::query_as!(
+        User,
+        "SELECT u.* FROM users u JOIN team_users tu ON tu.user_id = u.id
+        WHERE tu.team_id = $1 ORDER BY u.name, u.id",
+        team_id
     )
     .fetch_all(&data.db)
-    .await
-    {
-        Ok(teams) => {
-            let json_response = json!({
-                "status":"success",
-                "result": teams.len(),
-                "users": teams
-            });
-            return HttpResponse::Ok().json(json_response);
+    .await?;
+
+    let response = TeamResponse {
+        id: team.id,
+        name: team.name,
+        azure_id: team.azure_id,
+        description: team.description,
+        users: Some(users),
+    };
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":response})))
+}
+
+#[patch("/teams/{id}")]
+async fn update_team_by_id(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    body: Json<UpdateTeamRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
+    let team_id = path.into_inner();
+    current
+        .authorize(&data.db, Permission::ManageTeam(team_id))
+        .await?;
+
+    let team = sqlx::query_as!(
+        Team,
+        "UPDATE teams SET name = COALESCE($1, name), description = COALESCE($2, description)
+        WHERE id = $3 RETURNING *",
+        body.name,
+        body.description,
+        team_id
+    )
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Team {} not found", team_id)))?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":team})))
+}
+
+#[delete("/teams/{id}")]
+async fn delete_team(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let team_id = path.into_inner();
+    current
+        .authorize(&data.db, Permission::ManageTeam(team_id))
+        .await?;
+
+    let mut tx = data.db.begin().await?;
+
+    //projects must be moved or deleted first, otherwise their work items lose their owner
+    let projects = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM projects WHERE team_id = $1"#,
+        team_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    if projects > 0 {
+        return Err(AppError::Conflict(format!(
+            "Team {} still owns {} projects",
+            team_id, projects
+        )));
+    }
+
+    //memberships are removed by ON DELETE CASCADE
+    let result = sqlx::query!("DELETE FROM teams WHERE id = $1", team_id)
+        .execute(&mut tx)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound(format!("Team {} not found", team_id)));
+    }
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/teams/{id}/members/{user_id}")]
+async fn add_team_member(
+    current: CurrentUser,
+    path: Path<(Uuid, Uuid)>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let (team_id, user_id) = path.into_inner();
+    current
+        .authorize(&data.db, Permission::ManageTeam(team_id))
+        .await?;
+
+    let mut tx = data.db.begin().await?;
+
+    //unknown team or user ids surface as 422 through the foreign keys
+    let member = sqlx::query_as!(
+        TeamUser,
+        "INSERT INTO team_users (team_id, user_id, role) VALUES ($1,$2,$3)
+        ON CONFLICT DO NOTHING RETURNING *",
+        team_id,
+        user_id,
+        TEAM_MEMBER
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    match member {
+        Some(member) => {
+            webhooks::enqueue_member_events(&mut tx, Some(current.0.id), team_id, &[user_id], &[])
+                .await?;
+            tx.commit().await?;
+            Ok(HttpResponse::Created().json(json!({"status":"success", "data":member})))
         }
-        Err(error) => {
-            return HttpResponse::InternalServerError().json(json!({
-                "status":"error",
-                "message": format!("{:?}",error)
-            }));
+        None => {
+            let member = sqlx::query_as!(
+                TeamUser,
+                "SELECT * FROM team_users WHERE team_id = $1 AND user_id = $2",
+                team_id,
+                user_id
+            )
+            .fetch_one(&data.db)
+            .await?;
+            Ok(HttpResponse::Ok().json(json!({"status":"success", "data":member})))
         }
     }
 }
+
+#[delete("/teams/{id}/members/{user_id}")]
+async fn remove_team_member(
+    current: CurrentUser,
+    path: Path<(Uuid, Uuid)>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let (team_id, user_id) = path.into_inner();
+    current
+        .authorize(&data.db, Permission::ManageTeam(team_id))
+        .await?;
+
+    let mut tx = data.db.begin().await?;
+
+    let result = sqlx::query!(
+        "DELETE FROM team_users WHERE team_id = $1 AND user_id = $2",
+        team_id,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound(format!(
+            "User {} is not a member of team {}",
+            user_id, team_id
+        )));
+    }
+    webhooks::enqueue_member_events(&mut tx, Some(current.0.id), team_id, &[], &[user_id]).await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[put("/teams/by-azure-id/{azure_id}")]
+async fn upsert_team_by_azure_id(
+    current: CurrentUser,
+    path: Path<String>,
+    body: Json<CreateTeamRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+    current
+        .authorize(&data.db, Permission::ManageOrganization)
+        .await?;
+
+    let azure_id = path.into_inner();
+    if body.azure_id.as_ref().is_some_and(|id| *id != azure_id) {
+        return Err(AppError::Validation(
+            "azure_id in the body does not match the path".to_string(),
+        ));
+    }
+
+    let mut tx = data.db.begin().await?;
+
+    let users = find_team_members(&mut tx, &body.user_ids).await?;
+
+    let upserted = sqlx::query_as::<_, Upserted<Team>>(
+        "INSERT INTO teams (name, description, azure_id) VALUES ($1, $2, $3)
+        ON CONFLICT (azure_id) DO UPDATE SET name = EXCLUDED.name, description = EXCLUDED.description
+        RETURNING *, (xmax = 0) AS inserted",
+    )
+    .bind(&body.name)
+    .bind(&body.description)
+    .bind(&azure_id)
+    .fetch_one(&mut tx)
+    .await?;
+    let team = upserted.row;
+
+    //membership is replaced by the given users, existing members keep their role
+    let user_ids: Vec<_> = users.iter().map(|user| user.id).collect();
+    let removed = sqlx::query_scalar!(
+        "DELETE FROM team_users WHERE team_id = $1 AND NOT (user_id = ANY($2)) RETURNING user_id",
+        team.id,
+        &user_ids
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    let added = sqlx::query_scalar!(
+        "INSERT INTO team_users (team_id, user_id) SELECT $1, UNNEST($2::uuid[])
+        ON CONFLICT DO NOTHING RETURNING user_id",
+        team.id,
+        &user_ids
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    webhooks::enqueue_member_events(&mut tx, Some(current.0.id), team.id, &added, &removed).await?;
+
+    tx.commit().await?;
+
+    let response = TeamResponse {
+        id: team.id,
+        name: team.name,
+        azure_id: team.azure_id,
+        description: team.description,
+        users: Some(users),
+    };
+
+    if upserted.inserted {
+        Ok(HttpResponse::Created().json(json!({"status":"success", "data":response})))
+    } else {
+        Ok(HttpResponse::Ok().json(json!({"status":"success", "data":response})))
+    }
+}
+
+#[put("/teams/{id}/members/{user_id}/role")]
+async fn update_team_member_role(
+    current: CurrentUser,
+    path: Path<(Uuid, Uuid)>,
+    body: Json<UpdateTeamRoleRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
+    let (team_id, user_id) = path.into_inner();
+    current
+        .authorize(&data.db, Permission::ManageTeam(team_id))
+        .await?;
+
+    let member = sqlx::query_as!(
+        TeamUser,
+        "UPDATE team_users SET role = $1 WHERE team_id = $2 AND user_id = $3 RETURNING *",
+        body.role,
+        team_id,
+        user_id
+    )
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or_else(|| {
+        AppError::NotFound(format!(
+            "User {} is not a member of team {}",
+            user_id, team_id
+        ))
+    })?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":member})))
+}
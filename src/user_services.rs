@@ -1,22 +1,29 @@
 use actix_web::{
-    delete, get, patch, post,
-    web::{scope, Data, Json, Path, Query, ServiceConfig},
+    delete, get, patch, post, put,
+    web::{Data, Json, Path, Query},
     HttpResponse, Responder,
 };
 
 use serde_json::json;
 use uuid::Uuid;
-
-use log::{error, info};
-
-use sqlx::{postgres::PgArguments, Arguments, Postgres};
+use validator::Validate;
 
 use crate::{
-    model::User,
-    schema::{CreateUserRequest, FilterOptions},
+    auth::CurrentUser,
+    error::AppError,
+    listing::{contains_pattern, Listing, Page, SortField},
+    model::{Upserted, User},
+    policy::Permission,
+    schema::{CreateUserRequest, UpdateUserRoleRequest, UserFilterOptions},
     AppState,
 };
 
+const USER_SORT_FIELDS: &[SortField] = &[
+    SortField::new("name", "name", "varchar").nullable(),
+    SortField::new("email", "email", "varchar").nullable(),
+    SortField::new("role", "role", "varchar"),
+];
+
 #[get("/healthcheck")]
 async fn health_check() -> impl Responder {
     const MESSAGE: &str = "Healthcheck api route up and running";
@@ -27,9 +34,29 @@ async fn health_check() -> impl Responder {
     }))
 }
 
+#[get("/me")]
+async fn get_current_user(
+    current: CurrentUser,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status":"success",
+        "user": current.0
+    })))
+}
+
 #[post("/users")]
-async fn create_user(body: Json<CreateUserRequest>, data: Data<AppState>) -> impl Responder {
-    match sqlx::query_as!(
+async fn create_user(
+    current: CurrentUser,
+    body: Json<CreateUserRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+    current.authorize(&data.db, Permission::ManageUsers).await?;
+
+    let user = sqlx::query_as!(
         User,
         "INSERT INTO users (name, azure_id, email) VALUES ($1,$2,$3)
              RETURNING *",
@@ -38,147 +65,186 @@ async fn create_user(body: Json<CreateUserRequest>, data: Data<AppState>) -> imp
         body.email.as_deref()
     )
     .fetch_one(&data.db)
-    .await
-    {
-        Ok(user) => {
-            let note_response = json!({
-                "status":"success",
-                "user": json!({
-                    "user": user
-                })
-            });
-            return HttpResponse::Ok().json(note_response);
-        }
-        Err(error) => {
-            println!("Entrou no erro");
-            return HttpResponse::InternalServerError().json(json!({
-                "status":"error",
-                "message": format!("{:?}",error)
-            }));
-        }
-    }
+    .await?;
+
+    let note_response = json!({
+        "status":"success",
+        "user": json!({
+            "user": user
+        })
+    });
+    Ok(HttpResponse::Ok().json(note_response))
+}
+
+// Users matching the list filters, read from `from`, which has to be aliased users
+pub fn user_listing(from: &'static str, opts: &UserFilterOptions) -> Listing {
+    let mut listing = Listing::new("*", from, "id", "uuid", USER_SORT_FIELDS, "name");
+    listing
+        .filter("name ILIKE $", opts.name.as_deref().map(contains_pattern))
+        .filter("lower(email) = lower($)", opts.email.clone())
+        .filter("role = $", opts.role.clone());
+    listing
 }
 
 #[get("/users")]
-async fn get_all_users(opts: Query<FilterOptions>, data: Data<AppState>) -> impl Responder {
-    let limit = opts.limit.unwrap_or(10);
-    let offset = (opts.page.unwrap_or(1) - 1) * limit;
+async fn get_all_users(
+    current: CurrentUser,
+    opts: Query<UserFilterOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
 
-    match sqlx::query_as!(
-        User,
-        "SELECT * FROM users ORDER by id LIMIT $1 OFFSET $2",
-        limit as i32,
-        offset as i32
-    )
-    .fetch_all(&data.db)
-    .await
-    {
-        Ok(users) => {
-            let json_response = json!({
-                "status":"success",
-                "result": users.len(),
-                "users": users
-            });
-            return HttpResponse::Ok().json(json_response);
-        }
-        Err(error) => {
-            return HttpResponse::InternalServerError().json(json!({
-                "status":"error",
-                "message": format!("{:?}",error)
-            }));
-        }
-    }
+    let page: Page<User> = user_listing("users", &opts)
+        .fetch(
+            &data.db,
+            opts.sort.as_deref(),
+            opts.cursor.as_deref(),
+            opts.limit,
+        )
+        .await?;
+
+    Ok(page.into_response("users"))
 }
 
 #[get("/users/{id}")]
-async fn get_user_by_id(path: Path<Uuid>, data: Data<AppState>) -> impl Responder {
+async fn get_user_by_id(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
     let user_id = path.into_inner();
 
-    match sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", user_id)
-        .fetch_one(&data.db)
-        .await
-    {
-        Ok(user) => {
-            let user = json!({
-                "status":"success",
-                "user":user
-            });
-            return HttpResponse::Ok().json(user);
-        }
-        Err(error) => {
-            return HttpResponse::InternalServerError().json(json!({
-                "status":"error",
-                "message": format!("{:?}",error)
-            }));
-        }
-    }
+    let user = sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", user_id)
+        .fetch_optional(&data.db)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User {} not found", user_id)))?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status":"success",
+        "user":user
+    })))
 }
 
 #[delete("/users/{id}")]
-async fn delete_user(path: Path<Uuid>, data: Data<AppState>) -> impl Responder {
+async fn delete_user(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::ManageUsers).await?;
+
     let user_id = path.into_inner();
 
-    match sqlx::query_as!(User, "DELETE FROM users WHERE id = $1", user_id)
+    let result = sqlx::query!("DELETE FROM users WHERE id = $1", user_id)
         .execute(&data.db)
-        .await
-    {
-        Ok(_) => return HttpResponse::NoContent().finish(),
-        Err(error) => {
-            return HttpResponse::InternalServerError().json(json!({
-                "status":"error",
-                "message": format!("{:?}",error)
-            }));
-        }
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound(format!("User {} not found", user_id)));
     }
+
+    Ok(HttpResponse::NoContent().finish())
 }
 
 #[patch("/users/{id}")]
 async fn update_user_by_id(
+    current: CurrentUser,
     path: Path<Uuid>,
     body: Json<CreateUserRequest>,
     data: Data<AppState>,
-) -> impl Responder {
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
     let user_id = path.into_inner();
+    current
+        .authorize(&data.db, Permission::EditUser(user_id))
+        .await?;
+
+    let user = sqlx::query_as!(
+        User,
+        "UPDATE users SET name = COALESCE($1, name), email = COALESCE($2, email) WHERE id = $3 RETURNING *",
+        body.name.as_deref(),
+        body.email.as_deref(),
+        user_id
+    )
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("User {} not found", user_id)))?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status":"success",
+        "user": user
+    })))
+}
+
+#[put("/users/by-azure-id/{azure_id}")]
+async fn upsert_user_by_azure_id(
+    current: CurrentUser,
+    path: Path<String>,
+    body: Json<CreateUserRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+    current.authorize(&data.db, Permission::ManageUsers).await?;
+
+    let azure_id = path.into_inner();
+    if body.azure_id.as_ref().is_some_and(|id| *id != azure_id) {
+        return Err(AppError::Validation(
+            "azure_id in the body does not match the path".to_string(),
+        ));
+    }
+
+    let mut tx = data.db.begin().await?;
+
+    let upserted = sqlx::query_as::<_, Upserted<User>>(
+        "INSERT INTO users (name, azure_id, email) VALUES ($1,$2,$3)
+        ON CONFLICT (azure_id) DO UPDATE SET name = EXCLUDED.name, email = EXCLUDED.email
+        RETURNING *, (xmax = 0) AS inserted",
+    )
+    .bind(body.name.as_deref())
+    .bind(&azure_id)
+    .bind(body.email.as_deref())
+    .fetch_one(&mut tx)
+    .await?;
 
-    match sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", user_id)
-        .fetch_one(&data.db)
-        .await
-    {
-        Ok(user) => {
-            match sqlx::query_as!(
-                User,
-                "UPDATE users SET name = $1, email = $2 WHERE id = $3 RETURNING *",
-                body.name
-                    .to_owned()
-                    .unwrap_or(user.name.expect("No info for user")),
-                body.email
-                    .to_owned()
-                    .unwrap_or(user.email.expect("No info for user")),
-                user_id
-            )
-            .fetch_one(&data.db)
-            .await
-            {
-                Ok(user) => {
-                    let user_response = json!({
-                        "status":"success",
-                        "user": user
-                    });
-                    return HttpResponse::Ok().json(user_response);
-                }
-                Err(error) => {
-                    return HttpResponse::InternalServerError().json(json!({
-                        "status":"error",
-                        "message": format!("{:?}",error)
-                    }));
-                }
-            }
-        }
-        Err(error) => {
-            return HttpResponse::InternalServerError().json(json!({
-                "status":"error",
-                "message": format!("{:?}",error)
-            }));
-        }
+    tx.commit().await?;
+
+    let user_response = json!({
+        "status":"success",
+        "user": upserted.row
+    });
+    if upserted.inserted {
+        Ok(HttpResponse::Created().json(user_response))
+    } else {
+        Ok(HttpResponse::Ok().json(user_response))
     }
 }
+
+#[put("/users/{id}/role")]
+async fn update_user_role(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    body: Json<UpdateUserRoleRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+    current.authorize(&data.db, Permission::ManageUsers).await?;
+
+    let user_id = path.into_inner();
+
+    let user = sqlx::query_as!(
+        User,
+        "UPDATE users SET role = $1 WHERE id = $2 RETURNING *",
+        body.role,
+        user_id
+    )
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("User {} not found", user_id)))?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status":"success",
+        "user": user
+    })))
+}
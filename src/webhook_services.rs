@@ -0,0 +1,325 @@
+use actix_web::{
+    delete, get, patch, post,
+    web::{Data, Json, Path, Query},
+    HttpResponse,
+};
+use serde_json::json;
+use sqlx::{Executor, Postgres};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    auth::CurrentUser,
+    error::AppError,
+    listing::{Listing, Page, SortField},
+    model::{Webhook, WebhookDelivery},
+    policy::Permission,
+    schema::{
+        CreateWebhookRequest, UpdateWebhookRequest, WebhookDeliveryFilterOptions,
+        WebhookFilterOptions,
+    },
+    webhooks, AppState,
+};
+
+const WEBHOOK_SORT_FIELDS: &[SortField] = &[
+    SortField::new("created_at", "created_at", "timestamptz"),
+    SortField::new("url", "url", "varchar"),
+];
+
+const DELIVERY_SORT_FIELDS: &[SortField] = &[
+    SortField::new("created_at", "created_at", "timestamptz"),
+    SortField::new("next_attempt_at", "next_attempt_at", "timestamptz"),
+];
+
+async fn find_webhook<'e, E: Executor<'e, Database = Postgres>>(
+    executor: E,
+    webhook_id: Uuid,
+) -> Result<Webhook, AppError> {
+    sqlx::query_as!(Webhook, "SELECT * FROM webhooks WHERE id = $1", webhook_id)
+        .fetch_optional(executor)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Webhook {} not found", webhook_id)))
+}
+
+async fn find_delivery<'e, E: Executor<'e, Database = Postgres>>(
+    executor: E,
+    webhook_id: Uuid,
+    delivery_id: i32,
+) -> Result<WebhookDelivery, AppError> {
+    sqlx::query_as!(
+        WebhookDelivery,
+        "SELECT * FROM webhook_deliveries WHERE id = $1 AND webhook_id = $2",
+        delivery_id,
+        webhook_id
+    )
+    .fetch_optional(executor)
+    .await?
+    .ok_or_else(|| {
+        AppError::NotFound(format!(
+            "Delivery {} of webhook {} not found",
+            delivery_id, webhook_id
+        ))
+    })
+}
+
+#[post("/webhooks")]
+async fn create_webhook(
+    current: CurrentUser,
+    body: Json<CreateWebhookRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+    current
+        .authorize(&data.db, Permission::ManageWebhooks)
+        .await?;
+
+    //two random uuids give 244 random bits
+    let secret = body
+        .secret
+        .clone()
+        .unwrap_or_else(|| format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple()));
+
+    let webhook = sqlx::query_as!(
+        Webhook,
+        "INSERT INTO webhooks (url, secret, events, description, active, created_by_id)
+        VALUES ($1, $2, $3, $4, COALESCE($5, TRUE), $6) RETURNING *",
+        body.url,
+        secret,
+        &body.events,
+        body.description,
+        body.active,
+        current.0.id
+    )
+    .fetch_one(&data.db)
+    .await?;
+
+    //the secret is not returned again, receivers need it to verify signatures
+    let mut response = json!(webhook);
+    response["secret"] = json!(webhook.secret);
+
+    Ok(HttpResponse::Created().json(json!({"status":"success", "data":response})))
+}
+
+#[get("/webhooks")]
+async fn get_all_webhooks(
+    current: CurrentUser,
+    opts: Query<WebhookFilterOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current
+        .authorize(&data.db, Permission::ManageWebhooks)
+        .await?;
+
+    let mut listing = Listing::new(
+        "*",
+        "webhooks",
+        "id",
+        "uuid",
+        WEBHOOK_SORT_FIELDS,
+        "created_at",
+    );
+    listing
+        .filter("events && ARRAY[$, '*']::varchar[]", opts.event.clone())
+        .filter("active = $", opts.active);
+
+    let page: Page<Webhook> = listing
+        .fetch(
+            &data.db,
+            opts.sort.as_deref(),
+            opts.cursor.as_deref(),
+            opts.limit,
+        )
+        .await?;
+
+    Ok(page.into_response("webhooks"))
+}
+
+#[get("/webhooks/{id}")]
+async fn get_webhook_by_id(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current
+        .authorize(&data.db, Permission::ManageWebhooks)
+        .await?;
+
+    let webhook = find_webhook(&data.db, path.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":webhook})))
+}
+
+#[patch("/webhooks/{id}")]
+async fn update_webhook(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    body: Json<UpdateWebhookRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+    current
+        .authorize(&data.db, Permission::ManageWebhooks)
+        .await?;
+
+    let webhook_id = path.into_inner();
+    let webhook = sqlx::query_as!(
+        Webhook,
+        "UPDATE webhooks SET
+            url = COALESCE($1, url),
+            secret = COALESCE($2, secret),
+            events = COALESCE($3, events),
+            description = COALESCE($4, description),
+            active = COALESCE($5, active)
+        WHERE id = $6 RETURNING *",
+        body.url,
+        body.secret,
+        body.events.as_deref(),
+        body.description,
+        body.active,
+        webhook_id
+    )
+    .fetch_optional(&data.db)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Webhook {} not found", webhook_id)))?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":webhook})))
+}
+
+#[delete("/webhooks/{id}")]
+async fn delete_webhook(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current
+        .authorize(&data.db, Permission::ManageWebhooks)
+        .await?;
+
+    let webhook_id = path.into_inner();
+    //the delivery log goes with it
+    let result = sqlx::query!("DELETE FROM webhooks WHERE id = $1", webhook_id)
+        .execute(&data.db)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound(format!(
+            "Webhook {} not found",
+            webhook_id
+        )));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Queues a ping to this webhook only, to check the receiver and its signature check
+#[post("/webhooks/{id}/ping")]
+async fn ping_webhook(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current
+        .authorize(&data.db, Permission::ManageWebhooks)
+        .await?;
+
+    let webhook = find_webhook(&data.db, path.into_inner()).await?;
+    let payload = webhooks::envelope(
+        webhooks::PING_EVENT,
+        Some(current.0.id),
+        json!({"webhook_id": webhook.id}),
+    );
+
+    let delivery = sqlx::query_as!(
+        WebhookDelivery,
+        "INSERT INTO webhook_deliveries (webhook_id, event, payload) VALUES ($1, $2, $3) RETURNING *",
+        webhook.id,
+        webhooks::PING_EVENT,
+        payload
+    )
+    .fetch_one(&data.db)
+    .await?;
+
+    Ok(HttpResponse::Accepted().json(json!({"status":"success", "data":delivery})))
+}
+
+#[get("/webhooks/{id}/deliveries")]
+async fn get_webhook_deliveries(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    opts: Query<WebhookDeliveryFilterOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current
+        .authorize(&data.db, Permission::ManageWebhooks)
+        .await?;
+
+    let webhook = find_webhook(&data.db, path.into_inner()).await?;
+
+    let mut listing = Listing::new(
+        "*",
+        "webhook_deliveries",
+        "id",
+        "int4",
+        DELIVERY_SORT_FIELDS,
+        "-created_at",
+    );
+    listing
+        .filter("webhook_id = $", Some(webhook.id))
+        .filter("status = $", opts.status.clone())
+        .filter("event = $", opts.event.clone());
+
+    let page: Page<WebhookDelivery> = listing
+        .fetch(
+            &data.db,
+            opts.sort.as_deref(),
+            opts.cursor.as_deref(),
+            opts.limit,
+        )
+        .await?;
+
+    Ok(page.into_response("deliveries"))
+}
+
+#[get("/webhooks/{id}/deliveries/{delivery_id}")]
+async fn get_webhook_delivery(
+    current: CurrentUser,
+    path: Path<(Uuid, i32)>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current
+        .authorize(&data.db, Permission::ManageWebhooks)
+        .await?;
+
+    let (webhook_id, delivery_id) = path.into_inner();
+    let delivery = find_delivery(&data.db, webhook_id, delivery_id).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":delivery})))
+}
+
+// Queues the same payload again as a new delivery; the original keeps its log entry
+#[post("/webhooks/{id}/deliveries/{delivery_id}/replay")]
+async fn replay_webhook_delivery(
+    current: CurrentUser,
+    path: Path<(Uuid, i32)>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current
+        .authorize(&data.db, Permission::ManageWebhooks)
+        .await?;
+
+    let (webhook_id, delivery_id) = path.into_inner();
+    let original = find_delivery(&data.db, webhook_id, delivery_id).await?;
+
+    let delivery = sqlx::query_as!(
+        WebhookDelivery,
+        "INSERT INTO webhook_deliveries (webhook_id, event, payload, replay_of)
+        VALUES ($1, $2, $3, $4) RETURNING *",
+        original.webhook_id,
+        original.event,
+        original.payload,
+        original.id
+    )
+    .fetch_one(&data.db)
+    .await?;
+
+    Ok(HttpResponse::Accepted().json(json!({"status":"success", "data":delivery})))
+}
@@ -0,0 +1,452 @@
+use std::{
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::Utc;
+use futures::future::join_all;
+use hmac::{Hmac, Mac};
+use hyper::client::connect::dns::Name;
+use log::{error, warn};
+use reqwest::{
+    dns::{Addrs, Resolve, Resolving},
+    header::CONTENT_TYPE,
+    redirect, Url,
+};
+use serde_json::{json, Map, Value};
+use sha2::Sha256;
+use sqlx::{Executor, Pool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::model::WorkItem;
+
+/// Events a webhook can subscribe to, next to `*` for all of them.
+pub const EVENTS: &[&str] = &[
+    "workitem.created",
+    "workitem.updated",
+    "workitem.state_changed",
+    "workitem.deleted",
+    "project.created",
+    "project.updated",
+    "project.deleted",
+    "team.member_added",
+    "team.member_removed",
+];
+
+// Sent on request to a single webhook to check the receiver, whatever it subscribed to
+pub const PING_EVENT: &str = "ping";
+
+const BATCH_SIZE: i64 = 20;
+// A claimed delivery is not picked up again before its lease runs out, so an
+// instance that dies mid-request only delays the retry
+const LEASE_SECS: f64 = 60.0;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_ATTEMPTS: i32 = 8;
+// doubled after every failed attempt: 30s, 1m, 2m, ... 32m
+const RETRY_BASE_SECS: i32 = 30;
+const MAX_RESPONSE_BODY: usize = 2000;
+
+/// The body posted to receivers.
+pub fn envelope(event: &str, actor: Option<Uuid>, data: Value) -> Value {
+    json!({
+        "event": event,
+        "occurred_at": Utc::now(),
+        "actor_id": actor,
+        "data": data
+    })
+}
+
+/// Queues `event` for every active webhook subscribed to it. Called inside the
+/// transaction of the change, so a rolled back change sends nothing.
+pub async fn enqueue<'e, E: Executor<'e, Database = Postgres>>(
+    executor: E,
+    event: &str,
+    actor: Option<Uuid>,
+    data: Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO webhook_deliveries (webhook_id, event, payload)
+        SELECT id, $1, $2 FROM webhooks WHERE active AND events && ARRAY[$1, '*']::varchar[]",
+        event,
+        envelope(event, actor, data)
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+// The events of one recorded work item change, `changes` being the revision diff
+pub async fn enqueue_workitem_events(
+    tx: &mut Transaction<'_, Postgres>,
+    actor: Option<Uuid>,
+    before: Option<&WorkItem>,
+    after: Option<&WorkItem>,
+    changes: &Map<String, Value>,
+) -> Result<(), sqlx::Error> {
+    match (before, after) {
+        (None, Some(after)) => {
+            enqueue(
+                &mut *tx,
+                "workitem.created",
+                actor,
+                json!({"workitem": after}),
+            )
+            .await
+        }
+        (Some(before), None) => {
+            enqueue(
+                &mut *tx,
+                "workitem.deleted",
+                actor,
+                json!({"workitem": before}),
+            )
+            .await
+        }
+        (Some(before), Some(after)) => {
+            enqueue(
+                &mut *tx,
+                "workitem.updated",
+                actor,
+                json!({"workitem": after, "changes": changes}),
+            )
+            .await?;
+            if before.state != after.state {
+                enqueue(
+                    &mut *tx,
+                    "workitem.state_changed",
+                    actor,
+                    json!({"workitem": after, "from": before.state, "to": after.state}),
+                )
+                .await?;
+            }
+            Ok(())
+        }
+        (None, None) => Ok(()),
+    }
+}
+
+// team.member_added and team.member_removed for a membership change
+pub async fn enqueue_member_events(
+    tx: &mut Transaction<'_, Postgres>,
+    actor: Option<Uuid>,
+    team_id: Uuid,
+    added: &[Uuid],
+    removed: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    for (event, user_ids) in [
+        ("team.member_added", added),
+        ("team.member_removed", removed),
+    ] {
+        for user_id in user_ids {
+            enqueue(
+                &mut *tx,
+                event,
+                actor,
+                json!({"team_id": team_id, "user_id": user_id}),
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// `sha256=` and the hex HMAC-SHA256 of the body, sent as X-Webhook-Signature.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// The receiver URL of a webhook, which has to be http or https.
+pub fn check_url(url: &str) -> Result<Url, String> {
+    let url = Url::parse(url).map_err(|error| format!("must be a valid URL: {}", error))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host().is_none() {
+        return Err("must be an http or https URL".to_string());
+    }
+    Ok(url)
+}
+
+// Whether an address is reachable on the internet, as opposed to this host, its
+// network or the cloud metadata service a receiver URL could otherwise point at
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || first == 0
+                // shared address space 100.64.0.0/10
+                || (first == 100 && second & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // unique local fc00::/7 and link-local fe80::/10
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+// Resolves receiver hosts to their public addresses only. Connections use these
+// addresses, so a name cannot be switched to a private one after the check.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs: Vec<SocketAddr> = actix_web::rt::task::spawn_blocking({
+                let host = host.clone();
+                move || (host.as_str(), 0).to_socket_addrs()
+            })
+            .await??
+            .filter(|addr| is_public(addr.ip()))
+            .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", host).into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Sends webhook requests. Unless private targets are allowed, receivers on
+/// private, loopback or link-local addresses are refused.
+pub struct DeliveryClient {
+    http: reqwest::Client,
+    allow_private: bool,
+}
+
+impl DeliveryClient {
+    pub fn new(allow_private: bool) -> Self {
+        //a redirect could lead anywhere, it counts as a failed delivery instead
+        let mut builder = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .redirect(redirect::Policy::none());
+        if !allow_private {
+            builder = builder.dns_resolver(Arc::new(PublicResolver));
+        }
+        DeliveryClient {
+            http: builder
+                .build()
+                .expect("Failed to build the webhook HTTP client"),
+            allow_private,
+        }
+    }
+
+    // URLs with an address for a host do not go through the resolver
+    fn check_target(&self, url: &str) -> Result<Url, String> {
+        let url = check_url(url)?;
+        let ip = url
+            .host_str()
+            .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+            .and_then(|host| host.parse::<IpAddr>().ok());
+        match ip {
+            Some(ip) if !self.allow_private && !is_public(ip) => {
+                Err(format!("{} is not a public address", ip))
+            }
+            _ => Ok(url),
+        }
+    }
+}
+
+/// Sends due deliveries every `interval`, draining the queue before the next tick.
+pub fn spawn_delivery_worker(db: Pool<Postgres>, interval: Duration, client: DeliveryClient) {
+    actix_web::rt::spawn(async move {
+        let mut ticker = actix_web::rt::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            loop {
+                match deliver_due(&db, &client).await {
+                    Ok(sent) if sent as i64 == BATCH_SIZE => continue,
+                    Ok(_) => break,
+                    Err(err) => {
+                        error!("Failed to send webhook deliveries: {}", err);
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+struct DueDelivery {
+    id: i32,
+    event: String,
+    payload: Value,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+// Claims a batch of due deliveries of active webhooks and sends them concurrently.
+// SKIP LOCKED and the lease keep other instances off the claimed rows.
+async fn deliver_due(db: &Pool<Postgres>, client: &DeliveryClient) -> Result<usize, sqlx::Error> {
+    let due = sqlx::query_as!(
+        DueDelivery,
+        "UPDATE webhook_deliveries d SET next_attempt_at = NOW() + make_interval(secs => $2)
+        FROM webhooks w
+        WHERE w.id = d.webhook_id AND d.id IN (
+            SELECT due.id FROM webhook_deliveries due JOIN webhooks hook ON hook.id = due.webhook_id
+            WHERE due.status = 'pending' AND due.next_attempt_at <= NOW() AND hook.active
+            ORDER BY due.next_attempt_at
+            LIMIT $1
+            FOR UPDATE OF due SKIP LOCKED)
+        RETURNING d.id, d.event, d.payload, d.attempts, w.url, w.secret",
+        BATCH_SIZE,
+        LEASE_SECS
+    )
+    .fetch_all(db)
+    .await?;
+
+    let sent = due.len();
+    for result in join_all(
+        due.into_iter()
+            .map(|delivery| attempt(db, client, delivery)),
+    )
+    .await
+    {
+        result?;
+    }
+    Ok(sent)
+}
+
+async fn attempt(
+    db: &Pool<Postgres>,
+    client: &DeliveryClient,
+    delivery: DueDelivery,
+) -> Result<(), sqlx::Error> {
+    let body = serde_json::to_vec(&delivery.payload).unwrap_or_default();
+    let result = match client.check_target(&delivery.url) {
+        Ok(url) => Ok(client
+            .http
+            .post(url)
+            .header(CONTENT_TYPE, "application/json")
+            .header("X-Webhook-Event", &delivery.event)
+            .header("X-Webhook-Delivery", delivery.id.to_string())
+            .header("X-Webhook-Signature", sign(&delivery.secret, &body))
+            .body(body)
+            .send()
+            .await),
+        Err(reason) => Err(reason),
+    };
+
+    let (response_status, response_body, failure) = match result {
+        Ok(Ok(response)) => {
+            let status = response.status();
+            let text: String = response
+                .text()
+                .await
+                .unwrap_or_default()
+                .chars()
+                .take(MAX_RESPONSE_BODY)
+                .collect();
+            let failure = (!status.is_success()).then(|| format!("Receiver answered {}", status));
+            (Some(status.as_u16() as i32), Some(text), failure)
+        }
+        Ok(Err(err)) => (None, None, Some(err.to_string())),
+        Err(reason) => (None, None, Some(format!("Receiver refused: {}", reason))),
+    };
+
+    let attempts = delivery.attempts + 1;
+    let (status, retry_in) = match &failure {
+        None => ("succeeded", 0),
+        Some(_) if attempts >= MAX_ATTEMPTS => ("failed", 0),
+        Some(_) => ("pending", RETRY_BASE_SECS << (attempts - 1)),
+    };
+    if let Some(failure) = &failure {
+        warn!(
+            "Webhook delivery {} attempt {} failed: {}",
+            delivery.id, attempts, failure
+        );
+    }
+
+    sqlx::query!(
+        "UPDATE webhook_deliveries SET status = $2, attempts = $3, last_attempt_at = NOW(),
+            next_attempt_at = NOW() + make_interval(secs => $4),
+            response_status = $5, response_body = $6, error = $7
+        WHERE id = $1",
+        delivery.id,
+        status,
+        attempts,
+        retry_in as f64,
+        response_status,
+        response_body,
+        failure
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_matches_known_hmac_sha256_vector() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn check_url_accepts_http_and_https_only() {
+        assert!(check_url("https://hooks.example.com/receive").is_ok());
+        assert!(check_url("http://hooks.example.com:8080/").is_ok());
+        assert!(check_url("ftp://hooks.example.com/").is_err());
+        assert!(check_url("file:///etc/passwd").is_err());
+        assert!(check_url("not a url").is_err());
+    }
+
+    #[test]
+    fn private_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{} should be private", ip);
+        }
+        for ip in ["93.184.216.34", "2606:2800:220:1::1"] {
+            assert!(is_public(ip.parse().unwrap()), "{} should be public", ip);
+        }
+    }
+
+    #[test]
+    fn check_target_refuses_private_address_literals() {
+        let client = DeliveryClient::new(false);
+        assert!(client.check_target("http://169.254.169.254/latest").is_err());
+        assert!(client.check_target("http://[::1]:8080/").is_err());
+        assert!(client.check_target("https://93.184.216.34/").is_ok());
+        assert!(DeliveryClient::new(true)
+            .check_target("http://127.0.0.1:9000/")
+            .is_ok());
+    }
+}
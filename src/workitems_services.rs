@@ -1,194 +1,1109 @@
 use actix_web::{
-    get, post,
-    web::{Data, Json, Query},
-    HttpResponse, Responder,
+    delete, get, patch, post, put,
+    web::{Data, Json, Path, Query},
+    HttpResponse,
 };
-use chrono::offset;
+use std::collections::HashMap;
 
-use log::{error, info};
+use sqlx::{Pool, Postgres, Transaction};
+use uuid::Uuid;
 
 use crate::{
-    model::{ProjectModel, Team, User, WorkItem},
-    schema::{CreateProjectRequest, CreateWorkItemRequest, FilterOptions},
+    auth::CurrentUser,
+    error::AppError,
+    listing::{split_list, Listing, Page, SortField},
+    model::{Iteration, ProjectModel, Upserted, User, WorkItem, WorkItemNode, WorkItemRollup},
+    notification_services,
+    policy::Permission,
+    process, revision_services,
+    schema::{
+        CreateWorkItemRequest, UpdateWorkItemParentRequest, UpdateWorkItemRequest,
+        WorkItemFilterOptions, WorkItemTreeOptions,
+    },
+    tag_services::{normalize_tags, split_tags},
     AppState,
 };
 use serde_json::json;
+use validator::Validate;
+
+const WORKITEM_SORT_FIELDS: &[SortField] = &[
+    SortField::new("title", "title", "varchar"),
+    SortField::new("state", "state", "varchar"),
+    SortField::new("w_type", "w_type", "varchar"),
+    SortField::new("priority", "priority", "int4").nullable(),
+    SortField::new("created_date", "created_date", "timestamp").nullable(),
+    SortField::new("changed_date", "changed_date", "timestamp").nullable(),
+];
+
+// Upper bound on parent links followed, so corrupt data cannot loop forever
+const MAX_HIERARCHY_DEPTH: i32 = 64;
+// Key for the advisory lock that serializes parent changes, so two of them cannot each pass
+// the cycle check and together close a loop. Parents may be in other projects, hence one key.
+const HIERARCHY_LOCK_KEY: i64 = 0x4849_4552;
+const DEFAULT_TREE_DEPTH: i32 = 3;
+
+pub async fn find_project_by_name(
+    tx: &mut Transaction<'_, Postgres>,
+    name: &str,
+) -> Result<ProjectModel, AppError> {
+    sqlx::query_as!(ProjectModel, "SELECT * FROM projects WHERE name = $1", name)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::Unprocessable(format!("Project {} not found", name)))
+}
+
+pub async fn find_user_by_azure_id(
+    tx: &mut Transaction<'_, Postgres>,
+    azure_id: &str,
+) -> Result<User, AppError> {
+    sqlx::query_as!(User, "SELECT * FROM users WHERE azure_id = $1", azure_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::Unprocessable(format!("User {} not found", azure_id)))
+}
+
+pub async fn find_user_by_email(
+    tx: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<User, AppError> {
+    sqlx::query_as!(User, "SELECT * FROM users WHERE email = $1", email)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::Unprocessable(format!("User {} not found", email)))
+}
+
+async fn find_workitem(db: &Pool<Postgres>, workitem_id: Uuid) -> Result<WorkItem, AppError> {
+    sqlx::query_as!(
+        WorkItem,
+        "SELECT * FROM work_items WHERE id = $1",
+        workitem_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Work item {} not found", workitem_id)))
+}
+
+// Serializes parent changes until the transaction ends, including those of Azure DevOps
+// syncs and service hooks. Taken before any work item row is locked: setting a parent
+// locks the parent row, which a transaction waiting here could hold.
+pub async fn lock_hierarchy(tx: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(HIERARCHY_LOCK_KEY)
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+// Whether making `parent_id` the parent would make the work item its own ancestor. The
+// caller holds the hierarchy lock and sets the parent in the same transaction.
+pub async fn closes_cycle(
+    tx: &mut Transaction<'_, Postgres>,
+    workitem_id: Uuid,
+    parent_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    //walk up from the new parent, reaching the work item means it would become its own ancestor
+    sqlx::query_scalar!(
+        r#"WITH RECURSIVE ancestors AS (
+            SELECT id, parent_id, 1 AS depth FROM work_items WHERE id = $1
+            UNION ALL
+            SELECT w.id, w.parent_id, a.depth + 1
+            FROM work_items w JOIN ancestors a ON w.id = a.parent_id
+            WHERE a.depth < $3
+        )
+        SELECT EXISTS(SELECT 1 FROM ancestors WHERE id = $2) AS "exists!""#,
+        parent_id,
+        workitem_id,
+        MAX_HIERARCHY_DEPTH
+    )
+    .fetch_one(&mut *tx)
+    .await
+}
+
+// Rejects a parent that is the work item itself or one of its descendants
+async fn check_parent(
+    tx: &mut Transaction<'_, Postgres>,
+    workitem_id: Uuid,
+    parent_id: Uuid,
+) -> Result<(), AppError> {
+    if parent_id == workitem_id {
+        return Err(AppError::Validation(
+            "A work item cannot be its own parent".to_string(),
+        ));
+    }
+
+    if closes_cycle(tx, workitem_id, parent_id).await? {
+        return Err(AppError::Validation(format!(
+            "Work item {} is a descendant of {}, making it the parent would create a cycle",
+            parent_id, workitem_id
+        )));
+    }
+    Ok(())
+}
+
+// Direct children of each work item counted by state
+async fn fetch_rollups(
+    db: &Pool<Postgres>,
+    workitem_ids: &[Uuid],
+) -> Result<HashMap<Uuid, WorkItemRollup>, AppError> {
+    let counts = sqlx::query!(
+        r#"SELECT parent_id AS "parent_id!", state, COUNT(*) AS "count!"
+        FROM work_items WHERE parent_id = ANY($1)
+        GROUP BY parent_id, state"#,
+        workitem_ids
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut rollups: HashMap<Uuid, WorkItemRollup> = HashMap::new();
+    for count in counts {
+        let rollup = rollups.entry(count.parent_id).or_default();
+        rollup.child_count += count.count;
+        rollup.children_by_state.insert(count.state, count.count);
+    }
+    Ok(rollups)
+}
+
+// Links a work item to an iteration of its project, by id or by path for Azure DevOps
+// compatibility. A path without a matching iteration is kept as text only.
+async fn resolve_iteration(
+    tx: &mut Transaction<'_, Postgres>,
+    project_id: Uuid,
+    iteration_id: Option<Uuid>,
+    iteration_path: Option<&str>,
+) -> Result<(Option<Uuid>, Option<String>), AppError> {
+    if let Some(iteration_id) = iteration_id {
+        let iteration = sqlx::query_as!(
+            Iteration,
+            "SELECT * FROM iterations WHERE id = $1 AND project_id = $2",
+            iteration_id,
+            project_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| {
+            AppError::Unprocessable(format!(
+                "Iteration {} not found in project {}",
+                iteration_id, project_id
+            ))
+        })?;
+        return Ok((Some(iteration.id), Some(iteration.path)));
+    }
+
+    match iteration_path {
+        Some(path) => {
+            let iteration = sqlx::query!(
+                "SELECT id, path FROM iterations WHERE project_id = $1 AND lower(path) = lower($2)",
+                project_id,
+                path
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+            Ok(match iteration {
+                Some(iteration) => (Some(iteration.id), Some(iteration.path)),
+                None => (None, Some(path.to_string())),
+            })
+        }
+        None => Ok((None, None)),
+    }
+}
+
+// Checks the area path against the project's area tree and returns its canonical
+// spelling. The project name is the root area; projects without areas accept any path.
+async fn check_area_path(
+    tx: &mut Transaction<'_, Postgres>,
+    project: &ProjectModel,
+    area_path: Option<&str>,
+) -> Result<Option<String>, AppError> {
+    let Some(area_path) = area_path else {
+        return Ok(None);
+    };
+    if let Some(root) = project
+        .name
+        .as_ref()
+        .filter(|name| name.eq_ignore_ascii_case(area_path))
+    {
+        return Ok(Some(root.clone()));
+    }
+    let area = sqlx::query!(
+        r#"SELECT
+            (SELECT path FROM areas WHERE project_id = $1 AND lower(path) = lower($2)) AS path,
+            EXISTS(SELECT 1 FROM areas WHERE project_id = $1) AS "has_areas!""#,
+        project.id,
+        area_path
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    match area.path {
+        Some(path) => Ok(Some(path)),
+        None if !area.has_areas => Ok(Some(area_path.to_string())),
+        None => Err(AppError::Validation(format!(
+            "Area path '{}' is not an area of project {}",
+            area_path,
+            project.name.as_deref().unwrap_or_default()
+        ))),
+    }
+}
+
+struct WorkItemRelations {
+    project: ProjectModel,
+    assigned_user: Option<User>,
+    created_by_user: User,
+    parent: Option<WorkItem>,
+    iteration_id: Option<Uuid>,
+    iteration_path: Option<String>,
+    area_path: Option<String>,
+}
+
+// Looks up the project by name, assignee by azure_id, creator by email, parent by azure_id
+// the iteration by id or path and checks the area path
+async fn resolve_relations(
+    tx: &mut Transaction<'_, Postgres>,
+    body: &CreateWorkItemRequest,
+) -> Result<WorkItemRelations, AppError> {
+    let project = find_project_by_name(tx, &body.project).await?;
+
+    let assigned_user = match &body.assigned_to_id {
+        Some(azure_id) => Some(find_user_by_azure_id(tx, azure_id).await?),
+        None => None,
+    };
+
+    let created_by_user = find_user_by_email(tx, &body.created_by_id).await?;
+
+    // an unknown parent is ignored so children can be imported before their parents
+    let parent = sqlx::query_as!(
+        WorkItem,
+        "SELECT * FROM work_items WHERE azure_id = $1",
+        body.parent_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let (iteration_id, iteration_path) = resolve_iteration(
+        tx,
+        project.id,
+        body.iteration_id,
+        body.iteration_path.as_deref(),
+    )
+    .await?;
+
+    let area_path = check_area_path(tx, &project, body.area_path.as_deref()).await?;
+
+    Ok(WorkItemRelations {
+        project,
+        assigned_user,
+        created_by_user,
+        parent,
+        iteration_id,
+        iteration_path,
+        area_path,
+    })
+}
+
+// Checks the type and state against the project's process template and returns
+// their canonical spelling. Projects without a known template accept any value.
+async fn check_workflow(
+    tx: &mut Transaction<'_, Postgres>,
+    project: &ProjectModel,
+    before: Option<&WorkItem>,
+    w_type: &str,
+    state: &str,
+) -> Result<(String, String), AppError> {
+    let process = match &project.template {
+        Some(template) => process::find_process(&mut *tx, template).await?,
+        None => None,
+    };
+    match (process, before) {
+        (Some(process), Some(before)) => process.check_change(before, w_type, state),
+        (Some(process), None) => process.check_new(w_type, state),
+        (None, _) => Ok((w_type.to_string(), state.to_string())),
+    }
+}
+
+// Creates a work item inside the caller's transaction. Used by POST /workitems and
+// imports; the body must already be validated.
+pub async fn insert_workitem(
+    tx: &mut Transaction<'_, Postgres>,
+    current: &CurrentUser,
+    db: &Pool<Postgres>,
+    body: &CreateWorkItemRequest,
+) -> Result<WorkItem, AppError> {
+    let relations = resolve_relations(tx, body).await?;
+    current
+        .authorize(db, Permission::WriteProject(relations.project.id))
+        .await?;
+    //the creator is who the assignee's notification comes from
+    current
+        .authorize(db, Permission::ActAsUser(relations.created_by_user.id))
+        .await?;
+    let (w_type, state) =
+        check_workflow(tx, &relations.project, None, &body.w_type, &body.state).await?;
+
+    let tags = body.tags.as_deref().map(normalize_tags);
+    let workitem = sqlx::query_as!(WorkItem,"INSERT INTO work_items (azure_id, title, w_type, state, project,assigned_to_id,created_by_id,priority,
+        severity, description, area_path, iteration_path, iteration_id, parent_id, tags, url) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15,$16) RETURNING *",
+        body.azure_id,
+        body.title,
+        w_type,
+        state,
+        relations.project.id,
+        relations.assigned_user.as_ref().map(|user| user.id),
+        relations.created_by_user.id,
+        body.priority,
+        body.severity,
+        body.description,
+        relations.area_path,
+        relations.iteration_path,
+        relations.iteration_id,
+        relations.parent.as_ref().map(|parent| parent.id),
+        tags.as_deref(),
+        body.url
+    ).fetch_one(&mut *tx)
+    .await?;
+
+    revision_services::record_and_publish(tx, Some(current.0.id), None, Some(&workitem)).await?;
+    notify_new_workitem(tx, current.0.id, &workitem).await?;
+
+    Ok(workitem)
+}
 
 #[post("/workitems")]
 async fn create_workitem(
+    current: CurrentUser,
     body: Json<CreateWorkItemRequest>,
     data: Data<AppState>,
-) -> impl Responder {
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
     //begin transaction
-    println!("{:?}", body.azure_id);
-    let mut tx = match data.db.begin().await {
-        Ok(tx) => tx,
-        Err(error) => {
-            return HttpResponse::InternalServerError().json(json!({"status":"error", "message": format!("Failed to begin transaction: {}",error)}));
+    let mut tx = data.db.begin().await?;
+
+    let workitem = insert_workitem(&mut tx, &current, &data.db, &body).await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Created().json(json!({"status":"success", "data":workitem})))
+}
+
+#[put("/workitems/by-azure-id/{azure_id}")]
+async fn upsert_workitem_by_azure_id(
+    current: CurrentUser,
+    path: Path<String>,
+    body: Json<CreateWorkItemRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
+    let azure_id = path.into_inner();
+    if body.azure_id.as_ref().is_some_and(|id| *id != azure_id) {
+        return Err(AppError::Validation(
+            "azure_id in the body does not match the path".to_string(),
+        ));
+    }
+
+    let mut tx = data.db.begin().await?;
+    if body.parent_id.is_some() {
+        lock_hierarchy(&mut tx).await?;
+    }
+
+    let relations = resolve_relations(&mut tx, &body).await?;
+
+    let existing = sqlx::query_as!(
+        WorkItem,
+        "SELECT * FROM work_items WHERE azure_id = $1 FOR UPDATE",
+        azure_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    //moving an existing item needs write access to both projects
+    current
+        .authorize(&data.db, Permission::WriteProject(relations.project.id))
+        .await?;
+    current
+        .authorize(&data.db, Permission::ActAsUser(relations.created_by_user.id))
+        .await?;
+    if let Some(before) = &existing {
+        current
+            .authorize(&data.db, Permission::WriteProject(before.project))
+            .await?;
+        if let Some(parent) = &relations.parent {
+            check_parent(&mut tx, before.id, parent.id).await?;
         }
-    };
-    //find relations
-    let project = match sqlx::query_as!(
-        ProjectModel,
-        "SELECT * FROM projects WHERE name = $1",
-        body.project
+    }
+    let (w_type, state) = check_workflow(
+        &mut tx,
+        &relations.project,
+        existing.as_ref(),
+        &body.w_type,
+        &body.state,
     )
+    .await?;
+
+    let tags = body.tags.as_deref().map(normalize_tags);
+    let upserted = sqlx::query_as::<_, Upserted<WorkItem>>(
+        "INSERT INTO work_items (azure_id, title, w_type, state, project,assigned_to_id,created_by_id,priority,
+        severity, description, area_path, iteration_path, iteration_id, parent_id, tags, url) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15,$16)
+        ON CONFLICT (azure_id) DO UPDATE SET
+            title = EXCLUDED.title,
+            w_type = EXCLUDED.w_type,
+            state = EXCLUDED.state,
+            project = EXCLUDED.project,
+            assigned_to_id = EXCLUDED.assigned_to_id,
+            created_by_id = EXCLUDED.created_by_id,
+            priority = EXCLUDED.priority,
+            severity = EXCLUDED.severity,
+            description = EXCLUDED.description,
+            area_path = EXCLUDED.area_path,
+            iteration_path = EXCLUDED.iteration_path,
+            iteration_id = EXCLUDED.iteration_id,
+            parent_id = EXCLUDED.parent_id,
+            tags = EXCLUDED.tags,
+            url = EXCLUDED.url,
+            changed_date = NOW()
+        RETURNING *, (xmax = 0) AS inserted",
+    )
+    .bind(&azure_id)
+    .bind(&body.title)
+    .bind(&w_type)
+    .bind(&state)
+    .bind(relations.project.id)
+    .bind(relations.assigned_user.as_ref().map(|user| user.id))
+    .bind(relations.created_by_user.id)
+    .bind(body.priority)
+    .bind(&body.severity)
+    .bind(&body.description)
+    .bind(&relations.area_path)
+    .bind(&relations.iteration_path)
+    .bind(relations.iteration_id)
+    .bind(relations.parent.as_ref().map(|parent| parent.id))
+    .bind(tags.as_deref())
+    .bind(&body.url)
     .fetch_one(&mut tx)
-    .await
-    {
-        Ok(project) => project,
-        Err(error) => {
-            error!("Failed here on fiding project");
-            return HttpResponse::InternalServerError()
-                .json(json!({"status":"error", "message": format!("{:?}",error)}));
-        }
+    .await?;
+    //the checks above ran without the row a concurrent upsert inserted meanwhile
+    if !upserted.inserted && existing.is_none() {
+        return Err(AppError::Conflict(format!(
+            "Work item {} was created concurrently",
+            azure_id
+        )));
+    }
+    let workitem = upserted.row;
+
+    revision_services::record_and_publish(
+        &mut tx,
+        Some(current.0.id),
+        existing.as_ref(),
+        Some(&workitem),
+    )
+    .await?;
+    match &existing {
+        Some(before) => notify_workitem_changes(&mut tx, current.0.id, before, &workitem).await?,
+        None => notify_new_workitem(&mut tx, current.0.id, &workitem).await?,
+    }
+
+    tx.commit().await?;
+
+    match existing {
+        Some(_) => Ok(HttpResponse::Ok().json(json!({"status":"success", "data":workitem}))),
+        None => Ok(HttpResponse::Created().json(json!({"status":"success", "data":workitem}))),
+    }
+}
+
+// Work items matching the list filters, read from `from`, which has to be aliased work_items
+pub fn workitem_listing(from: &'static str, opts: &WorkItemFilterOptions) -> Listing {
+    let mut listing = Listing::new(
+        "*",
+        from,
+        "id",
+        "uuid",
+        WORKITEM_SORT_FIELDS,
+        "-created_date",
+    );
+    listing
+        .filter("state = ANY($)", opts.state.as_deref().map(split_list))
+        .filter("w_type = ANY($)", opts.w_type.as_deref().map(split_list))
+        .filter("assigned_to_id = $", opts.assigned_to_id)
+        .filter("created_by_id = $", opts.created_by_id)
+        .filter("project = $", opts.project)
+        .filter("parent_id = $", opts.parent_id)
+        .filter("iteration_id = $", opts.iteration_id)
+        .filter("tags @> $::varchar[]", opts.tags.as_deref().map(split_tags))
+        .filter(
+            "tags @> $::varchar[]",
+            opts.tags_all.as_deref().map(split_tags),
+        )
+        .filter(
+            "tags && $::varchar[]",
+            opts.tags_any.as_deref().map(split_tags),
+        )
+        .filter("priority >= $", opts.priority_min)
+        .filter("priority <= $", opts.priority_max)
+        .filter("created_date >= $", opts.created_after)
+        .filter("created_date < $", opts.created_before)
+        .filter("changed_date >= $", opts.changed_after)
+        .filter("changed_date < $", opts.changed_before);
+    listing
+}
+
+#[get("/workitems")]
+async fn get_all_workitem(
+    current: CurrentUser,
+    opts: Query<WorkItemFilterOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let page: Page<WorkItem> = workitem_listing("work_items", &opts)
+        .fetch(
+            &data.db,
+            opts.sort.as_deref(),
+            opts.cursor.as_deref(),
+            opts.limit,
+        )
+        .await?;
+
+    Ok(page.into_response("workitems"))
+}
+
+// Work items in any area the team owns, including the areas below it
+#[get("/teams/{id}/backlog")]
+async fn get_team_backlog(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    opts: Query<WorkItemFilterOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let team_id = path.into_inner();
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM teams WHERE id = $1) AS "exists!""#,
+        team_id
+    )
+    .fetch_one(&data.db)
+    .await?;
+    if !exists {
+        return Err(AppError::NotFound(format!("Team {} not found", team_id)));
+    }
+
+    let mut listing = workitem_listing("work_items", &opts);
+    listing.filter(
+        r"EXISTS(SELECT 1 FROM areas a
+            WHERE a.team_id = $ AND a.project_id = work_items.project
+                AND (lower(work_items.area_path) = lower(a.path)
+                    OR starts_with(lower(work_items.area_path), lower(a.path) || '\')))",
+        Some(team_id),
+    );
+
+    let page: Page<WorkItem> = listing
+        .fetch(
+            &data.db,
+            opts.sort.as_deref(),
+            opts.cursor.as_deref(),
+            opts.limit,
+        )
+        .await?;
+
+    Ok(page.into_response("workitems"))
+}
+
+#[get("/workitems/{id}")]
+async fn get_workitem_by_id(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let workitem = find_workitem(&data.db, path.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":workitem})))
+}
+
+#[get("/workitems/{id}/children")]
+async fn get_workitem_children(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let workitem = find_workitem(&data.db, path.into_inner()).await?;
+
+    let children = sqlx::query_as!(
+        WorkItem,
+        "SELECT * FROM work_items WHERE parent_id = $1
+        ORDER BY priority NULLS LAST, title, id",
+        workitem.id
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    let ids: Vec<Uuid> = children.iter().map(|child| child.id).collect();
+    let mut rollups = fetch_rollups(&data.db, &ids).await?;
+    let children: Vec<WorkItemNode> = children
+        .into_iter()
+        .map(|child| WorkItemNode {
+            rollup: rollups.remove(&child.id).unwrap_or_default(),
+            workitem: child,
+            children: None,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status":"success",
+        "result": children.len(),
+        "workitems": children
+    })))
+}
+
+#[get("/workitems/{id}/ancestors")]
+async fn get_workitem_ancestors(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let workitem = find_workitem(&data.db, path.into_inner()).await?;
+
+    //ordered from the root down to the direct parent
+    let ancestors = sqlx::query_as!(
+        WorkItem,
+        "WITH RECURSIVE ancestors AS (
+            SELECT parent_id AS id, 1 AS depth FROM work_items WHERE id = $1
+            UNION ALL
+            SELECT w.parent_id, a.depth + 1
+            FROM work_items w JOIN ancestors a ON w.id = a.id
+            WHERE w.parent_id IS NOT NULL AND a.depth < $2
+        )
+        SELECT w.* FROM work_items w JOIN ancestors a ON a.id = w.id
+        ORDER BY a.depth DESC",
+        workitem.id,
+        MAX_HIERARCHY_DEPTH
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status":"success",
+        "result": ancestors.len(),
+        "workitems": ancestors
+    })))
+}
+
+// Nests the loaded work items under their parents down to `depth` levels
+fn build_tree(
+    workitem: WorkItem,
+    depth: i32,
+    by_parent: &mut HashMap<Uuid, Vec<WorkItem>>,
+    rollups: &mut HashMap<Uuid, WorkItemRollup>,
+) -> WorkItemNode {
+    let children = if depth > 0 {
+        let children = by_parent.remove(&workitem.id).unwrap_or_default();
+        Some(
+            children
+                .into_iter()
+                .map(|child| build_tree(child, depth - 1, by_parent, rollups))
+                .collect(),
+        )
+    } else {
+        None
     };
+    WorkItemNode {
+        rollup: rollups.remove(&workitem.id).unwrap_or_default(),
+        workitem,
+        children,
+    }
+}
+
+#[get("/workitems/{id}/tree")]
+async fn get_workitem_tree(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    opts: Query<WorkItemTreeOptions>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    opts.validate()?;
+    current.authorize(&data.db, Permission::Read).await?;
+
+    let workitem_id = path.into_inner();
+    let depth = opts.depth.unwrap_or(DEFAULT_TREE_DEPTH);
 
-    let assigned_user = match sqlx::query_as!(
-        User,
-        "SELECT * FROM users WHERE azure_id = $1",
-        body.assigned_to_id
+    let workitems = sqlx::query_as!(
+        WorkItem,
+        "WITH RECURSIVE tree AS (
+            SELECT id, 0 AS depth FROM work_items WHERE id = $1
+            UNION ALL
+            SELECT w.id, t.depth + 1
+            FROM work_items w JOIN tree t ON w.parent_id = t.id
+            WHERE t.depth < $2
+        )
+        SELECT w.* FROM work_items w JOIN tree t ON t.id = w.id
+        ORDER BY t.depth, w.priority NULLS LAST, w.title, w.id",
+        workitem_id,
+        depth
     )
-    .fetch_one(&mut tx)
-    .await
-    {
-        Ok(user) => user,
-        Err(error) => {
-            error!("failed to find user: {:?}", body.assigned_to_id);
-            return HttpResponse::InternalServerError()
-                .json(json!({"status":"error", "message": format!("{:?}",error)}));
+    .fetch_all(&data.db)
+    .await?;
+
+    let ids: Vec<Uuid> = workitems.iter().map(|workitem| workitem.id).collect();
+    let mut rollups = fetch_rollups(&data.db, &ids).await?;
+
+    let mut workitems = workitems.into_iter();
+    let root = workitems
+        .next()
+        .ok_or_else(|| AppError::NotFound(format!("Work item {} not found", workitem_id)))?;
+    let mut by_parent: HashMap<Uuid, Vec<WorkItem>> = HashMap::new();
+    for workitem in workitems {
+        if let Some(parent_id) = workitem.parent_id {
+            by_parent.entry(parent_id).or_default().push(workitem);
         }
-    };
+    }
 
-    let created_by_user = match sqlx::query_as!(
-        User,
-        "SELECT * FROM users WHERE email = $1",
-        body.created_by_id
+    let tree = build_tree(root, depth, &mut by_parent, &mut rollups);
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":tree})))
+}
+
+#[put("/workitems/{id}/parent")]
+async fn update_workitem_parent(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    body: Json<UpdateWorkItemParentRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
+    let workitem_id = path.into_inner();
+
+    let mut tx = data.db.begin().await?;
+    if body.parent_id.is_some() {
+        lock_hierarchy(&mut tx).await?;
+    }
+
+    let before = sqlx::query_as!(
+        WorkItem,
+        "SELECT * FROM work_items WHERE id = $1 FOR UPDATE",
+        workitem_id
     )
-    .fetch_one(&mut tx)
-    .await
-    {
-        Ok(user) => user,
-        Err(error) => {
-            error!("Failed to find creation user: {:?}", body.created_by_id);
-            return HttpResponse::InternalServerError()
-                .json(json!({"status":"error", "message": format!("{:?}",error)}));
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Work item {} not found", workitem_id)))?;
+    current
+        .authorize(&data.db, Permission::WriteProject(before.project))
+        .await?;
+
+    if let Some(parent_id) = body.parent_id {
+        let exists = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM work_items WHERE id = $1) AS "exists!""#,
+            parent_id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        if !exists {
+            return Err(AppError::Unprocessable(format!(
+                "Parent work item {} not found",
+                parent_id
+            )));
         }
-    };
+        check_parent(&mut tx, workitem_id, parent_id).await?;
+    }
 
-    let parent_workitem = sqlx::query_as!(
+    let workitem = sqlx::query_as!(
         WorkItem,
-        "SELECT * FROM work_items WHERE azure_id = $1",
-        body.parent_id
+        "UPDATE work_items SET parent_id = $1, changed_date = NOW() WHERE id = $2 RETURNING *",
+        body.parent_id,
+        workitem_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    revision_services::record_and_publish(&mut tx, Some(current.0.id), Some(&before), Some(&workitem)).await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":workitem})))
+}
+
+#[patch("/workitems/{id}")]
+async fn update_workitem_by_id(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    body: Json<UpdateWorkItemRequest>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    body.validate()?;
+
+    let workitem_id = path.into_inner();
+
+    let mut tx = data.db.begin().await?;
+    if matches!(body.parent_id, Some(Some(_))) {
+        lock_hierarchy(&mut tx).await?;
+    }
+
+    let before = sqlx::query_as!(
+        WorkItem,
+        "SELECT * FROM work_items WHERE id = $1 FOR UPDATE",
+        workitem_id
     )
     .fetch_optional(&mut tx)
-    .await
-    .ok()
-    .flatten();
-    match parent_workitem {
-        Some(parent) => {
-            let workitem = match sqlx::query_as!(WorkItem,"INSERT INTO work_items (azure_id, title, w_type, state, project,assigned_to_id,created_by_id,priority, 
-            severity, description, area_path, iteration_path, parent_id, tags, url) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15) RETURNING *",
-            body.azure_id,
-            body.title,
-            body.w_type,
-            body.state,
-            project.id,
-            assigned_user.id,
-            created_by_user.id,
-            body.priority,
-            body.severity,
-            body.description,
-            body.area_path,
-            body.iteration_path,
-            parent.id,
-            body.tags.as_deref(),
-            body.url
-        ).fetch_one(&mut tx)
-        .await{
-            Ok(wi) => wi,
-            Err(error)=>{
-                return HttpResponse::InternalServerError().json(json!({
-                    "status":"error",
-                    "message":format!("{:?}",error)
-                }));
-            }
-        };
-            if let Err(e) = tx.commit().await {
-                return HttpResponse::InternalServerError().json(json!({
-                    "status": "error",
-                    "message": format!("Failed to commit transaction: {}", e)
-                }));
-            }
-
-            HttpResponse::Created().json(json!({"status":"success", "data":workitem}))
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Work item {} not found", workitem_id)))?;
+    current
+        .authorize(&data.db, Permission::WriteProject(before.project))
+        .await?;
+
+    let project = match &body.project {
+        Some(name) => {
+            let project = find_project_by_name(&mut tx, name).await?;
+            current
+                .authorize(&data.db, Permission::WriteProject(project.id))
+                .await?;
+            project
         }
         None => {
-            let workitem = match sqlx::query_as!(WorkItem,"INSERT INTO work_items (azure_id, title, w_type, state, project,assigned_to_id,created_by_id,priority, 
-            severity, description, area_path, iteration_path, tags, url) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14) RETURNING *",
-            body.azure_id,
-            body.title,
-            body.w_type,
-            body.state,
-            project.id,
-            assigned_user.id,
-            created_by_user.id,
-            body.priority,
-            body.severity,
-            body.description,
-            body.area_path,
-            body.iteration_path,
-            body.tags.as_deref(),
-            body.url
-        ).fetch_one(&mut tx)
-        .await{
-            Ok(wi) => wi,
-            Err(error)=>{
-                return HttpResponse::InternalServerError().json(json!({
-                    "status":"error",
-                    "message":format!("{:?}",error)
-                }));
-            }
-        };
-            if let Err(e) = tx.commit().await {
-                return HttpResponse::InternalServerError().json(json!({
-                    "status": "error",
-                    "message": format!("Failed to commit transaction: {}", e)
-                }));
-            }
-
-            HttpResponse::Created().json(json!({"status":"success", "data":workitem}))
+            sqlx::query_as!(
+                ProjectModel,
+                "SELECT * FROM projects WHERE id = $1",
+                before.project
+            )
+            .fetch_one(&mut tx)
+            .await?
+        }
+    };
+
+    //values recorded before the process was enforced stay until the type, state or project changes
+    let workflow_changed = body.w_type.is_some() || body.state.is_some() || body.project.is_some();
+    let (w_type, state) = if workflow_changed {
+        check_workflow(
+            &mut tx,
+            &project,
+            Some(&before),
+            body.w_type.as_deref().unwrap_or(&before.w_type),
+            body.state.as_deref().unwrap_or(&before.state),
+        )
+        .await?
+    } else {
+        (before.w_type.clone(), before.state.clone())
+    };
+
+    //an omitted field keeps its value, an explicit null clears it
+    let assigned_to_id = match &body.assigned_to_id {
+        Some(Some(azure_id)) => Some(find_user_by_azure_id(&mut tx, azure_id).await?.id),
+        Some(None) => None,
+        None => before.assigned_to_id,
+    };
+
+    //moving to another project looks the current path up among its iterations
+    let (iteration_id, iteration_path) = match (&body.iteration_id, &body.iteration_path) {
+        (Some(Some(iteration_id)), _) => {
+            resolve_iteration(&mut tx, project.id, Some(*iteration_id), None).await?
+        }
+        (_, Some(Some(path))) => resolve_iteration(&mut tx, project.id, None, Some(path)).await?,
+        (Some(None), _) | (_, Some(None)) => (None, None),
+        (None, None) if body.project.is_some() => {
+            resolve_iteration(&mut tx, project.id, None, before.iteration_path.as_deref()).await?
+        }
+        (None, None) => (before.iteration_id, before.iteration_path.clone()),
+    };
+
+    let area_path = match &body.area_path {
+        Some(area_path) => check_area_path(&mut tx, &project, area_path.as_deref()).await?,
+        None if body.project.is_some() => {
+            check_area_path(&mut tx, &project, before.area_path.as_deref()).await?
+        }
+        None => before.area_path.clone(),
+    };
+
+    let parent_id = match &body.parent_id {
+        Some(Some(azure_id)) => {
+            let parent = sqlx::query_as!(
+                WorkItem,
+                "SELECT * FROM work_items WHERE azure_id = $1",
+                azure_id
+            )
+            .fetch_optional(&mut tx)
+            .await?
+            .ok_or_else(|| {
+                AppError::Unprocessable(format!("Parent work item {} not found", azure_id))
+            })?;
+            check_parent(&mut tx, workitem_id, parent.id).await?;
+            Some(parent.id)
+        }
+        Some(None) => None,
+        None => before.parent_id,
+    };
+
+    let priority = body.priority.unwrap_or(before.priority);
+    let severity = body.severity.clone().unwrap_or(before.severity.clone());
+    let description = body.description.clone().unwrap_or(before.description.clone());
+    let tags = match &body.tags {
+        Some(tags) => tags.as_deref().map(normalize_tags),
+        None => before.tags.clone(),
+    };
+    let workitem = sqlx::query_as!(
+        WorkItem,
+        "UPDATE work_items SET
+            title = COALESCE($1, title),
+            w_type = COALESCE($2, w_type),
+            state = COALESCE($3, state),
+            project = COALESCE($4, project),
+            assigned_to_id = $5,
+            priority = $6,
+            severity = $7,
+            description = $8,
+            area_path = $9,
+            iteration_path = $10,
+            iteration_id = $11,
+            parent_id = $12,
+            tags = $13,
+            url = COALESCE($14, url),
+            changed_date = NOW()
+        WHERE id = $15 RETURNING *",
+        body.title,
+        w_type,
+        state,
+        project.id,
+        assigned_to_id,
+        priority,
+        severity,
+        description,
+        area_path,
+        iteration_path,
+        iteration_id,
+        parent_id,
+        tags.as_deref(),
+        body.url,
+        workitem_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    revision_services::record_and_publish(&mut tx, Some(current.0.id), Some(&before), Some(&workitem)).await?;
+    notify_workitem_changes(&mut tx, current.0.id, &before, &workitem).await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status":"success", "data":workitem})))
+}
+
+// Notify the assignee of a newly created work item, sent by the creating user
+async fn notify_new_workitem(
+    tx: &mut Transaction<'_, Postgres>,
+    sender_id: Uuid,
+    workitem: &WorkItem,
+) -> Result<(), sqlx::Error> {
+    match workitem.assigned_to_id {
+        Some(assignee_id) => {
+            notification_services::notify(
+                tx,
+                sender_id,
+                assignee_id,
+                "Work item assigned",
+                &format!("{} '{}' was assigned to you", workitem.w_type, workitem.title),
+            )
+            .await
         }
+        None => Ok(()),
     }
 }
 
-#[get("/workitem")]
-async fn get_all_workitem(opts: Query<FilterOptions>, data: Data<AppState>) -> impl Responder {
-    let limit = opts.limit.unwrap_or(10);
-    let offset = (opts.page.unwrap_or(1) - 1) * limit;
+// Notify the assignee when an update reassigns the work item or moves its state,
+// sent by the changing user
+async fn notify_workitem_changes(
+    tx: &mut Transaction<'_, Postgres>,
+    changed_by_id: Uuid,
+    before: &WorkItem,
+    after: &WorkItem,
+) -> Result<(), sqlx::Error> {
+    let Some(assignee_id) = after.assigned_to_id else {
+        return Ok(());
+    };
 
-    match sqlx::query_as!(
+    if before.assigned_to_id != after.assigned_to_id {
+        notification_services::notify(
+            tx,
+            changed_by_id,
+            assignee_id,
+            "Work item assigned",
+            &format!("{} '{}' was assigned to you", after.w_type, after.title),
+        )
+        .await?;
+    } else if before.state != after.state {
+        notification_services::notify(
+            tx,
+            changed_by_id,
+            assignee_id,
+            "Work item state changed",
+            &format!(
+                "{} '{}' moved from {} to {}",
+                after.w_type, after.title, before.state, after.state
+            ),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Deletes a work item locked by the caller, recording the revisions of the
+/// deletion and of its detached children.
+pub async fn remove_workitem(
+    tx: &mut Transaction<'_, Postgres>,
+    changed_by: Option<Uuid>,
+    workitem: &WorkItem,
+) -> Result<(), sqlx::Error> {
+    //children are detached rather than deleted, same as removing the parent link in Azure DevOps
+    let children = sqlx::query_as!(
         WorkItem,
-        "SELECT * FROM work_items ORDER by id LIMIT $1 OFFSET $2",
-        limit as i32,
-        offset as i32
+        "SELECT * FROM work_items WHERE parent_id = $1 FOR UPDATE",
+        workitem.id
     )
-    .fetch_all(&data.db)
-    .await
-    {
-        Ok(wi) => {
-            let json_response = json!({
-                "status":"success",
-                "result":wi.len(),
-                "workitems":wi
-            });
-            return HttpResponse::Ok().json(json_response);
-        }
-        Err(error) => {
-            return HttpResponse::InternalServerError().json(json!({
-                "status":"error",
-                "message": format!("{:?}",error)
-            }));
-        }
+    .fetch_all(&mut *tx)
+    .await?;
+    for child in &children {
+        let detached = sqlx::query_as!(
+            WorkItem,
+            "UPDATE work_items SET parent_id = NULL, changed_date = NOW() WHERE id = $1 RETURNING *",
+            child.id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        revision_services::record_and_publish(tx, changed_by, Some(child), Some(&detached)).await?;
     }
+
+    sqlx::query!("DELETE FROM work_items WHERE id = $1", workitem.id)
+        .execute(&mut *tx)
+        .await?;
+    revision_services::record_and_publish(tx, changed_by, Some(workitem), None).await
+}
+
+#[delete("/workitems/{id}")]
+async fn delete_workitem(
+    current: CurrentUser,
+    path: Path<Uuid>,
+    data: Data<AppState>,
+) -> Result<HttpResponse, AppError> {
+    let workitem_id = path.into_inner();
+
+    let mut tx = data.db.begin().await?;
+
+    let workitem = sqlx::query_as!(
+        WorkItem,
+        "SELECT * FROM work_items WHERE id = $1 FOR UPDATE",
+        workitem_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Work item {} not found", workitem_id)))?;
+    current
+        .authorize(&data.db, Permission::WriteProject(workitem.project))
+        .await?;
+
+    remove_workitem(&mut tx, Some(current.0.id), &workitem).await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
 }